@@ -0,0 +1,46 @@
+//! Compares `Dir::route` with `FrozenDir::route` on a deep path.
+//!
+//! Run with `cargo bench --bench route`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tc_ir::{parse_route_path, Dir, Route};
+
+const ITERATIONS: u32 = 1_000_000;
+
+fn main() {
+    let mut dir = Dir::new();
+
+    for version in 0..10 {
+        for item in 0..100 {
+            let path = format!("/lib/acme/v{version}/items/item{item}/ops/get");
+            dir.insert(&parse_route_path(&path).expect("path"), item)
+                .expect("route");
+        }
+    }
+
+    let path = parse_route_path("/lib/acme/v7/items/item42/ops/get").expect("path");
+    let frozen = dir.clone().freeze();
+    assert_eq!(dir.route(&path), frozen.route(&path));
+
+    let tree = time(|| dir.route(black_box(&path)).copied());
+    let flat = time(|| frozen.route(black_box(&path)).copied());
+
+    println!("Dir::route        {:>8.1} ns/iter", per_iter(tree));
+    println!("FrozenDir::route  {:>8.1} ns/iter", per_iter(flat));
+}
+
+fn time<F: FnMut() -> Option<i32>>(mut route: F) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        black_box(route());
+    }
+
+    start.elapsed()
+}
+
+fn per_iter(elapsed: Duration) -> f64 {
+    elapsed.as_nanos() as f64 / f64::from(ITERATIONS)
+}
@@ -0,0 +1,88 @@
+//! Compares the `BTreeMap` behind `Map` with a sorted `Vec` for the tiny maps (1-3 entries) which
+//! make up most op forms, both on their own and as part of decoding.
+//!
+//! A sorted `Vec` builds and searches a tiny map in about half the time, but that saves well under
+//! a tenth of the time it takes to decode the same map from JSON, so `Map` keeps its `BTreeMap`
+//! (and the `Deref<Target = BTreeMap>` API which callers rely on) rather than switching backing
+//! stores at a size threshold. Rerun this before revisiting that decision.
+//!
+//! Run with `cargo bench --bench small_map`.
+
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use futures::{executor::block_on, stream};
+use tc_ir::{DecodeOptions, Id, Map, Scalar};
+
+const ITERATIONS: u32 = 200_000;
+
+fn main() {
+    for len in 1..=3 {
+        let entries = (0..len)
+            .map(|i| {
+                let key = format!("param{i}").parse::<Id>().expect("id");
+                (key, Scalar::from(i as u64))
+            })
+            .collect::<Vec<_>>();
+
+        let json = format!(
+            "{{{}}}",
+            (0..len)
+                .map(|i| format!("\"param{i}\": {i}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let tree = time(|| build_tree(black_box(&entries)));
+        let vec = time(|| build_vec(black_box(&entries)));
+        let decode = time(|| decode(black_box(&json)));
+
+        println!("{len} entries:");
+        println!("  BTreeMap build + lookup  {:>8.1} ns/iter", per_iter(tree));
+        println!("  sorted Vec build + lookup {:>7.1} ns/iter", per_iter(vec));
+        println!(
+            "  Map decode (JSON)        {:>8.1} ns/iter",
+            per_iter(decode)
+        );
+    }
+}
+
+fn build_tree(entries: &[(Id, Scalar)]) -> usize {
+    let map = entries.iter().cloned().collect::<BTreeMap<_, _>>();
+    entries
+        .iter()
+        .filter(|(key, _)| map.contains_key(key))
+        .count()
+}
+
+fn build_vec(entries: &[(Id, Scalar)]) -> usize {
+    let mut map = entries.to_vec();
+    map.sort_by(|(l, _), (r, _)| l.cmp(r));
+
+    entries
+        .iter()
+        .filter(|(key, _)| map.binary_search_by(|(k, _)| k.cmp(key)).is_ok())
+        .count()
+}
+
+fn decode(json: &str) -> usize {
+    let source = stream::iter([Ok::<_, std::io::Error>(json.as_bytes().to_vec().into())]);
+    let map: Map<Scalar> =
+        block_on(destream_json::try_decode(DecodeOptions::default(), source)).expect("map");
+    map.len()
+}
+
+fn time<F: FnMut() -> usize>(mut f: F) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+
+    start.elapsed()
+}
+
+fn per_iter(elapsed: Duration) -> f64 {
+    elapsed.as_nanos() as f64 / f64::from(ITERATIONS)
+}
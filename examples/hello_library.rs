@@ -5,14 +5,14 @@
 // cargo run --example hello_library
 // ```
 
-use std::{future::Future, pin::Pin, str::FromStr};
+use std::str::FromStr;
 
 use futures::executor::block_on;
 use pathlink::Link;
-use tc_error::TCResult;
+use tc_error::{TCError, TCResult};
 use tc_ir::{
-    parse_route_path, tc_library_routes, Claim, HandleGet, Library, LibraryModule, LibrarySchema,
-    NetworkTime, Route, Transaction, TxnId,
+    parse_route_path, tc_handler, tc_library_routes, Claim, HandleGet, Library, LibraryModule,
+    LibrarySchema, NetworkTime, Route, Transaction, TxnId,
 };
 use umask::Mode;
 
@@ -43,15 +43,12 @@ impl Transaction for ExampleTxn {
 
 struct HelloHandler;
 
-impl HandleGet<ExampleTxn> for HelloHandler {
-    type Request = String;
-    type RequestContext = ();
-    type Response = String;
-    type Error = tc_error::TCError;
-    type Fut<'a> = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
-
-    fn get<'a>(&'a self, _txn: &'a ExampleTxn, name: Self::Request) -> TCResult<Self::Fut<'a>> {
-        Ok(Box::pin(async move { Ok(format!("Hello, {name}!")) }))
+// `tc_handler!` fills in the associated types and the boxed future for the `HandleGet` impl.
+tc_handler! {
+    impl HandleGet<ExampleTxn> for HelloHandler {
+        async fn get(&self, _txn, name: String) -> Result<String, TCError> {
+            Ok(format!("Hello, {name}!"))
+        }
     }
 }
 
@@ -0,0 +1,181 @@
+//! Alpha-equivalence of op definitions, i.e. structural equality up to renaming of bound ids.
+
+use std::collections::HashMap;
+use std::mem::discriminant;
+
+use crate::{Id, IdRef, OpDef, OpRef, Scalar, Subject, TCRef};
+
+impl OpDef {
+    /// Return `true` if `self` and `other` are the same program up to a consistent renaming of
+    /// their bound ids, e.g. to share a cache entry between them.
+    ///
+    /// The bound ids of an op are its parameters (the key of a GET or DELETE op, or the key and
+    /// value of a PUT op) and the names of its form bindings. A `$id` reference to a bound id is
+    /// compared by what it refers to rather than by name, including references from a nested op
+    /// definition to the bindings of an enclosing op. Free ids, like `$self` or the parameters of
+    /// a POST op (which callers pass by name), must match exactly.
+    pub fn alpha_eq(&self, other: &OpDef) -> bool {
+        AlphaEq::default().op_def(self, other)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Binding {
+    Param(usize),
+    Form(usize),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Resolved<'a> {
+    /// A bound id, identified by how many scopes out it's bound and its position in that scope.
+    Bound {
+        depth: usize,
+        binding: Binding,
+    },
+    Free(&'a Id),
+}
+
+type Scope<'a> = HashMap<&'a Id, Binding>;
+
+fn scope(op_def: &OpDef) -> Scope<'_> {
+    let params = match op_def {
+        OpDef::Get((key, _)) | OpDef::Delete((key, _)) => vec![key],
+        OpDef::Put((key, value, _)) => vec![key, value],
+        OpDef::Post(_) => vec![],
+    };
+
+    let mut scope = HashMap::new();
+
+    for (i, param) in params.into_iter().enumerate() {
+        scope.insert(param, Binding::Param(i));
+    }
+
+    // a form binding shadows a parameter (or an earlier binding) with the same name
+    for (i, (id, _)) in op_def.form().iter().enumerate() {
+        scope.insert(id, Binding::Form(i));
+    }
+
+    scope
+}
+
+fn resolve<'a>(scopes: &[Scope<'a>], id: &'a Id) -> Resolved<'a> {
+    scopes
+        .iter()
+        .rev()
+        .enumerate()
+        .find_map(|(depth, scope)| {
+            let binding = *scope.get(id)?;
+            Some(Resolved::Bound { depth, binding })
+        })
+        .unwrap_or(Resolved::Free(id))
+}
+
+/// A lockstep comparison of two op definitions, tracking the scopes entered on each side.
+#[derive(Default)]
+struct AlphaEq<'a> {
+    left: Vec<Scope<'a>>,
+    right: Vec<Scope<'a>>,
+}
+
+impl<'a> AlphaEq<'a> {
+    fn op_def(&mut self, left: &'a OpDef, right: &'a OpDef) -> bool {
+        if discriminant(left) != discriminant(right) || left.form().len() != right.form().len() {
+            return false;
+        }
+
+        self.left.push(scope(left));
+        self.right.push(scope(right));
+
+        let eq = left
+            .form()
+            .iter()
+            .zip(right.form())
+            .all(|((l_id, l), (r_id, r))| self.id(l_id, r_id) && self.scalar(l, r));
+
+        self.left.pop();
+        self.right.pop();
+
+        eq
+    }
+
+    fn id(&self, left: &'a Id, right: &'a Id) -> bool {
+        resolve(&self.left, left) == resolve(&self.right, right)
+    }
+
+    fn id_ref(&self, left: &'a IdRef, right: &'a IdRef) -> bool {
+        self.id(left.id(), right.id())
+    }
+
+    fn scalar(&mut self, left: &'a Scalar, right: &'a Scalar) -> bool {
+        match (left, right) {
+            (Scalar::Value(l), Scalar::Value(r)) => l == r,
+            (Scalar::Bytes(l), Scalar::Bytes(r)) => l == r,
+            (Scalar::Ref(l), Scalar::Ref(r)) => self.tc_ref(l, r),
+            (Scalar::Op(l), Scalar::Op(r)) => self.op_def(l, r),
+            (Scalar::Map(l), Scalar::Map(r)) => {
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|((l_key, l), (r_key, r))| l_key == r_key && self.scalar(l, r))
+            }
+            (Scalar::Tuple(l), Scalar::Tuple(r)) => {
+                l.len() == r.len() && l.iter().zip(r).all(|(l, r)| self.scalar(l, r))
+            }
+            _ => false,
+        }
+    }
+
+    fn tc_ref(&mut self, left: &'a TCRef, right: &'a TCRef) -> bool {
+        match (left, right) {
+            (TCRef::Id(l), TCRef::Id(r)) => self.id_ref(l, r),
+            (TCRef::Op(l), TCRef::Op(r)) => self.op_ref(l, r),
+            (TCRef::Cond(l), TCRef::Cond(r)) => {
+                self.tc_ref(&l.cond, &r.cond)
+                    && self.scalar(&l.then, &r.then)
+                    && self.scalar(&l.or_else, &r.or_else)
+            }
+            (TCRef::While(l), TCRef::While(r)) => {
+                self.scalar(&l.cond, &r.cond)
+                    && self.scalar(&l.closure, &r.closure)
+                    && self.scalar(&l.state, &r.state)
+            }
+            (TCRef::ForEach(l), TCRef::ForEach(r)) => {
+                l.item_name == r.item_name
+                    && self.scalar(&l.items, &r.items)
+                    && self.scalar(&l.op, &r.op)
+            }
+            _ => false,
+        }
+    }
+
+    fn op_ref(&mut self, left: &'a OpRef, right: &'a OpRef) -> bool {
+        if !self.subject(left.subject(), right.subject()) {
+            return false;
+        }
+
+        match (left, right) {
+            (OpRef::Get((_, l)), OpRef::Get((_, r))) => self.scalar(l, r),
+            (OpRef::Delete((_, l)), OpRef::Delete((_, r))) => self.scalar(l, r),
+            (OpRef::Put((_, l_key, l)), OpRef::Put((_, r_key, r))) => {
+                self.scalar(l_key, r_key) && self.scalar(l, r)
+            }
+            (OpRef::Post((_, l)), OpRef::Post((_, r))) => {
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|((l_key, l), (r_key, r))| l_key == r_key && self.scalar(l, r))
+            }
+            _ => false,
+        }
+    }
+
+    fn subject(&self, left: &'a Subject, right: &'a Subject) -> bool {
+        match (left, right) {
+            (Subject::Link(l), Subject::Link(r)) => l == r,
+            (Subject::Ref(l, l_path), Subject::Ref(r, r_path)) => {
+                l_path == r_path && self.id_ref(l, r)
+            }
+            _ => false,
+        }
+    }
+}
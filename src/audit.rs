@@ -0,0 +1,218 @@
+//! An encoding of a library which records the fingerprint of each of its ops, so that a later load
+//! can detect an op which was changed after it was persisted.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::str::FromStr;
+
+use destream::{de, en, EncodeMap};
+use pathlink::PathBuf;
+
+use crate::decode::{decode_root, At, DecodeOptions, Frame, FromStreamAt, Step};
+use crate::{LibrarySchema, OpDef};
+
+/// A [`LibrarySchema`] and the op definitions it serves, keyed by path, which encodes with the
+/// [`OpDef::fingerprint`] of each op and verifies every fingerprint when decoded.
+///
+/// This encodes as `{"schema": ..., "ops": {path: op, ...}, "fingerprints": {path: hex, ...}}`,
+/// where each fingerprint is 16 lowercase hex digits. Decoding fails if an op does not match its
+/// fingerprint, or if an op or a fingerprint is missing its counterpart. Since the canonical
+/// encoding normalizes numbers, an op whose literals decode as a different number type than they
+/// were built as (e.g. `1u64` as an int, or `2.0` as `2`) still matches its fingerprint.
+///
+/// A fingerprint is not a cryptographic hash, so this detects accidental or careless edits to a
+/// persisted library, not a deliberate forgery: anyone who can edit an op can also recompute its
+/// fingerprint. Sign the encoding itself if that matters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FingerprintedLibrary {
+    schema: LibrarySchema,
+    ops: BTreeMap<PathBuf, OpDef>,
+}
+
+impl FingerprintedLibrary {
+    pub fn new<I: IntoIterator<Item = (PathBuf, OpDef)>>(schema: LibrarySchema, ops: I) -> Self {
+        Self {
+            schema,
+            ops: ops.into_iter().collect(),
+        }
+    }
+
+    pub fn schema(&self) -> &LibrarySchema {
+        &self.schema
+    }
+
+    /// The op definitions of this library, keyed by path.
+    pub fn ops(&self) -> &BTreeMap<PathBuf, OpDef> {
+        &self.ops
+    }
+
+    /// The fingerprint of each op of this library, keyed by path.
+    pub fn fingerprints(&self) -> BTreeMap<PathBuf, u64> {
+        self.ops
+            .iter()
+            .map(|(path, op)| (path.clone(), op.fingerprint()))
+            .collect()
+    }
+
+    pub fn into_parts(self) -> (LibrarySchema, BTreeMap<PathBuf, OpDef>) {
+        (self.schema, self.ops)
+    }
+}
+
+/// Check that `fingerprints` lists exactly the fingerprint of every op in `ops`.
+fn verify(
+    ops: &BTreeMap<PathBuf, OpDef>,
+    fingerprints: &BTreeMap<PathBuf, u64>,
+) -> Result<(), String> {
+    for (path, op) in ops {
+        let actual = op.fingerprint();
+
+        match fingerprints.get(path) {
+            Some(expected) if *expected == actual => {}
+            Some(expected) => {
+                return Err(format!(
+                    "op {path} does not match its fingerprint (expected {expected:016x}, found {actual:016x})"
+                ))
+            }
+            None => return Err(format!("op {path} has no fingerprint")),
+        }
+    }
+
+    match fingerprints.keys().find(|path| !ops.contains_key(*path)) {
+        Some(path) => Err(format!("there is a fingerprint for {path} but no op")),
+        None => Ok(()),
+    }
+}
+
+fn parse_path(path: &str) -> Result<PathBuf, String> {
+    PathBuf::from_str(path).map_err(|err| format!("invalid op path {path:?}: {err}"))
+}
+
+fn parse_fingerprint(path: &PathBuf, fingerprint: &str) -> Result<u64, String> {
+    if fingerprint.len() != 16 {
+        return Err(format!(
+            "invalid fingerprint {fingerprint:?} for {path} (expected 16 hex digits)"
+        ));
+    }
+
+    u64::from_str_radix(fingerprint, 16)
+        .map_err(|err| format!("invalid fingerprint {fingerprint:?} for {path}: {err}"))
+}
+
+impl de::FromStream for FingerprintedLibrary {
+    type Context = DecodeOptions;
+
+    async fn from_stream<D: de::Decoder>(
+        options: Self::Context,
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        decode_root(options, decoder).await
+    }
+}
+
+impl FromStreamAt for FingerprintedLibrary {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        struct LibraryVisitor<'a> {
+            frame: Frame<'a>,
+        }
+
+        impl de::Visitor for LibraryVisitor<'_> {
+            type Value = FingerprintedLibrary;
+
+            fn expecting() -> &'static str {
+                "a fingerprinted library map"
+            }
+
+            async fn visit_map<A: de::MapAccess>(
+                self,
+                mut map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut schema = None;
+                let mut ops = None;
+                let mut fingerprints = None;
+
+                while let Some(key) = map.next_key::<String>(()).await? {
+                    match key.as_str() {
+                        "schema" if schema.is_none() => {
+                            schema = Some(map.next_value::<LibrarySchema>(()).await?);
+                        }
+                        "ops" if ops.is_none() => {
+                            let at = self.frame.child(Step::Key("ops"));
+                            let frame = self.frame.within(&at);
+                            let At(value, _) =
+                                map.next_value::<At<BTreeMap<String, OpDef>>>(frame).await?;
+                            ops = Some(value);
+                        }
+                        "fingerprints" if fingerprints.is_none() => {
+                            fingerprints =
+                                Some(map.next_value::<BTreeMap<String, String>>(()).await?);
+                        }
+                        "schema" | "ops" | "fingerprints" => {
+                            return Err(de::Error::custom(format!("duplicate {key} field")));
+                        }
+                        _ => {
+                            let _ = map.next_value::<de::IgnoredAny>(()).await?;
+                        }
+                    }
+                }
+
+                let schema = schema.ok_or_else(|| de::Error::custom("missing schema field"))?;
+
+                let ops = ops
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(path, op)| parse_path(&path).map(|path| (path, op)))
+                    .collect::<Result<BTreeMap<_, _>, _>>()
+                    .map_err(de::Error::custom)?;
+
+                let fingerprints = fingerprints
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(path, fingerprint)| {
+                        let path = parse_path(&path)?;
+                        let fingerprint = parse_fingerprint(&path, &fingerprint)?;
+                        Ok((path, fingerprint))
+                    })
+                    .collect::<Result<BTreeMap<_, _>, String>>()
+                    .map_err(de::Error::custom)?;
+
+                verify(&ops, &fingerprints).map_err(de::Error::custom)?;
+
+                Ok(FingerprintedLibrary { schema, ops })
+            }
+        }
+
+        decoder.decode_map(LibraryVisitor { frame })
+    }
+}
+
+impl<'en> en::IntoStream<'en> for FingerprintedLibrary {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let fingerprints = self
+            .fingerprints()
+            .into_iter()
+            .map(|(path, fingerprint)| (path.to_string(), format!("{fingerprint:016x}")))
+            .collect::<BTreeMap<_, _>>();
+
+        let ops = self
+            .ops
+            .into_iter()
+            .map(|(path, op)| (path.to_string(), op))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut map = encoder.encode_map(Some(3))?;
+        map.encode_entry("schema", self.schema)?;
+        map.encode_entry("ops", ops)?;
+        map.encode_entry("fingerprints", fingerprints)?;
+        map.end()
+    }
+}
+
+impl<'en> en::ToStream<'en> for FingerprintedLibrary {
+    fn to_stream<E: en::Encoder<'en>>(&'en self, encoder: E) -> Result<E::Ok, E::Error> {
+        en::IntoStream::into_stream(self.clone(), encoder)
+    }
+}
@@ -0,0 +1,423 @@
+//! Authorization claims and the capability bits TinyChain assigns to them.
+//!
+//! A claim's mask is a [`umask::Mode`]. TinyChain expresses the capabilities of the claim's holder
+//! in the owner ("user") class of the mode:
+//!
+//! - [`READ`] (`0o400`): read state or invoke `GET` on the claimed link.
+//! - [`WRITE`] (`0o200`): mutate state, e.g. `PUT` or `DELETE`.
+//! - [`EXECUTE`] (`0o100`): execute ops, e.g. `POST`.
+//! - [`ADMIN`] (`0o777`): every bit, so it implies all of the above.
+//!
+//! The group and other classes are reserved for the control plane.
+
+use std::hash::Hasher;
+use std::str::FromStr;
+
+use pathlink::Link;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tc_error::{TCError, TCResult};
+
+use crate::FingerprintHasher;
+
+/// Permission to read state on the claimed link.
+pub const READ: umask::Mode = umask::USER_READ;
+
+/// Permission to mutate state on the claimed link.
+pub const WRITE: umask::Mode = umask::USER_WRITE;
+
+/// Permission to execute ops on the claimed link.
+pub const EXECUTE: umask::Mode = umask::USER_EXEC;
+
+/// Every permission bit, implying all other capabilities.
+pub const ADMIN: umask::Mode = umask::Mode::all();
+
+/// Authorization data issued by the control-plane / IAM stack.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Claim {
+    pub link: Link,
+    pub mask: umask::Mode,
+}
+
+impl Claim {
+    pub fn new(link: Link, mask: umask::Mode) -> Self {
+        Self { link, mask }
+    }
+
+    /// Start building a claim on `link` which grants no capabilities until they're added.
+    pub fn builder(link: Link) -> ClaimBuilder {
+        ClaimBuilder {
+            link,
+            mask: umask::Mode::from(0),
+        }
+    }
+
+    /// Parse a symbolic permission string like `rwx` or `r--` into a mask.
+    ///
+    /// The short form has one position per capability, in the order [`READ`], [`WRITE`],
+    /// [`EXECUTE`], each either its letter or `-`. A mask with group or other bits (like
+    /// [`ADMIN`]) uses the long form, which continues with the group and other classes in the
+    /// same order, like `rwxrwxrwx`; a long form without any of those bits is an error, since it
+    /// has a short form. [`Claim::format_mode`] is the inverse.
+    pub fn parse_mode(symbolic: &str) -> TCResult<umask::Mode> {
+        let invalid = || {
+            TCError::bad_request(format!(
+                "invalid permission string {symbolic:?} (expected e.g. \"rwx\", \"r--\", or \"rwxrwxrwx\")"
+            ))
+        };
+
+        let symbols = symbolic.chars().collect::<Vec<_>>();
+        if symbols.len() != SHORT_MODE_LEN && symbols.len() != SYMBOLS.len() {
+            return Err(invalid());
+        }
+
+        let mask = symbols
+            .iter()
+            .zip(SYMBOLS)
+            .try_fold(0, |mask, (symbol, (letter, bit))| match *symbol {
+                '-' => Ok(mask),
+                symbol if symbol == letter => Ok(mask | u32::from(bit)),
+                _ => Err(invalid()),
+            })?;
+
+        if symbols.len() == SYMBOLS.len() && mask & !USER_MASK == 0 {
+            return Err(TCError::bad_request(format!(
+                "permission string {symbolic:?} has no group or other bits, so write it as {:?}",
+                &symbolic[..SHORT_MODE_LEN]
+            )));
+        }
+
+        Ok(umask::Mode::from(mask))
+    }
+
+    /// Format the permission bits of `mask` as a symbolic permission string like `r-x`, or like
+    /// `rwxrwxrwx` if it has group or other bits; see [`Claim::parse_mode`].
+    pub fn format_mode(mask: umask::Mode) -> String {
+        let mask = u32::from(mask);
+        let len = if mask & ALL_MASK & !USER_MASK == 0 {
+            SHORT_MODE_LEN
+        } else {
+            SYMBOLS.len()
+        };
+
+        SYMBOLS[..len]
+            .iter()
+            .map(|(letter, bit)| {
+                let bit = u32::from(*bit);
+                if mask & bit == bit {
+                    *letter
+                } else {
+                    '-'
+                }
+            })
+            .collect()
+    }
+
+    /// Construct a claim on `link` from a list of named capabilities, as issued by an IAM system
+    /// which doesn't use bitmasks.
+    ///
+    /// The names are `"read"` ([`READ`]), `"write"` ([`WRITE`]), `"execute"` ([`EXECUTE`]), and
+    /// `"admin"` ([`ADMIN`]); any other name is an error. [`Claim::capabilities`] is the inverse.
+    pub fn from_capabilities(link: Link, capabilities: &[&str]) -> TCResult<Self> {
+        let mask = capabilities.iter().try_fold(0, |mask, name| {
+            CAPABILITIES
+                .into_iter()
+                .find(|(capability, _)| capability == name)
+                .map(|(_, bit)| mask | u32::from(bit))
+                .ok_or_else(|| {
+                    TCError::bad_request(format!(
+                        "unknown capability {name:?} (expected one of \"read\", \"write\", \"execute\", or \"admin\")"
+                    ))
+                })
+        })?;
+
+        Ok(Self::new(link, mask.into()))
+    }
+
+    /// The named capabilities this claim grants (see [`Claim::from_capabilities`]).
+    ///
+    /// A claim with every bit set is just `["admin"]`. Otherwise this lists the capabilities
+    /// granted by the owner class of the mask, so any group or other bits are omitted.
+    pub fn capabilities(&self) -> Vec<&'static str> {
+        if self.mask == ADMIN {
+            return vec!["admin"];
+        }
+
+        let mask = u32::from(self.mask);
+
+        CAPABILITIES
+            .into_iter()
+            .filter(|(_, bit)| *bit != ADMIN && mask & u32::from(*bit) == u32::from(*bit))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Construct a claim which grants every capability ([`ADMIN`]) on `link`.
+    pub fn grant_admin(link: Link) -> Self {
+        Self::new(link, ADMIN)
+    }
+
+    /// Return true if this claim grants the required mask.
+    pub fn allows(&self, link: &Link, required: umask::Mode) -> bool {
+        if self.link != *link {
+            return false;
+        }
+
+        let have: u32 = self.mask.into();
+        let need: u32 = required.into();
+        have & need == need
+    }
+
+    /// The authority granted by both this claim and `other`: the intersection of their masks, if
+    /// both claim the same link (a claim grants nothing on any other link).
+    pub fn intersect(&self, other: &Claim) -> Option<Claim> {
+        if self.link != other.link {
+            return None;
+        }
+
+        let mask = u32::from(self.mask) & u32::from(other.mask);
+        Some(Self::new(self.link.clone(), mask.into()))
+    }
+
+    /// Narrow this claim to the capabilities it shares with `mask`, e.g. to delegate read-only
+    /// access. The result never grants more than this claim.
+    pub fn attenuate(&self, mask: umask::Mode) -> Claim {
+        let mask = u32::from(self.mask) & u32::from(mask);
+        Self::new(self.link.clone(), mask.into())
+    }
+
+    /// Render this claim for a log without its link, as a stable fingerprint of the link followed
+    /// by the symbolic mask, e.g. `link:3f2a9c0d1e4b5a67 r-x`.
+    ///
+    /// The same link always renders the same way, so redacted log lines can still be correlated.
+    pub fn redacted(&self) -> String {
+        format!(
+            "{} {}",
+            redact_link(&self.link),
+            Self::format_mode(self.mask)
+        )
+    }
+}
+
+/// A stable fingerprint of `link` to log in its place (see [`Claim::redacted`]).
+pub(crate) fn redact_link(link: &Link) -> String {
+    let mut hasher = FingerprintHasher::new();
+    hasher.write(link.to_string().as_bytes());
+    format!("link:{:016x}", hasher.finish())
+}
+
+/// A [`Claim`] which records the path of grantors through which it was delegated, for audit.
+///
+/// Each call to [`DelegatedClaim::attenuate`] or [`DelegatedClaim::intersect`] appends the link
+/// of the grantor which narrowed the claim, so [`DelegatedClaim::grantors`] lists them in the
+/// order they delegated it. This encodes as `[link, mask]` like a `Claim` if there are no
+/// grantors, or `[link, mask, [grantor, ...]]` otherwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegatedClaim {
+    claim: Claim,
+    grantors: Vec<Link>,
+}
+
+impl DelegatedClaim {
+    /// Start a delegation chain at `claim`, with no grantors.
+    pub fn new(claim: Claim) -> Self {
+        Self {
+            claim,
+            grantors: Vec::new(),
+        }
+    }
+
+    /// The authority this claim grants.
+    pub fn claim(&self) -> &Claim {
+        &self.claim
+    }
+
+    /// The grantors which delegated this claim, first to last.
+    pub fn grantors(&self) -> &[Link] {
+        &self.grantors
+    }
+
+    /// Delegate this claim as narrowed by `grantor` to `mask`; see [`Claim::attenuate`].
+    pub fn attenuate(&self, grantor: Link, mask: umask::Mode) -> Self {
+        self.delegate(grantor, self.claim.attenuate(mask))
+    }
+
+    /// Delegate this claim as narrowed by `grantor` to the authority it shares with `other`; see
+    /// [`Claim::intersect`].
+    pub fn intersect(&self, grantor: Link, other: &Claim) -> Option<Self> {
+        self.claim
+            .intersect(other)
+            .map(|claim| self.delegate(grantor, claim))
+    }
+
+    /// Discard the grantors of this claim.
+    pub fn into_claim(self) -> Claim {
+        self.claim
+    }
+
+    fn delegate(&self, grantor: Link, claim: Claim) -> Self {
+        let mut grantors = Vec::with_capacity(self.grantors.len() + 1);
+        grantors.extend_from_slice(&self.grantors);
+        grantors.push(grantor);
+
+        Self { claim, grantors }
+    }
+}
+
+impl From<Claim> for DelegatedClaim {
+    fn from(claim: Claim) -> Self {
+        Self::new(claim)
+    }
+}
+
+/// The symbol of each permission bit, in the order of the long symbolic form; the short form is
+/// the first [`SHORT_MODE_LEN`] of them.
+const SYMBOLS: [(char, umask::Mode); 9] = [
+    ('r', READ),
+    ('w', WRITE),
+    ('x', EXECUTE),
+    ('r', umask::GROUP_READ),
+    ('w', umask::GROUP_WRITE),
+    ('x', umask::GROUP_EXEC),
+    ('r', umask::OTHERS_READ),
+    ('w', umask::OTHERS_WRITE),
+    ('x', umask::OTHERS_EXEC),
+];
+
+const SHORT_MODE_LEN: usize = 3;
+
+const USER_MASK: u32 = 0o700;
+
+const ALL_MASK: u32 = 0o777;
+
+const CAPABILITIES: [(&str, umask::Mode); 4] = [
+    ("read", READ),
+    ("write", WRITE),
+    ("execute", EXECUTE),
+    ("admin", ADMIN),
+];
+
+/// Builds a [`Claim`] one capability at a time; see [`Claim::builder`].
+#[derive(Clone, Debug)]
+pub struct ClaimBuilder {
+    link: Link,
+    mask: umask::Mode,
+}
+
+impl ClaimBuilder {
+    /// Grant [`READ`].
+    pub fn read(self) -> Self {
+        self.grant(READ)
+    }
+
+    /// Grant [`WRITE`].
+    pub fn write(self) -> Self {
+        self.grant(WRITE)
+    }
+
+    /// Grant [`EXECUTE`].
+    pub fn execute(self) -> Self {
+        self.grant(EXECUTE)
+    }
+
+    /// Grant every capability in `mask`, e.g. one parsed with [`Claim::parse_mode`].
+    pub fn grant(mut self, mask: umask::Mode) -> Self {
+        self.mask = (u32::from(self.mask) | u32::from(mask)).into();
+        self
+    }
+
+    pub fn build(self) -> Claim {
+        Claim::new(self.link, self.mask)
+    }
+}
+
+impl Serialize for Claim {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let tuple = (self.link.to_string(), u32::from(self.mask) as u16);
+        tuple.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Claim {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        <(String, u16)>::deserialize(deserializer).and_then(|(link, mask)| {
+            let link =
+                Link::from_str(&link).map_err(|err| serde::de::Error::custom(err.to_string()))?;
+            Ok(Claim {
+                link,
+                mask: (mask as u32).into(),
+            })
+        })
+    }
+}
+
+impl Serialize for DelegatedClaim {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeTuple;
+
+        let len = if self.grantors.is_empty() { 2 } else { 3 };
+
+        let mut tuple = serializer.serialize_tuple(len)?;
+        tuple.serialize_element(&self.claim.link.to_string())?;
+        tuple.serialize_element(&(u32::from(self.claim.mask) as u16))?;
+
+        if !self.grantors.is_empty() {
+            let grantors = self.grantors.iter().map(Link::to_string);
+            tuple.serialize_element(&grantors.collect::<Vec<_>>())?;
+        }
+
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for DelegatedClaim {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::{Error, SeqAccess, Visitor};
+
+        struct DelegatedClaimVisitor;
+
+        impl<'de> Visitor<'de> for DelegatedClaimVisitor {
+            type Value = DelegatedClaim;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a claim as [link, mask] or [link, mask, [grantor, ...]]")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let parse = |link: String| Link::from_str(&link).map_err(Error::custom);
+
+                let link = seq
+                    .next_element::<String>()?
+                    .ok_or_else(|| Error::invalid_length(0, &self))?;
+
+                let mask = seq
+                    .next_element::<u16>()?
+                    .ok_or_else(|| Error::invalid_length(1, &self))?;
+
+                let grantors = seq.next_element::<Vec<String>>()?.unwrap_or_default();
+
+                if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                    return Err(Error::invalid_length(4, &self));
+                }
+
+                Ok(DelegatedClaim {
+                    claim: Claim::new(parse(link)?, (mask as u32).into()),
+                    grantors: grantors.into_iter().map(parse).collect::<Result<_, _>>()?,
+                })
+            }
+        }
+
+        deserializer.deserialize_seq(DelegatedClaimVisitor)
+    }
+}
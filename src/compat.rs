@@ -0,0 +1,358 @@
+//! Conversions between the v1 `tc-scalar` / `tc-transact` types and the v2 IR, for adapters which
+//! still hold v1 values in memory.
+//!
+//! Every conversion is a `TryFrom`, since neither model is a subset of the other. A construct with
+//! no counterpart on the other side (e.g. a v1 `After` ref, a v1 range, or a v2 `ForEach` ref) is
+//! rejected with an error naming it, rather than approximated. Identifiers, paths and links are
+//! converted through their string forms, since the two stacks depend on different releases of
+//! `hr-id` and `pathlink`.
+//!
+//! The fields of the v1 `If` and `While` refs are private, so those refs cannot be converted here;
+//! encode them with the v1 stack and decode the JSON into the v2 types instead (see
+//! `IR_INTERFACE_GUIDELINES.md`).
+
+use std::fmt;
+use std::str::FromStr;
+
+use number_general::{Float, Int, Number, UInt};
+use pathlink::{Link, PathBuf};
+use tc_error::{TCError, TCResult};
+use tc_scalar::value as v1_value;
+use tc_value::Value;
+
+use crate::{Id, IdRef, Map, NetworkTime, OpDef, OpRef, Scalar, Subject, TCRef, TxnId};
+
+fn no_v2(construct: &str) -> TCError {
+    TCError::bad_request(format!("{construct} has no v2 IR counterpart"))
+}
+
+fn no_v1(construct: &str) -> TCError {
+    TCError::bad_request(format!("{construct} has no v1 counterpart"))
+}
+
+fn reparse<T: FromStr>(what: &str, from: impl fmt::Display) -> TCResult<T>
+where
+    T::Err: fmt::Display,
+{
+    let from = from.to_string();
+    from.parse()
+        .map_err(|err| TCError::bad_request(format!("cannot convert {what} {from:?}: {err}")))
+}
+
+fn map_from_v1<F, T>(map: tcgeneric::Map<F>) -> TCResult<Map<T>>
+where
+    T: TryFrom<F, Error = TCError>,
+{
+    map.into_iter()
+        .map(|(id, item)| Ok((reparse("id", id)?, T::try_from(item)?)))
+        .collect()
+}
+
+fn map_into_v1<F, T>(map: Map<F>) -> TCResult<tcgeneric::Map<T>>
+where
+    T: TryFrom<F, Error = TCError>,
+{
+    map.into_iter()
+        .map(|(id, item)| Ok((reparse("id", id)?, T::try_from(item)?)))
+        .collect::<TCResult<std::collections::BTreeMap<_, _>>>()
+        .map(tcgeneric::Map::from)
+}
+
+fn form_from_v1(form: Vec<(tcgeneric::Id, tc_scalar::Scalar)>) -> TCResult<Vec<(Id, Scalar)>> {
+    form.into_iter()
+        .map(|(id, scalar)| Ok((reparse("id", id)?, Scalar::try_from(scalar)?)))
+        .collect()
+}
+
+fn form_into_v1(form: Vec<(Id, Scalar)>) -> TCResult<Vec<(tcgeneric::Id, tc_scalar::Scalar)>> {
+    form.into_iter()
+        .map(|(id, scalar)| Ok((reparse("id", id)?, tc_scalar::Scalar::try_from(scalar)?)))
+        .collect()
+}
+
+fn number_from_v1(number: v1_value::Number) -> TCResult<Number> {
+    use v1_value::{Float as F, Int as I, Number as N, UInt as U};
+
+    Ok(match number {
+        N::Bool(b) => Number::from(bool::from(b)),
+        N::Complex(_) => return Err(no_v2("a v1 complex number")),
+        N::Float(F::F32(f)) => Number::Float(Float::F32(f)),
+        N::Float(F::F64(f)) => Number::Float(Float::F64(f)),
+        N::Int(I::I8(i)) => Number::Int(Int::I8(i)),
+        N::Int(I::I16(i)) => Number::Int(Int::I16(i)),
+        N::Int(I::I32(i)) => Number::Int(Int::I32(i)),
+        N::Int(I::I64(i)) => Number::Int(Int::I64(i)),
+        N::UInt(U::U8(u)) => Number::UInt(UInt::U8(u)),
+        N::UInt(U::U16(u)) => Number::UInt(UInt::U16(u)),
+        N::UInt(U::U32(u)) => Number::UInt(UInt::U32(u)),
+        N::UInt(U::U64(u)) => Number::UInt(UInt::U64(u)),
+    })
+}
+
+fn number_into_v1(number: Number) -> TCResult<v1_value::Number> {
+    use v1_value::{Float as F, Int as I, Number as N, UInt as U};
+
+    Ok(match number {
+        Number::Bool(b) => N::from(bool::from(b)),
+        Number::Complex(_) => return Err(no_v1("a complex number")),
+        Number::Float(Float::F32(f)) => N::Float(F::F32(f)),
+        Number::Float(Float::F64(f)) => N::Float(F::F64(f)),
+        Number::Int(Int::I8(i)) => N::Int(I::I8(i)),
+        Number::Int(Int::I16(i)) => N::Int(I::I16(i)),
+        Number::Int(Int::I32(i)) => N::Int(I::I32(i)),
+        Number::Int(Int::I64(i)) => N::Int(I::I64(i)),
+        Number::UInt(UInt::U8(u)) => N::UInt(U::U8(u)),
+        Number::UInt(UInt::U16(u)) => N::UInt(U::U16(u)),
+        Number::UInt(UInt::U32(u)) => N::UInt(U::U32(u)),
+        Number::UInt(UInt::U64(u)) => N::UInt(U::U64(u)),
+    })
+}
+
+/// A v1 value becomes a v2 [`Scalar`] rather than a [`Value`], since a v1 tuple or byte string
+/// value is a scalar in the v2 IR.
+fn scalar_from_v1_value(value: v1_value::Value) -> TCResult<Scalar> {
+    use v1_value::Value as V;
+
+    match value {
+        V::Bytes(bytes) => Ok(Scalar::Bytes(bytes.to_vec())),
+        V::Email(_) => Err(no_v2("a v1 email address value")),
+        V::Id(_) => Err(no_v2("a v1 Id value")),
+        V::Link(link) => reparse::<Link>("link", link).map(|link| Value::Link(link).into()),
+        V::None => Ok(Scalar::Value(Value::None)),
+        V::Number(n) => number_from_v1(n).map(|n| Value::Number(n).into()),
+        V::String(s) => Ok(Value::String(s.to_string()).into()),
+        V::Tuple(tuple) => tuple
+            .into_iter()
+            .map(scalar_from_v1_value)
+            .collect::<TCResult<_>>()
+            .map(Scalar::Tuple),
+        V::Version(_) => Err(no_v2("a v1 version value")),
+    }
+}
+
+fn value_into_v1(value: Value) -> TCResult<v1_value::Value> {
+    match value {
+        Value::Number(n) => number_into_v1(n).map(v1_value::Value::Number),
+        Value::String(s) => Ok(v1_value::Value::String(s.into())),
+        Value::Link(link) => reparse("link", link).map(v1_value::Value::Link),
+        other => {
+            let other = Scalar::Value(other);
+
+            if other.is_none() {
+                Ok(v1_value::Value::None)
+            } else {
+                Err(no_v1(&format!("the value {other:?}")))
+            }
+        }
+    }
+}
+
+impl TryFrom<tc_scalar::Scalar> for Scalar {
+    type Error = TCError;
+
+    fn try_from(scalar: tc_scalar::Scalar) -> TCResult<Self> {
+        use tc_scalar::Scalar as S;
+
+        match scalar {
+            S::Cluster(_) => Err(no_v2("a v1 cluster ref")),
+            S::Map(map) => map_from_v1(map).map(Scalar::Map),
+            S::Op(op) => OpDef::try_from(op).map(Scalar::Op),
+            S::Range(_) => Err(no_v2("a v1 range")),
+            S::Ref(tc_ref) => TCRef::try_from(*tc_ref).map(|r| Scalar::Ref(Box::new(r))),
+            S::Tuple(tuple) => tuple
+                .into_iter()
+                .map(Scalar::try_from)
+                .collect::<TCResult<_>>()
+                .map(Scalar::Tuple),
+            S::Value(value) => scalar_from_v1_value(value),
+        }
+    }
+}
+
+impl TryFrom<Scalar> for tc_scalar::Scalar {
+    type Error = TCError;
+
+    fn try_from(scalar: Scalar) -> TCResult<Self> {
+        use tc_scalar::Scalar as S;
+
+        match scalar {
+            Scalar::Value(value) => value_into_v1(value).map(S::Value),
+            Scalar::Ref(tc_ref) => tc_scalar::TCRef::try_from(*tc_ref).map(|r| S::Ref(Box::new(r))),
+            Scalar::Op(op) => tc_scalar::OpDef::try_from(op).map(S::Op),
+            Scalar::Map(map) => map_into_v1(map).map(S::Map),
+            Scalar::Tuple(tuple) => tuple
+                .into_iter()
+                .map(S::try_from)
+                .collect::<TCResult<Vec<_>>>()
+                .map(|tuple| S::Tuple(tuple.into())),
+            Scalar::Bytes(bytes) => Ok(S::Value(v1_value::Value::Bytes(bytes.into()))),
+        }
+    }
+}
+
+impl TryFrom<tc_scalar::TCRef> for TCRef {
+    type Error = TCError;
+
+    fn try_from(tc_ref: tc_scalar::TCRef) -> TCResult<Self> {
+        use tc_scalar::TCRef as R;
+
+        match tc_ref {
+            R::After(_) => Err(no_v2("a v1 After ref")),
+            R::Case(_) => Err(no_v2("a v1 Case ref")),
+            R::Id(id_ref) => reparse("id", id_ref.id()).map(|id| TCRef::Id(IdRef::new(id))),
+            R::If(_) => Err(no_v2(
+                "an in-memory v1 If ref (decode its JSON encoding instead)",
+            )),
+            R::Op(op_ref) => OpRef::try_from(op_ref).map(TCRef::Op),
+            R::While(_) => Err(no_v2(
+                "an in-memory v1 While ref (decode its JSON encoding instead)",
+            )),
+            R::With(_) => Err(no_v2("a v1 With ref")),
+        }
+    }
+}
+
+impl TryFrom<TCRef> for tc_scalar::TCRef {
+    type Error = TCError;
+
+    fn try_from(tc_ref: TCRef) -> TCResult<Self> {
+        match tc_ref {
+            TCRef::Op(op_ref) => tc_scalar::OpRef::try_from(op_ref).map(Self::Op),
+            TCRef::Id(id_ref) => reparse::<tcgeneric::Id>("id", id_ref.id())
+                .map(|id| Self::Id(tc_scalar::IdRef::from(id))),
+            TCRef::Cond(_) => Err(no_v1("a Cond ref")),
+            TCRef::While(_) => Err(no_v1("a While ref")),
+            TCRef::ForEach(_) => Err(no_v1("a ForEach ref")),
+        }
+    }
+}
+
+impl TryFrom<tc_scalar::Subject> for Subject {
+    type Error = TCError;
+
+    fn try_from(subject: tc_scalar::Subject) -> TCResult<Self> {
+        match subject {
+            tc_scalar::Subject::Link(link) => reparse("link", link).map(Subject::Link),
+            tc_scalar::Subject::Ref(id_ref, path) => Ok(Subject::Ref(
+                IdRef::new(reparse("id", id_ref.id())?),
+                reparse::<PathBuf>("path", path)?,
+            )),
+        }
+    }
+}
+
+impl TryFrom<Subject> for tc_scalar::Subject {
+    type Error = TCError;
+
+    fn try_from(subject: Subject) -> TCResult<Self> {
+        match subject {
+            Subject::Link(link) => reparse("link", link).map(Self::Link),
+            Subject::Ref(id_ref, path) => Ok(Self::Ref(
+                tc_scalar::IdRef::from(reparse::<tcgeneric::Id>("id", id_ref.id())?),
+                reparse("path", path)?,
+            )),
+        }
+    }
+}
+
+impl TryFrom<tc_scalar::OpRef> for OpRef {
+    type Error = TCError;
+
+    fn try_from(op_ref: tc_scalar::OpRef) -> TCResult<Self> {
+        use tc_scalar::OpRef as R;
+
+        Ok(match op_ref {
+            R::Get((subject, key)) => OpRef::Get((subject.try_into()?, key.try_into()?)),
+            R::Put((subject, key, value)) => {
+                OpRef::Put((subject.try_into()?, key.try_into()?, value.try_into()?))
+            }
+            R::Post((subject, params)) => OpRef::Post((subject.try_into()?, map_from_v1(params)?)),
+            R::Delete((subject, key)) => OpRef::Delete((subject.try_into()?, key.try_into()?)),
+        })
+    }
+}
+
+impl TryFrom<OpRef> for tc_scalar::OpRef {
+    type Error = TCError;
+
+    fn try_from(op_ref: OpRef) -> TCResult<Self> {
+        Ok(match op_ref {
+            OpRef::Get((subject, key)) => Self::Get((subject.try_into()?, key.try_into()?)),
+            OpRef::Put((subject, key, value)) => {
+                Self::Put((subject.try_into()?, key.try_into()?, value.try_into()?))
+            }
+            OpRef::Post((subject, params)) => {
+                Self::Post((subject.try_into()?, map_into_v1(params)?))
+            }
+            OpRef::Delete((subject, key)) => Self::Delete((subject.try_into()?, key.try_into()?)),
+        })
+    }
+}
+
+impl TryFrom<tc_scalar::OpDef> for OpDef {
+    type Error = TCError;
+
+    fn try_from(op_def: tc_scalar::OpDef) -> TCResult<Self> {
+        use tc_scalar::OpDef as D;
+
+        Ok(match op_def {
+            D::Get((key, form)) => OpDef::Get((reparse("id", key)?, form_from_v1(form)?)),
+            D::Put((key, value, form)) => OpDef::Put((
+                reparse("id", key)?,
+                reparse("id", value)?,
+                form_from_v1(form)?,
+            )),
+            D::Post(form) => OpDef::Post(form_from_v1(form)?),
+            D::Delete((key, form)) => OpDef::Delete((reparse("id", key)?, form_from_v1(form)?)),
+        })
+    }
+}
+
+impl TryFrom<OpDef> for tc_scalar::OpDef {
+    type Error = TCError;
+
+    fn try_from(op_def: OpDef) -> TCResult<Self> {
+        Ok(match op_def {
+            OpDef::Get((key, form)) => Self::Get((reparse("id", key)?, form_into_v1(form)?)),
+            OpDef::Put((key, value, form)) => Self::Put((
+                reparse("id", key)?,
+                reparse("id", value)?,
+                form_into_v1(form)?,
+            )),
+            OpDef::Post(form) => Self::Post(form_into_v1(form)?),
+            OpDef::Delete((key, form)) => Self::Delete((reparse("id", key)?, form_into_v1(form)?)),
+        })
+    }
+}
+
+impl TryFrom<tc_transact::TxnId> for TxnId {
+    type Error = TCError;
+
+    /// A v1 `TxnId` is a timestamp and a nonce, so its v2 counterpart has a zero trace.
+    fn try_from(txn_id: tc_transact::TxnId) -> TCResult<Self> {
+        let encoded = txn_id.to_string();
+        let (timestamp, nonce) = encoded
+            .split_once('-')
+            .ok_or_else(|| TCError::bad_request(format!("invalid v1 TxnId {encoded}")))?;
+
+        Ok(TxnId::from_parts(
+            NetworkTime::from_nanos(reparse("v1 TxnId timestamp", timestamp)?),
+            reparse("v1 TxnId nonce", nonce)?,
+        ))
+    }
+}
+
+impl TryFrom<TxnId> for tc_transact::TxnId {
+    type Error = TCError;
+
+    fn try_from(txn_id: TxnId) -> TCResult<Self> {
+        if txn_id.trace_bytes().iter().any(|byte| *byte != 0) {
+            return Err(no_v1("the trace of a TxnId"));
+        }
+
+        let encoded = format!("{}-{}", txn_id.timestamp().as_nanos(), txn_id.nonce());
+        encoded
+            .parse()
+            .map_err(|err| TCError::bad_request(format!("cannot convert TxnId {encoded}: {err}")))
+    }
+}
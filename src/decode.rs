@@ -0,0 +1,735 @@
+//! Options which tune how strictly a [`Scalar`] is decoded, statistics on how much work a decode
+//! took, and the frames which let a decode error name where it was raised.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use destream::de;
+use pathlink::{Link, PathBuf};
+
+use crate::{
+    Id, Scalar, Subject, NUMBER_METHODS, NUMBER_OP_PREFIX, OPDEF_REFLECT_FORM,
+    OPDEF_REFLECT_LAST_ID, OPDEF_REFLECT_SCALARS, SCALAR_PREFIX, SCALAR_REFLECT_CLASS,
+    SCALAR_REFLECT_REF_PARTS, TCREF_COND, TCREF_FOR_EACH, TCREF_IF, TCREF_WHILE,
+};
+
+/// What to do with a subject-keyed map like `{"/state/scalar/value/numbr": [3]}`, whose
+/// `/state/scalar/...` key is not a class or op the IR knows.
+///
+/// The `/state/scalar` namespace belongs to the IR, so under [`UnknownClassPolicy::Reject`] an
+/// op ref into it must target one of the [`crate::NUMBER_METHODS`] (like
+/// `/state/scalar/value/number/add`) or a reflection op (like `/state/scalar/reflect/class`). Op
+/// refs elsewhere under `/state` may target runtime classes the IR doesn't know, and are always
+/// accepted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownClassPolicy {
+    /// Decode it as an op ref to that path, like any other link key (the v1 behavior).
+    #[default]
+    OpRef,
+    /// Fail to decode, e.g. to catch a misspelled typed-value key in a library manifest.
+    Reject,
+}
+
+/// What to do with a control-flow ref (`/state/scalar/ref/if`, `cond`, `while`, or `for_each`).
+///
+/// Under [`ControlFlowPolicy::Reject`], a control-flow ref anywhere in the decoded value
+/// (including within an op definition) is an error naming its path, raised before its arguments
+/// are decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlFlowPolicy {
+    /// Decode it as a [`crate::TCRef::Cond`], [`crate::TCRef::While`], or
+    /// [`crate::TCRef::ForEach`].
+    #[default]
+    Allow,
+    /// Fail to decode, e.g. in a minimal host which only executes straight-line op sequences.
+    Reject,
+}
+
+/// Options for decoding a [`Scalar`], passed as the context of its [`de::FromStream`] impl (or
+/// that of an [`crate::OpDef`], [`crate::OpRef`], or [`crate::TCRef`]) and applied to every node
+/// within it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub unknown_class: UnknownClassPolicy,
+    pub control_flow: ControlFlowPolicy,
+}
+
+impl DecodeOptions {
+    /// The strictest options: reject every unknown `/state/scalar/...` key.
+    pub fn strict() -> Self {
+        Self {
+            unknown_class: UnknownClassPolicy::Reject,
+            ..Self::default()
+        }
+    }
+
+    /// These options, but rejecting every control-flow ref; see [`ControlFlowPolicy::Reject`].
+    pub fn without_control_flow(self) -> Self {
+        Self {
+            control_flow: ControlFlowPolicy::Reject,
+            ..self
+        }
+    }
+}
+
+/// How much work it took to decode a [`Scalar`], as reported by
+/// [`Scalar::from_stream_with_stats`], e.g. to rate-limit the senders of expensive manifests.
+///
+/// The counts cover every map and sequence in the encoded scalar, including the tuples within an
+/// op definition or explicit op ref and any value which is skipped, but not typed arrays. They
+/// are collected by wrapping the decoder, so a plain decode pays nothing for them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// The number of maps decoded.
+    pub maps: usize,
+    /// The number of sequences decoded.
+    pub seqs: usize,
+    /// The greatest number of maps and sequences open at once.
+    pub max_depth: usize,
+}
+
+impl Scalar {
+    /// Decode a scalar like [`de::FromStream::from_stream`] with the given `options`, and also
+    /// return [`DecodeStats`] describing the work done to decode it.
+    pub async fn from_stream_with_stats<D: de::Decoder>(
+        options: DecodeOptions,
+        decoder: &mut D,
+    ) -> Result<(Self, DecodeStats), D::Error> {
+        let tracker = Tracker::default();
+        let mut decoder = CountingDecoder::new(decoder, &tracker);
+        let scalar = <Self as de::FromStream>::from_stream(options, &mut decoder).await?;
+        Ok((scalar, tracker.stats()))
+    }
+}
+
+/// The counts behind a [`DecodeStats`], shared by every [`CountingDecoder`] of one decode.
+#[derive(Default)]
+struct Tracker {
+    maps: AtomicUsize,
+    seqs: AtomicUsize,
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+}
+
+impl Tracker {
+    fn open(&self, counter: &AtomicUsize) -> OpenCollection<'_> {
+        counter.fetch_add(1, Ordering::Relaxed);
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+        OpenCollection(self)
+    }
+
+    fn stats(&self) -> DecodeStats {
+        DecodeStats {
+            maps: self.maps.load(Ordering::Relaxed),
+            seqs: self.seqs.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Marks a collection as open until dropped, for [`DecodeStats::max_depth`].
+struct OpenCollection<'t>(&'t Tracker);
+
+impl Drop for OpenCollection<'_> {
+    fn drop(&mut self) {
+        self.0.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A [`de::Decoder`] which records every map and sequence it decodes in a [`Tracker`], and wraps
+/// the decoder of each of their items in turn.
+struct CountingDecoder<'d, 't, D> {
+    inner: &'d mut D,
+    tracker: &'t Tracker,
+}
+
+impl<'d, 't, D> CountingDecoder<'d, 't, D> {
+    fn new(inner: &'d mut D, tracker: &'t Tracker) -> Self {
+        Self { inner, tracker }
+    }
+}
+
+macro_rules! forward_decode {
+    ($($method:ident),* $(,)?) => {
+        $(
+            async fn $method<V: de::Visitor>(&mut self, visitor: V) -> Result<V::Value, D::Error> {
+                let visitor = CountingVisitor::new(visitor, self.tracker);
+                self.inner.$method(visitor).await
+            }
+        )*
+    };
+}
+
+impl<D: de::Decoder> de::Decoder for CountingDecoder<'_, '_, D> {
+    type Error = D::Error;
+
+    forward_decode!(
+        decode_any,
+        decode_bool,
+        decode_bytes,
+        decode_i8,
+        decode_i16,
+        decode_i32,
+        decode_i64,
+        decode_u8,
+        decode_u16,
+        decode_u32,
+        decode_u64,
+        decode_f32,
+        decode_f64,
+        decode_array_bool,
+        decode_array_i8,
+        decode_array_i16,
+        decode_array_i32,
+        decode_array_i64,
+        decode_array_u8,
+        decode_array_u16,
+        decode_array_u32,
+        decode_array_u64,
+        decode_array_f32,
+        decode_array_f64,
+        decode_map,
+        decode_option,
+        decode_seq,
+        decode_string,
+        decode_unit,
+        decode_uuid,
+        decode_ignored_any,
+    );
+
+    async fn decode_tuple<V: de::Visitor>(
+        &mut self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, D::Error> {
+        let visitor = CountingVisitor::new(visitor, self.tracker);
+        self.inner.decode_tuple(len, visitor).await
+    }
+}
+
+/// A [`de::Visitor`] which forwards to `inner`, counting the maps and sequences it visits.
+struct CountingVisitor<'t, V> {
+    inner: V,
+    tracker: &'t Tracker,
+}
+
+impl<'t, V> CountingVisitor<'t, V> {
+    fn new(inner: V, tracker: &'t Tracker) -> Self {
+        Self { inner, tracker }
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<E: de::Error>(self, v: $ty) -> Result<Self::Value, E> {
+                self.inner.$method(v)
+            }
+        )*
+    };
+}
+
+macro_rules! forward_visit_array {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            async fn $method<A: de::ArrayAccess<$ty>>(
+                self,
+                array: A,
+            ) -> Result<Self::Value, A::Error> {
+                self.inner.$method(array).await
+            }
+        )*
+    };
+}
+
+impl<V: de::Visitor> de::Visitor for CountingVisitor<'_, V> {
+    type Value = V::Value;
+
+    fn expecting() -> &'static str {
+        V::expecting()
+    }
+
+    forward_visit!(
+        visit_bool(bool),
+        visit_i8(i8),
+        visit_i16(i16),
+        visit_i32(i32),
+        visit_i64(i64),
+        visit_u8(u8),
+        visit_u16(u16),
+        visit_u32(u32),
+        visit_u64(u64),
+        visit_f32(f32),
+        visit_f64(f64),
+        visit_string(String),
+    );
+
+    forward_visit_array!(
+        visit_array_bool(bool),
+        visit_array_i8(i8),
+        visit_array_i16(i16),
+        visit_array_i32(i32),
+        visit_array_i64(i64),
+        visit_array_u8(u8),
+        visit_array_u16(u16),
+        visit_array_u32(u32),
+        visit_array_u64(u64),
+        visit_array_f32(f32),
+        visit_array_f64(f64),
+    );
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.inner.visit_unit()
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.inner.visit_none()
+    }
+
+    async fn visit_some<D: de::Decoder>(self, decoder: &mut D) -> Result<Self::Value, D::Error> {
+        let mut decoder = CountingDecoder::new(decoder, self.tracker);
+        self.inner.visit_some(&mut decoder).await
+    }
+
+    async fn visit_map<A: de::MapAccess>(self, map: A) -> Result<Self::Value, A::Error> {
+        let _open = self.tracker.open(&self.tracker.maps);
+        let map = CountingAccess::new(map, self.tracker);
+        self.inner.visit_map(map).await
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, seq: A) -> Result<Self::Value, A::Error> {
+        let _open = self.tracker.open(&self.tracker.seqs);
+        let seq = CountingAccess::new(seq, self.tracker);
+        self.inner.visit_seq(seq).await
+    }
+}
+
+/// A [`de::MapAccess`] or [`de::SeqAccess`] which decodes each of its items with a
+/// [`CountingDecoder`].
+struct CountingAccess<'t, A> {
+    inner: A,
+    tracker: &'t Tracker,
+}
+
+impl<'t, A> CountingAccess<'t, A> {
+    fn new(inner: A, tracker: &'t Tracker) -> Self {
+        Self { inner, tracker }
+    }
+}
+
+impl<A: de::MapAccess> de::MapAccess for CountingAccess<'_, A> {
+    type Error = A::Error;
+
+    async fn next_key<K: de::FromStream>(
+        &mut self,
+        context: K::Context,
+    ) -> Result<Option<K>, Self::Error> {
+        let key = self
+            .inner
+            .next_key::<Counted<'_, K>>((context, self.tracker));
+        key.await.map(|key| key.map(|Counted(key, _)| key))
+    }
+
+    async fn next_value<V: de::FromStream>(
+        &mut self,
+        context: V::Context,
+    ) -> Result<V, Self::Error> {
+        let value = self
+            .inner
+            .next_value::<Counted<'_, V>>((context, self.tracker));
+        value.await.map(|Counted(value, _)| value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+impl<A: de::SeqAccess> de::SeqAccess for CountingAccess<'_, A> {
+    type Error = A::Error;
+
+    async fn next_element<T: de::FromStream>(
+        &mut self,
+        context: T::Context,
+    ) -> Result<Option<T>, Self::Error> {
+        let item = self
+            .inner
+            .next_element::<Counted<'_, T>>((context, self.tracker));
+        item.await.map(|item| item.map(|Counted(item, _)| item))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+/// A `T` decoded with a [`CountingDecoder`].
+struct Counted<'t, T>(T, PhantomData<&'t Tracker>);
+
+impl<'t, T: de::FromStream> de::FromStream for Counted<'t, T> {
+    type Context = (T::Context, &'t Tracker);
+
+    async fn from_stream<D: de::Decoder>(
+        (context, tracker): Self::Context,
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        let mut decoder = CountingDecoder::new(decoder, tracker);
+        let value = T::from_stream(context, &mut decoder).await?;
+        Ok(Counted(value, PhantomData))
+    }
+}
+
+/// A step from a node being decoded to one of its children: a map key or parameter name, `[i]`
+/// for a tuple item, or `form[i]` for an op binding.
+#[derive(Clone, Copy)]
+pub(crate) enum Step<'a> {
+    Key(&'a str),
+    Index(usize),
+    Binding(usize),
+}
+
+/// Where a node sits within the value being decoded, as a path of [`Step`]s from the root which
+/// each borrow from the frame of the node they step from.
+pub(crate) struct Location<'a> {
+    parent: Option<&'a Location<'a>>,
+    step: Step<'a>,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut steps = Vec::new();
+        let mut location = Some(self);
+        while let Some(Location { parent, step }) = location {
+            steps.push(*step);
+            location = *parent;
+        }
+
+        for (i, step) in steps.into_iter().rev().enumerate() {
+            match step {
+                Step::Key(key) if i == 0 => f.write_str(key)?,
+                Step::Key(key) => write!(f, ".{key}")?,
+                Step::Index(index) => write!(f, "[{index}]")?,
+                Step::Binding(index) if i == 0 => write!(f, "form[{index}]")?,
+                Step::Binding(index) => write!(f, ".form[{index}]")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The context of one node of a decode: the [`DecodeOptions`] of the whole decode, the node's
+/// [`Location`], and whether an error raised within it has already been located.
+///
+/// Every node of a decode shares the same `located` flag, so an error is located once, by the
+/// innermost node it is raised within, and passes through the nodes around that one unchanged.
+#[derive(Clone, Copy)]
+pub(crate) struct Frame<'a> {
+    options: DecodeOptions,
+    location: Option<&'a Location<'a>>,
+    located: &'a AtomicBool,
+}
+
+impl<'a> Frame<'a> {
+    fn root(options: DecodeOptions, located: &'a AtomicBool) -> Self {
+        Self {
+            options,
+            location: None,
+            located,
+        }
+    }
+
+    /// The location of the child `step` of this node, to decode the child [`Frame::within`].
+    pub(crate) fn child<'b>(self, step: Step<'b>) -> Location<'b>
+    where
+        'a: 'b,
+    {
+        Location {
+            parent: self.location,
+            step,
+        }
+    }
+
+    /// The frame of the child of this node at `location`.
+    pub(crate) fn within<'b>(self, location: &'b Location<'b>) -> Frame<'b>
+    where
+        'a: 'b,
+    {
+        Frame {
+            options: self.options,
+            location: Some(location),
+            located: self.located,
+        }
+    }
+
+    /// Report that `err` was raised within this node, so that it reads like
+    /// `at form[3].params.x: invalid type: ...`. Since [`de::Error`] can only be constructed from
+    /// a message, a located error is a [`de::Error::custom`] error.
+    fn locate<E: de::Error>(self, err: E) -> E {
+        match self.location {
+            Some(location) if !self.located.swap(true, Ordering::Relaxed) => {
+                de::Error::custom(format!("at {location}: {err}"))
+            }
+            _ => err,
+        }
+    }
+
+    /// Check that this decode's [`ControlFlowPolicy`] allows a ref keyed by `path`, before its
+    /// arguments are decoded.
+    pub(crate) fn check_control_flow<E: de::Error>(self, path: &PathBuf) -> Result<(), E> {
+        if self.options.control_flow == ControlFlowPolicy::Allow {
+            return Ok(());
+        }
+
+        let control_flow = [TCREF_IF, TCREF_COND, TCREF_WHILE, TCREF_FOR_EACH];
+        if control_flow
+            .into_iter()
+            .any(|label| *path == PathBuf::from(label))
+        {
+            Err(de::Error::custom(format!(
+                "control-flow ref {path} is not supported by this host"
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check that this decode's [`UnknownClassPolicy`] allows an op ref to `subject`.
+    pub(crate) fn check_subject<E: de::Error>(self, subject: &Subject) -> Result<(), E> {
+        match subject {
+            Subject::Link(link)
+                if self.options.unknown_class == UnknownClassPolicy::Reject && !is_known(link) =>
+            {
+                Err(de::Error::custom(format!(
+                    "unknown scalar class or op {link} (is this a typo for a typed value key?)"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Decode a value within a [`Frame`] of an enclosing decode, so that an error names where in the
+/// enclosing value it was raised.
+pub(crate) trait FromStreamAt: Send + Sized {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send;
+}
+
+/// Decode a `T` as the root of a new decode with the given `options`, e.g. in its
+/// [`de::FromStream`] impl.
+pub(crate) async fn decode_root<T: FromStreamAt, D: de::Decoder>(
+    options: DecodeOptions,
+    decoder: &mut D,
+) -> Result<T, D::Error> {
+    let located = AtomicBool::new(false);
+    T::from_stream_at(Frame::root(options, &located), decoder).await
+}
+
+/// A `T` decoded within a [`Frame`], which locates any error raised within it.
+pub(crate) struct At<'a, T>(pub(crate) T, pub(crate) PhantomData<Frame<'a>>);
+
+impl<'a, T: FromStreamAt> de::FromStream for At<'a, T> {
+    type Context = Frame<'a>;
+
+    async fn from_stream<D: de::Decoder>(
+        frame: Self::Context,
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        match T::from_stream_at(frame, decoder).await {
+            Ok(value) => Ok(At(value, PhantomData)),
+            Err(err) => Err(frame.locate(err)),
+        }
+    }
+}
+
+macro_rules! from_stream_at_leaf {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl FromStreamAt for $ty {
+                fn from_stream_at<D: de::Decoder>(
+                    _frame: Frame<'_>,
+                    decoder: &mut D,
+                ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+                    <$ty as de::FromStream>::from_stream((), decoder)
+                }
+            }
+        )*
+    };
+}
+
+from_stream_at_leaf!(Id, String, Subject);
+
+/// Decodes a sequence of `T`s, all within the frame of the sequence.
+struct VecVisitor<'a, T> {
+    frame: Frame<'a>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: FromStreamAt> de::Visitor for VecVisitor<'_, T> {
+    type Value = Vec<T>;
+
+    fn expecting() -> &'static str {
+        "a sequence"
+    }
+
+    async fn visit_seq<A: de::SeqAccess>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(At(item, _)) = seq.next_element::<At<T>>(self.frame).await? {
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+}
+
+impl<T: FromStreamAt> FromStreamAt for Vec<T> {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        let visitor = VecVisitor {
+            frame,
+            marker: PhantomData,
+        };
+
+        decoder.decode_seq(visitor)
+    }
+}
+
+/// Decodes a map of `T`s, each within the frame of its key.
+struct MapVisitor<'a, K, T> {
+    frame: Frame<'a>,
+    marker: PhantomData<fn() -> (K, T)>,
+}
+
+impl<K, T> de::Visitor for MapVisitor<'_, K, T>
+where
+    K: FromStreamAt + Ord + Borrow<str>,
+    T: FromStreamAt,
+{
+    type Value = BTreeMap<K, T>;
+
+    fn expecting() -> &'static str {
+        "a map"
+    }
+
+    async fn visit_map<A: de::MapAccess>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut items = BTreeMap::new();
+
+        while let Some(At(key, _)) = map.next_key::<At<K>>(self.frame).await? {
+            let at = self.frame.child(Step::Key(key.borrow()));
+            let At(value, _) = map.next_value::<At<T>>(self.frame.within(&at)).await?;
+            items.insert(key, value);
+        }
+
+        Ok(items)
+    }
+}
+
+impl<K, T> FromStreamAt for BTreeMap<K, T>
+where
+    K: FromStreamAt + Ord + Borrow<str>,
+    T: FromStreamAt,
+{
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        let visitor = MapVisitor {
+            frame,
+            marker: PhantomData,
+        };
+
+        decoder.decode_map(visitor)
+    }
+}
+
+/// Decodes a tuple whose items are all within the frame of the tuple.
+struct TupleVisitor<'a, T> {
+    frame: Frame<'a>,
+    marker: PhantomData<fn() -> T>,
+}
+
+macro_rules! from_stream_at_tuple {
+    ($($len:literal => ($($n:tt $name:ident)+))+) => {
+        $(
+            #[allow(non_snake_case)]
+            impl<$($name: FromStreamAt),+> de::Visitor for TupleVisitor<'_, ($($name,)+)> {
+                type Value = ($($name,)+);
+
+                fn expecting() -> &'static str {
+                    concat!("a tuple of size ", $len)
+                }
+
+                async fn visit_seq<A: de::SeqAccess>(
+                    self,
+                    mut seq: A,
+                ) -> Result<Self::Value, A::Error> {
+                    $(
+                        let $name = match seq.next_element::<At<$name>>(self.frame).await? {
+                            Some(At(value, _)) => value,
+                            None => return Err(de::Error::invalid_length($n, Self::expecting())),
+                        };
+                    )+
+
+                    Ok(($($name,)+))
+                }
+            }
+
+            impl<$($name: FromStreamAt),+> FromStreamAt for ($($name,)+) {
+                fn from_stream_at<D: de::Decoder>(
+                    frame: Frame<'_>,
+                    decoder: &mut D,
+                ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+                    let visitor = TupleVisitor::<Self> {
+                        frame,
+                        marker: PhantomData,
+                    };
+
+                    decoder.decode_tuple($len, visitor)
+                }
+            }
+        )+
+    };
+}
+
+from_stream_at_tuple! {
+    2 => (0 T0 1 T1)
+    3 => (0 T0 1 T1 2 T2)
+}
+
+fn is_known(link: &Link) -> bool {
+    let path = link.path();
+
+    if link.host().is_some()
+        || path.len() < SCALAR_PREFIX.len()
+        || path[..SCALAR_PREFIX.len()] != SCALAR_PREFIX[..]
+    {
+        return true;
+    }
+
+    let is_number_op = path.len() == NUMBER_OP_PREFIX.len() + 1
+        && path[..NUMBER_OP_PREFIX.len()] == NUMBER_OP_PREFIX[..]
+        && NUMBER_METHODS.contains(&path[NUMBER_OP_PREFIX.len()].as_str());
+
+    let reflect = [
+        SCALAR_REFLECT_CLASS,
+        SCALAR_REFLECT_REF_PARTS,
+        OPDEF_REFLECT_FORM,
+        OPDEF_REFLECT_LAST_ID,
+        OPDEF_REFLECT_SCALARS,
+    ];
+
+    is_number_op
+        || reflect
+            .into_iter()
+            .any(|reflect| *path == PathBuf::from(reflect))
+}
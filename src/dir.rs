@@ -1,25 +1,48 @@
-use std::{collections::BTreeMap, fmt, str::FromStr};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync::{Arc, PoisonError, RwLock},
+};
 
-use pathlink::{Path, PathSegment};
+use pathlink::{path_label, Link, Path, PathBuf, PathLabel, PathSegment};
 use tc_error::{TCError, TCResult};
 
-use crate::Route;
+use crate::{FormNode, FormWalk, OpDef, Route, Subject, TCRef};
+
+const RESERVED_PREFIX: PathLabel = path_label(&["state"]);
 
 /// Directory-style router inspired by TinyChain's transactional `Dir`.
-#[derive(Default)]
+///
+/// A new `Dir` refuses to mount anything under the runtime's reserved `/state` namespace; see
+/// [`Dir::with_reserved_prefixes`].
 pub struct Dir<H> {
     entries: BTreeMap<PathSegment, DirEntry<H>>,
+    reserved: Vec<Vec<PathSegment>>,
+    /// The segments mounted so far, if interning is enabled (only ever set on the root).
+    interned: Option<HashSet<PathSegment>>,
 }
 
 enum DirEntry<H> {
     Dir(Box<Dir<H>>),
     Handler(H),
+    /// An alternate name for the handler mounted at this absolute path.
+    Alias(Vec<PathSegment>),
+}
+
+impl<H> Default for Dir<H> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<H: Clone> Clone for Dir<H> {
     fn clone(&self) -> Self {
         Self {
             entries: self.entries.clone(),
+            reserved: self.reserved.clone(),
+            interned: self.interned.clone(),
         }
     }
 }
@@ -29,6 +52,7 @@ impl<H: Clone> Clone for DirEntry<H> {
         match self {
             Self::Dir(dir) => Self::Dir(Box::new((**dir).clone())),
             Self::Handler(handler) => Self::Handler(handler.clone()),
+            Self::Alias(target) => Self::Alias(target.clone()),
         }
     }
 }
@@ -44,6 +68,7 @@ impl<H: fmt::Debug> fmt::Debug for DirEntry<H> {
         match self {
             Self::Dir(_) => f.write_str("Dir(...)"),
             Self::Handler(handler) => f.debug_tuple("Handler").field(handler).finish(),
+            Self::Alias(target) => f.debug_tuple("Alias").field(&format_path(target)).finish(),
         }
     }
 }
@@ -52,6 +77,64 @@ impl<H> Dir<H> {
     pub fn new() -> Self {
         Self {
             entries: BTreeMap::new(),
+            reserved: vec![PathBuf::from(RESERVED_PREFIX).into_iter().collect()],
+            interned: None,
+        }
+    }
+
+    /// Share one allocation between every mounted segment with the same name, anywhere in the
+    /// tree (e.g. the `get` in `/users/get` and `/groups/get`), to save memory in large routers.
+    ///
+    /// This only affects paths mounted after it's called, and doesn't change how paths route.
+    pub fn with_interned_segments(mut self) -> Self {
+        self.interned.get_or_insert_with(HashSet::new);
+        self
+    }
+
+    fn intern<'p>(&mut self, path: &'p [PathSegment]) -> Cow<'p, [PathSegment]> {
+        let Some(interned) = &mut self.interned else {
+            return Cow::Borrowed(path);
+        };
+
+        let path = path
+            .iter()
+            .map(|segment| match interned.get(segment) {
+                Some(shared) => shared.clone(),
+                None => {
+                    interned.insert(segment.clone());
+                    segment.clone()
+                }
+            })
+            .collect();
+
+        Cow::Owned(path)
+    }
+
+    /// Replace the set of reserved path prefixes, under which nothing may be mounted.
+    ///
+    /// By default this is just `/state`, the root of every built-in TinyChain path (e.g.
+    /// `/state/scalar/...`). Pass an empty set to allow mounting anywhere.
+    pub fn with_reserved_prefixes<I>(mut self, prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = Vec<PathSegment>>,
+    {
+        self.reserved = prefixes.into_iter().collect();
+        self
+    }
+
+    /// The path prefixes under which nothing may be mounted in this directory.
+    pub fn reserved_prefixes(&self) -> &[Vec<PathSegment>] {
+        &self.reserved
+    }
+
+    fn check_reserved(&self, path: &[PathSegment]) -> TCResult<()> {
+        match self.reserved.iter().find(|prefix| path.starts_with(prefix)) {
+            Some(prefix) => Err(TCError::bad_request(format!(
+                "cannot mount at {}: the prefix {} is reserved",
+                format_path(path),
+                format_path(prefix)
+            ))),
+            None => Ok(()),
         }
     }
 
@@ -61,16 +144,86 @@ impl<H> Dir<H> {
         I: IntoIterator<Item = (Vec<PathSegment>, H)>,
     {
         let mut dir = Self::new();
-        for (path, handler) in routes {
-            if path.is_empty() {
-                return Err(TCError::bad_request("cannot mount handler at root"));
+        dir.try_extend(routes)?;
+        Ok(dir)
+    }
+
+    /// Mount `handler` at `path`, failing if the path is empty or already occupied.
+    pub fn insert(&mut self, path: &[PathSegment], handler: H) -> TCResult<()> {
+        if path.is_empty() {
+            return Err(TCError::bad_request("cannot mount handler at root"));
+        }
+
+        self.check_reserved(path)?;
+        let path = self.intern(path);
+        self.insert_segments(&path, DirEntry::Handler(handler), false)
+            .map(|_| ())
+    }
+
+    /// Mount `handler` at `path`, replacing (and returning) any handler already mounted there.
+    ///
+    /// This still fails if the path is empty, if a directory is mounted at `path`, or if `path`
+    /// passes through a leaf handler. If `path` is an alias (see [`Dir::alias`]), the alias is
+    /// replaced and `None` is returned, since the aliased handler is still mounted at its own path.
+    pub fn insert_or_replace(&mut self, path: &[PathSegment], handler: H) -> TCResult<Option<H>> {
+        if path.is_empty() {
+            return Err(TCError::bad_request("cannot mount handler at root"));
+        }
+
+        self.check_reserved(path)?;
+        let path = self.intern(path);
+        self.insert_segments(&path, DirEntry::Handler(handler), true)
+    }
+
+    /// Make `alias` route to the handler mounted at `existing`, without cloning it.
+    ///
+    /// The alias refers to the path of the handler, so replacing the handler at `existing` (e.g.
+    /// with [`Dir::insert_or_replace`]) is visible through the alias too. An alias of an alias
+    /// refers directly to the original handler. Fails if no handler is mounted at `existing` or if
+    /// `alias` is empty or already occupied.
+    pub fn alias(&mut self, existing: &[PathSegment], alias: &[PathSegment]) -> TCResult<()> {
+        if alias.is_empty() {
+            return Err(TCError::bad_request("cannot mount an alias at root"));
+        }
+
+        self.check_reserved(alias)?;
+
+        let target = match self.entry_at(existing) {
+            Some(DirEntry::Handler(_)) => self.intern(existing).into_owned(),
+            Some(DirEntry::Alias(target)) => target.clone(),
+            _ => {
+                return Err(TCError::not_found(format!(
+                    "no handler mounted at {} to alias",
+                    format_path(existing)
+                )))
             }
-            dir.insert_segments(&path, handler)?;
+        };
+
+        let alias = self.intern(alias);
+        self.insert_segments(&alias, DirEntry::Alias(target), false)
+            .map(|_| ())
+    }
+
+    /// Mount each `(path, handler)` entry, stopping at the first invalid mount.
+    ///
+    /// Entries mounted before the failing one remain in this directory.
+    pub fn try_extend<I>(&mut self, routes: I) -> TCResult<()>
+    where
+        I: IntoIterator<Item = (Vec<PathSegment>, H)>,
+    {
+        for (path, handler) in routes {
+            self.insert(&path, handler)?;
         }
-        Ok(dir)
+
+        Ok(())
     }
 
-    fn insert_segments(&mut self, path: &[PathSegment], handler: H) -> TCResult<()> {
+    fn insert_segments(
+        &mut self,
+        path: &[PathSegment],
+        leaf: DirEntry<H>,
+        replace: bool,
+    ) -> TCResult<Option<H>> {
         let (head, tail) = path
             .split_first()
             .expect("caller ensures path is non-empty");
@@ -80,24 +233,36 @@ impl<H> Dir<H> {
         if tail.is_empty() {
             match self.entries.entry(head.clone()) {
                 Entry::Vacant(entry) => {
-                    entry.insert(DirEntry::Handler(handler));
-                    Ok(())
+                    entry.insert(leaf);
+                    Ok(None)
                 }
-                Entry::Occupied(_) => Err(TCError::bad_request(format!(
-                    "handler already mounted at path {}",
-                    format_path(path)
-                ))),
+                Entry::Occupied(mut entry) => match entry.get() {
+                    DirEntry::Dir(_) => Err(TCError::bad_request(format!(
+                        "cannot mount handler over a directory at {}",
+                        format_path(path)
+                    ))),
+                    _ if replace => match entry.insert(leaf) {
+                        DirEntry::Handler(existing) => Ok(Some(existing)),
+                        _ => Ok(None),
+                    },
+                    _ => Err(TCError::bad_request(format!(
+                        "handler already mounted at path {}",
+                        format_path(path)
+                    ))),
+                },
             }
         } else {
             let entry = self.entries.entry(head.clone()).or_insert_with(|| {
                 DirEntry::Dir(Box::new(Dir {
                     entries: BTreeMap::new(),
+                    reserved: Vec::new(),
+                    interned: None,
                 }))
             });
 
             match entry {
-                DirEntry::Dir(dir) => dir.insert_segments(tail, handler),
-                DirEntry::Handler(_) => Err(TCError::bad_request(format!(
+                DirEntry::Dir(dir) => dir.insert_segments(tail, leaf, replace),
+                DirEntry::Handler(_) | DirEntry::Alias(_) => Err(TCError::bad_request(format!(
                     "cannot mount handler below a leaf handler at {}",
                     format_path(path)
                 ))),
@@ -105,21 +270,465 @@ impl<H> Dir<H> {
         }
     }
 
-    fn route_path<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a H> {
+    /// Iterate over every mounted path and the handler it routes to, including aliases.
+    ///
+    /// Paths are yielded in sorted order, segment by segment (so `/a/b` comes before `/b`), and
+    /// this order is part of the contract of `Dir`: it is stable across runs and builds, so it is
+    /// safe to depend on in e.g. golden files.
+    pub fn iter(&self) -> DirIter<'_, H> {
+        DirIter {
+            root: self,
+            stack: vec![(Vec::new(), self.entries.iter())],
+        }
+    }
+
+    /// Iterate over every mounted path, in the sorted order of [`Dir::iter`].
+    pub fn paths(&self) -> impl Iterator<Item = Vec<PathSegment>> + '_ {
+        self.iter().map(|(path, _)| path)
+    }
+
+    /// Collect every [`Link`] referenced by the op definitions of the handlers in this directory
+    /// (see [`OpDef::referenced_links`]), deduplicated and sorted.
+    ///
+    /// `op_def` returns the op definition of a handler, or `None` for a handler which has none
+    /// (e.g. a native Rust handler). Aliases are skipped, since they share a mounted handler.
+    pub fn referenced_links<'a, F>(&'a self, mut op_def: F) -> BTreeSet<Link>
+    where
+        F: FnMut(&'a H) -> Option<&'a OpDef>,
+    {
+        let mut links = BTreeSet::new();
+        let mut dirs = vec![self];
+
+        while let Some(dir) = dirs.pop() {
+            for entry in dir.entries.values() {
+                match entry {
+                    DirEntry::Dir(dir) => dirs.push(dir),
+                    DirEntry::Handler(handler) => {
+                        if let Some(op) = op_def(handler) {
+                            links.extend(op.referenced_links());
+                        }
+                    }
+                    DirEntry::Alias(_) => {}
+                }
+            }
+        }
+
+        links
+    }
+
+    fn entry_at(&self, path: &[PathSegment]) -> Option<&DirEntry<H>> {
+        let (head, tail) = path.split_first()?;
+        match self.entries.get(head)? {
+            entry if tail.is_empty() => Some(entry),
+            DirEntry::Dir(dir) => dir.entry_at(tail),
+            _ => None,
+        }
+    }
+
+    fn route_path<'a>(&'a self, root: &'a Self, path: &'a [PathSegment]) -> Option<&'a H> {
         let (head, tail) = path.split_first()?;
         match self.entries.get(head) {
             Some(DirEntry::Handler(handler)) if tail.is_empty() => Some(handler),
-            Some(DirEntry::Dir(dir)) => dir.route_path(tail),
+            Some(DirEntry::Alias(target)) if tail.is_empty() => root.route_path(root, target),
+            Some(DirEntry::Dir(dir)) => dir.route_path(root, tail),
             _ => None,
         }
     }
 }
 
+impl<H> Dir<H> {
+    /// List the paths added and removed by replacing this directory with `other`, e.g. to log
+    /// what changed when a library is hot-reloaded. Aliases count as paths.
+    ///
+    /// This doesn't compare handlers, so [`RouteDiff::changed`] is always empty; see
+    /// [`Dir::diff`].
+    pub fn diff_paths(&self, other: &Dir<H>) -> RouteDiff {
+        self.diff_by(other, |_, _| true)
+    }
+
+    fn diff_by<F>(&self, other: &Dir<H>, mut eq: F) -> RouteDiff
+    where
+        F: FnMut(&H, &H) -> bool,
+    {
+        let old: BTreeMap<_, _> = self.iter().collect();
+        let new: BTreeMap<_, _> = other.iter().collect();
+
+        let mut diff = RouteDiff::default();
+
+        for (path, handler) in &old {
+            match new.get(path) {
+                None => diff.removed.push(path.clone()),
+                Some(replacement) if !eq(handler, replacement) => diff.changed.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+
+        diff.added = new
+            .into_keys()
+            .filter(|path| !old.contains_key(path))
+            .collect();
+
+        diff
+    }
+}
+
+impl<H: PartialEq> Dir<H> {
+    /// Like [`Dir::diff_paths`], but also list the paths which route to a different handler in
+    /// `other`.
+    pub fn diff(&self, other: &Dir<H>) -> RouteDiff {
+        self.diff_by(other, |old, new| old == new)
+    }
+}
+
+/// The paths which differ between two [`Dir`]s, each in sorted order; see [`Dir::diff`].
+///
+/// The `Display` implementation lists one path per line, prefixed by `+` if it was added, `-` if
+/// it was removed, or `~` if it changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteDiff {
+    pub added: Vec<Vec<PathSegment>>,
+    pub removed: Vec<Vec<PathSegment>>,
+    pub changed: Vec<Vec<PathSegment>>,
+}
+
+impl RouteDiff {
+    /// Return `true` if the two directories have the same routes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for RouteDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = [
+            ("+", &self.added),
+            ("-", &self.removed),
+            ("~", &self.changed),
+        ]
+        .into_iter()
+        .flat_map(|(prefix, paths)| paths.iter().map(move |path| (prefix, path)));
+
+        for (i, (prefix, path)) in lines.enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+
+            write!(f, "{prefix} {}", format_path(path))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<H> Dir<H> {
+    /// Explain how `path` routes in this directory, e.g. to turn a "not found" error into one
+    /// which names the segment that didn't match and what's mounted there instead.
+    pub fn explain_route(&self, path: &[PathSegment]) -> RouteExplanation {
+        let mut dir = self;
+
+        for (i, segment) in path.iter().enumerate() {
+            match dir.entries.get(segment) {
+                Some(DirEntry::Dir(child)) => dir = child,
+                Some(DirEntry::Handler(_) | DirEntry::Alias(_)) => {
+                    return RouteExplanation {
+                        matched: path[..=i].to_vec(),
+                        node: RouteNode::Handler,
+                        unmatched: path.get(i + 1).cloned(),
+                        siblings: Vec::new(),
+                    }
+                }
+                None => {
+                    return RouteExplanation {
+                        matched: path[..i].to_vec(),
+                        node: RouteNode::Directory,
+                        unmatched: Some(segment.clone()),
+                        siblings: dir.entries.keys().cloned().collect(),
+                    }
+                }
+            }
+        }
+
+        RouteExplanation {
+            matched: path.to_vec(),
+            node: RouteNode::Directory,
+            unmatched: None,
+            siblings: dir.entries.keys().cloned().collect(),
+        }
+    }
+}
+
+/// The kind of node at the end of the matched part of a path; see [`RouteExplanation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteNode {
+    /// A directory (the root, if nothing matched).
+    Directory,
+    /// A handler or an alias of one.
+    Handler,
+}
+
+/// How a path routes in a [`Dir`], as returned by [`Dir::explain_route`].
+///
+/// The `Display` implementation is a one-line explanation suitable for an error message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteExplanation {
+    /// The longest prefix of the path which is mounted.
+    pub matched: Vec<PathSegment>,
+    /// What the matched prefix leads to.
+    pub node: RouteNode,
+    /// The first segment after the matched prefix, if any.
+    pub unmatched: Option<PathSegment>,
+    /// The segments mounted in the directory at the end of the matched prefix, in sorted order,
+    /// i.e. the valid alternatives to `unmatched`. This is empty if `node` is a handler.
+    pub siblings: Vec<PathSegment>,
+}
+
+impl RouteExplanation {
+    /// Return `true` if the whole path routes to a handler.
+    pub fn is_routed(&self) -> bool {
+        self.node == RouteNode::Handler && self.unmatched.is_none()
+    }
+}
+
+impl fmt::Display for RouteExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let matched = format_path(&self.matched);
+
+        match (&self.node, &self.unmatched) {
+            (RouteNode::Handler, None) => write!(f, "{matched} routes to a handler"),
+            (RouteNode::Handler, Some(segment)) => {
+                write!(
+                    f,
+                    "{matched} is a handler, with nothing mounted below it at {segment}"
+                )
+            }
+            (RouteNode::Directory, unmatched) => {
+                match unmatched {
+                    Some(segment) => write!(f, "nothing is mounted at {segment} in {matched}")?,
+                    None => write!(f, "{matched} is a directory, not a handler")?,
+                }
+
+                if self.siblings.is_empty() {
+                    f.write_str(", which is empty")
+                } else {
+                    let siblings = self.siblings.iter().map(PathSegment::as_str);
+                    write!(
+                        f,
+                        " (expected one of {})",
+                        siblings.collect::<Vec<_>>().join(", ")
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the paths and handlers of a [`Dir`], in sorted order; see [`Dir::iter`].
+pub struct DirIter<'a, H> {
+    root: &'a Dir<H>,
+    stack: Vec<(Vec<PathSegment>, Entries<'a, H>)>,
+}
+
+type Entries<'a, H> = std::collections::btree_map::Iter<'a, PathSegment, DirEntry<H>>;
+
+impl<'a, H> Iterator for DirIter<'a, H> {
+    type Item = (Vec<PathSegment>, &'a H);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (prefix, entries) = self.stack.last_mut()?;
+
+            let Some((segment, entry)) = entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+
+            let mut path = Vec::with_capacity(prefix.len() + 1);
+            path.extend_from_slice(prefix);
+            path.push(segment.clone());
+
+            match entry {
+                DirEntry::Dir(dir) => self.stack.push((path, dir.entries.iter())),
+                DirEntry::Handler(handler) => return Some((path, handler)),
+                DirEntry::Alias(target) => {
+                    if let Some(handler) = self.root.route_path(self.root, target) {
+                        return Some((path, handler));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Collect routes into a [`Dir`].
+///
+/// # Panics
+///
+/// Panics if a route is mounted at the root, on top of another handler, or below a leaf handler.
+/// Use [`Dir::from_routes`] to handle these cases as errors instead.
+impl<H> FromIterator<(Vec<PathSegment>, H)> for Dir<H> {
+    fn from_iter<I: IntoIterator<Item = (Vec<PathSegment>, H)>>(iter: I) -> Self {
+        let mut dir = Self::new();
+        dir.extend(iter);
+        dir
+    }
+}
+
+/// Mount additional routes in a [`Dir`].
+///
+/// # Panics
+///
+/// Panics on an invalid mount, like [`FromIterator`]. Use [`Dir::try_extend`] to handle these
+/// cases as errors instead.
+impl<H> Extend<(Vec<PathSegment>, H)> for Dir<H> {
+    fn extend<I: IntoIterator<Item = (Vec<PathSegment>, H)>>(&mut self, iter: I) {
+        if let Err(cause) = self.try_extend(iter) {
+            panic!("{cause}");
+        }
+    }
+}
+
 impl<H> Route for Dir<H> {
     type Handler = H;
 
     fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a Self::Handler> {
-        self.route_path(path)
+        self.route_path(self, path)
+    }
+
+    fn is_directory(&self, path: &[PathSegment]) -> bool {
+        path.is_empty() || matches!(self.entry_at(path), Some(DirEntry::Dir(_)))
+    }
+}
+
+impl<H> Dir<H> {
+    /// Freeze this `Dir` into a [`FrozenDir`], which can't be changed but routes a full path with
+    /// a single hash lookup instead of one map lookup per segment.
+    pub fn freeze(self) -> FrozenDir<H> {
+        let mut handlers = Vec::new();
+        let mut routes = HashMap::new();
+        let mut aliases = Vec::new();
+        let mut dirs = HashSet::from([Vec::new()]);
+        let mut stack = vec![(Vec::new(), self.entries)];
+
+        while let Some((prefix, entries)) = stack.pop() {
+            for (segment, entry) in entries {
+                let mut path = Vec::with_capacity(prefix.len() + 1);
+                path.extend_from_slice(&prefix);
+                path.push(segment);
+
+                match entry {
+                    DirEntry::Dir(dir) => {
+                        dirs.insert(path.clone());
+                        stack.push((path, dir.entries));
+                    }
+                    DirEntry::Handler(handler) => {
+                        routes.insert(path, handlers.len());
+                        handlers.push(handler);
+                    }
+                    DirEntry::Alias(target) => aliases.push((path, target)),
+                }
+            }
+        }
+
+        // an alias always refers to the path of a handler, never to another alias
+        for (alias, target) in aliases {
+            if let Some(&index) = routes.get(&target) {
+                routes.insert(alias, index);
+            }
+        }
+
+        FrozenDir {
+            handlers,
+            routes,
+            dirs,
+        }
+    }
+}
+
+/// An immutable [`Dir`] with every route precomputed, for a router which never changes after
+/// it's built; see [`Dir::freeze`].
+///
+/// Each handler is stored once, so an alias shares its handler just like it does in a `Dir`.
+#[derive(Clone, Debug)]
+pub struct FrozenDir<H> {
+    handlers: Vec<H>,
+    routes: HashMap<Vec<PathSegment>, usize>,
+    dirs: HashSet<Vec<PathSegment>>,
+}
+
+impl<H> FrozenDir<H> {
+    /// The number of routable paths, including aliases.
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl<H> Route for FrozenDir<H> {
+    type Handler = H;
+
+    fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a Self::Handler> {
+        self.routes.get(path).map(|&index| &self.handlers[index])
+    }
+
+    fn is_directory(&self, path: &[PathSegment]) -> bool {
+        self.dirs.contains(path)
+    }
+}
+
+impl<H: Clone> Dir<H> {
+    /// Take an immutable snapshot of this `Dir`, e.g. to publish it through a [`SharedDir`].
+    ///
+    /// This clones every handler, so it's cheap when the handlers are themselves `Arc`s.
+    pub fn snapshot(&self) -> Arc<Dir<H>> {
+        Arc::new(self.clone())
+    }
+}
+
+/// A [`Dir`] which can be replaced while it's in use, e.g. when a server reloads its libraries.
+///
+/// Readers take a [`SharedDir::snapshot`] and route against it for as long as they need a
+/// consistent view; a [`SharedDir::reload`] only affects snapshots taken after it returns. The
+/// lock is held just long enough to clone or swap an `Arc`, never while routing or while the new
+/// `Dir` is being built.
+pub struct SharedDir<H> {
+    current: RwLock<Arc<Dir<H>>>,
+}
+
+impl<H> SharedDir<H> {
+    pub fn new(dir: Dir<H>) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(dir)),
+        }
+    }
+
+    /// The `Dir` as of now, unaffected by any later [`SharedDir::reload`].
+    pub fn snapshot(&self) -> Arc<Dir<H>> {
+        // the lock only guards an `Arc` swap, which can't leave it in an inconsistent state
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&current)
+    }
+
+    /// Route `path` against the current `Dir`, returning a clone of its handler.
+    pub fn route(&self, path: &[PathSegment]) -> Option<H>
+    where
+        H: Clone,
+    {
+        self.snapshot().route(path).cloned()
+    }
+
+    /// Replace the current `Dir` with `dir`, returning the previous one.
+    pub fn reload(&self, dir: impl Into<Arc<Dir<H>>>) -> Arc<Dir<H>> {
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        std::mem::replace(&mut *current, dir.into())
+    }
+}
+
+impl<H> From<Dir<H>> for SharedDir<H> {
+    fn from(dir: Dir<H>) -> Self {
+        Self::new(dir)
     }
 }
 
@@ -129,6 +738,21 @@ fn format_path(path: &[PathSegment]) -> String {
 
 /// Parse a `/foo/bar`-style path into [`PathSegment`]s for use with a [`Dir`].
 pub fn parse_route_path(path: &str) -> TCResult<Vec<PathSegment>> {
+    route_segments(path)?.collect()
+}
+
+/// Check that `path` is a well-formed route path, applying the same rules as
+/// [`parse_route_path`] without collecting its segments.
+pub fn validate_route_path(path: &str) -> TCResult<()> {
+    route_segments(path)?.try_for_each(|segment| segment.map(|_| ()))
+}
+
+/// Return `true` if `path` is a well-formed route path (see [`validate_route_path`]).
+pub fn is_valid_route_path(path: &str) -> bool {
+    validate_route_path(path).is_ok()
+}
+
+fn route_segments(path: &str) -> TCResult<impl Iterator<Item = TCResult<PathSegment>> + '_> {
     if path.is_empty() {
         return Err(TCError::bad_request("route paths must not be empty"));
     }
@@ -141,21 +765,39 @@ pub fn parse_route_path(path: &str) -> TCResult<Vec<PathSegment>> {
         ));
     }
 
-    trimmed
-        .split('/')
-        .map(|segment| {
-            PathSegment::from_str(segment).map_err(|cause| {
-                TCError::bad_request(format!("invalid route segment '{segment}': {cause}"))
-            })
+    Ok(trimmed.split('/').map(|segment| {
+        PathSegment::from_str(segment).map_err(|cause| {
+            TCError::bad_request(format!("invalid route segment '{segment}': {cause}"))
         })
-        .collect()
+    }))
+}
+
+/// Check that every [`Subject::Link`] referenced by `op` resolves to a handler mounted in `dir`.
+///
+/// Only the path of each link is routed, so links are expected to be local to `dir`. Ref subjects
+/// (e.g. `$self/foo`) are skipped since they can only be resolved at runtime. Returns a "not found"
+/// error naming the first dangling link.
+pub fn check_references<H>(op: &OpDef, dir: &Dir<H>) -> TCResult<()> {
+    for (node, _) in FormWalk::new(op) {
+        if let FormNode::Ref(TCRef::Op(op_ref)) = node {
+            if let Subject::Link(link) = op_ref.subject() {
+                if dir.route(link.path()).is_none() {
+                    return Err(TCError::not_found(format!(
+                        "no handler mounted for referenced link {link}"
+                    )));
+                }
+            }
+        }
+    }
+
+    Ok(())
 }
 
 /// Build a [`Dir`] from string routes with minimal boilerplate.
 #[macro_export]
 macro_rules! tc_library_routes {
     ($($path:expr => $handler:expr),+ $(,)?) => {{
-        (|| -> tc_error::TCResult<_> {
+        (|| -> $crate::__tc_error::TCResult<_> {
             let routes = vec![
                 $(
                     ($crate::parse_route_path($path)?, $handler)
@@ -0,0 +1,181 @@
+//! The interface a kernel implements to execute op definitions, and the canonical driver loop.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use tc_error::{TCError, TCResult};
+use tc_value::Value;
+
+use crate::{Id, Map, OpArgs, OpDef, OpRef, Scalar, Subject, TCRef, Transaction};
+
+/// Resolves the op refs of a form on behalf of [`evaluate_op_def`].
+///
+/// This crate does not execute ops itself: a runtime implements `Evaluator` to route each op to
+/// its handler, and [`evaluate_op_def`] takes care of the rest of the evaluation order so that it
+/// stays consistent across runtimes.
+#[async_trait]
+pub trait Evaluator: Send + Sync {
+    /// Resolve a GET (one argument), PUT (two arguments), or POST (named arguments) op.
+    ///
+    /// The arguments have already been evaluated, so they contain no refs to other bindings.
+    async fn resolve(
+        &self,
+        txn: &dyn Transaction,
+        subject: &Subject,
+        args: &OpArgs,
+    ) -> TCResult<Scalar>;
+
+    /// Resolve a DELETE op, which can't be distinguished from a GET by its [`OpArgs`].
+    ///
+    /// The default implementation rejects every DELETE.
+    async fn resolve_delete(
+        &self,
+        txn: &dyn Transaction,
+        subject: &Subject,
+        key: &Scalar,
+    ) -> TCResult<Scalar> {
+        let _ = (txn, key);
+        Err(TCError::bad_request(format!(
+            "this evaluator does not support DELETE {subject}"
+        )))
+    }
+}
+
+/// Evaluate the form of `op` in order and return the value of its last binding.
+///
+/// `params` is the initial scope, i.e. the arguments the op was called with, keyed by the names
+/// the op declares for them (e.g. `key` for `{"/state/scalar/op/get": ["key", [...]]}`, or each
+/// named argument of a POST op). Each binding is evaluated in the scope of `params` and the
+/// bindings before it, and may shadow a parameter: an `$id` ref is replaced by the value it's bound
+/// to, maps and tuples are evaluated element-wise, and each op ref is passed to `evaluator` once
+/// its arguments are evaluated. Nested op definitions are values and are not evaluated.
+///
+/// A `$id/path` subject whose id is in scope must be bound to a link, and is resolved to that link
+/// (plus the path); a subject whose id is not in scope, like `$self`, is passed to the evaluator
+/// unchanged. A ref to an id which is not in scope is an error, as is a control-flow ref
+/// (conditionals and loops are left to the runtime). An empty form evaluates to [`Scalar::none`].
+pub async fn evaluate_op_def<E>(
+    evaluator: &E,
+    txn: &dyn Transaction,
+    op: &OpDef,
+    params: Map<Scalar>,
+) -> TCResult<Scalar>
+where
+    E: Evaluator + ?Sized,
+{
+    let mut scope = params.into_iter().collect::<Scope>();
+    scope.reserve(op.form().len());
+    let mut last = Scalar::none();
+
+    for (id, scalar) in op.form() {
+        last = evaluate(evaluator, txn, &scope, scalar).await?;
+        scope.insert(id.clone(), last.clone());
+    }
+
+    Ok(last)
+}
+
+type Scope = HashMap<Id, Scalar>;
+
+fn evaluate<'a, E>(
+    evaluator: &'a E,
+    txn: &'a dyn Transaction,
+    scope: &'a Scope,
+    scalar: &'a Scalar,
+) -> BoxFuture<'a, TCResult<Scalar>>
+where
+    E: Evaluator + ?Sized,
+{
+    Box::pin(async move {
+        match scalar {
+            Scalar::Ref(tc_ref) => match &**tc_ref {
+                TCRef::Id(id_ref) => lookup(scope, id_ref.id()).cloned(),
+                TCRef::Op(op_ref) => resolve(evaluator, txn, scope, op_ref).await,
+                other => Err(TCError::bad_request(format!(
+                    "cannot evaluate the control-flow ref {other:?}"
+                ))),
+            },
+            Scalar::Map(map) => {
+                let mut evaluated = Map::new();
+
+                for (key, item) in map.iter() {
+                    let item = evaluate(evaluator, txn, scope, item).await?;
+                    evaluated.insert(key.clone(), item);
+                }
+
+                Ok(Scalar::Map(evaluated))
+            }
+            Scalar::Tuple(items) => {
+                let mut evaluated = Vec::with_capacity(items.len());
+
+                for item in items {
+                    evaluated.push(evaluate(evaluator, txn, scope, item).await?);
+                }
+
+                Ok(Scalar::Tuple(evaluated))
+            }
+            other => Ok(other.clone()),
+        }
+    })
+}
+
+async fn resolve<E>(
+    evaluator: &E,
+    txn: &dyn Transaction,
+    scope: &Scope,
+    op_ref: &OpRef,
+) -> TCResult<Scalar>
+where
+    E: Evaluator + ?Sized,
+{
+    let subject = resolve_subject(scope, op_ref.subject())?;
+
+    let args = match op_ref {
+        OpRef::Get((_, key)) => OpArgs::Seq(vec![evaluate(evaluator, txn, scope, key).await?]),
+        OpRef::Put((_, key, value)) => OpArgs::Seq(vec![
+            evaluate(evaluator, txn, scope, key).await?,
+            evaluate(evaluator, txn, scope, value).await?,
+        ]),
+        OpRef::Post((_, params)) => {
+            let mut args = Map::new();
+
+            for (name, param) in params.iter() {
+                let param = evaluate(evaluator, txn, scope, param).await?;
+                args.insert(name.clone(), param);
+            }
+
+            OpArgs::Map(args)
+        }
+        OpRef::Delete((_, key)) => {
+            let key = evaluate(evaluator, txn, scope, key).await?;
+            return evaluator.resolve_delete(txn, &subject, &key).await;
+        }
+    };
+
+    evaluator.resolve(txn, &subject, &args).await
+}
+
+fn resolve_subject(scope: &Scope, subject: &Subject) -> TCResult<Subject> {
+    match subject {
+        Subject::Ref(id_ref, path) => match scope.get(id_ref.id()) {
+            Some(Scalar::Value(Value::Link(link))) => Ok(Subject::Link(
+                path.iter()
+                    .cloned()
+                    .fold(link.clone(), |link, segment| link.append(segment)),
+            )),
+            Some(other) => Err(TCError::bad_request(format!(
+                "cannot call {subject} since ${} is bound to {other:?}, not a link",
+                id_ref.id()
+            ))),
+            None => Ok(subject.clone()),
+        },
+        Subject::Link(_) => Ok(subject.clone()),
+    }
+}
+
+fn lookup<'a>(scope: &'a Scope, id: &Id) -> TCResult<&'a Scalar> {
+    scope.get(id).ok_or_else(|| {
+        TCError::not_found(format!("${id} (not bound before this point in the form)"))
+    })
+}
@@ -0,0 +1,680 @@
+//! Canonical hashing of IR values.
+//!
+//! The canonical encoding is a compact, self-delimiting byte sequence which is independent of the
+//! wire format: every node is written as a one-byte tag followed by its contents, integers are
+//! big-endian, and floats are written by bit pattern. It is fed to a [`Hasher`] incrementally, so
+//! hashing a large op catalog never buffers its whole encoding.
+//!
+//! Numbers are normalized first, so that numbers which are equal, or which a text encoding such
+//! as JSON cannot tell apart, encode the same and a fingerprint survives a round trip through the
+//! wire format:
+//!
+//! - an integer of any width, or a float with no fractional part within the range of a `u64` or
+//!   `i64`, is written as an unsigned integer if it is non-negative and a signed one otherwise
+//!   (so `1u8`, `1i64` and `1.0` are the same, as are `0.0` and `-0.0`)
+//! - every NaN is written as the same NaN
+//! - an `f32` is written as the `f64` nearest its shortest decimal form (e.g. `0.1f32` as
+//!   `0.1f64`), which is what a text encoding decodes it as
+//!
+//! Booleans are not numbers here: `true` and `1` encode differently.
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use bytes::Bytes;
+use destream::en::{self, IntoStream};
+use futures::stream::{self, Stream};
+use uuid::Uuid;
+
+use crate::{Id, OpDef, OpRef, Scalar, Subject, TCRef};
+
+const TAG_NONE: u8 = 0x00;
+const TAG_BOOL: u8 = 0x01;
+const TAG_INT: u8 = 0x02;
+const TAG_UINT: u8 = 0x03;
+const TAG_FLOAT: u8 = 0x04;
+const TAG_STR: u8 = 0x05;
+const TAG_BYTES: u8 = 0x06;
+const TAG_UUID: u8 = 0x07;
+const TAG_MAP: u8 = 0x08;
+const TAG_SEQ: u8 = 0x09;
+const TAG_END: u8 = 0x0a;
+const TAG_UNENCODABLE: u8 = 0x0b;
+
+const TAG_SCALAR_MAP: u8 = 0x10;
+const TAG_SCALAR_TUPLE: u8 = 0x11;
+const TAG_SUBJECT_LINK: u8 = 0x12;
+const TAG_SUBJECT_REF: u8 = 0x13;
+
+const TAG_REF_ID: u8 = 0x20;
+const TAG_REF_COND: u8 = 0x21;
+const TAG_REF_WHILE: u8 = 0x22;
+const TAG_REF_FOR_EACH: u8 = 0x23;
+
+const TAG_OPREF_GET: u8 = 0x30;
+const TAG_OPREF_PUT: u8 = 0x31;
+const TAG_OPREF_POST: u8 = 0x32;
+const TAG_OPREF_DELETE: u8 = 0x33;
+
+const TAG_OPDEF_GET: u8 = 0x40;
+const TAG_OPDEF_PUT: u8 = 0x41;
+const TAG_OPDEF_POST: u8 = 0x42;
+const TAG_OPDEF_DELETE: u8 = 0x43;
+
+/// A 64-bit FNV-1a [`Hasher`], used to compute stable fingerprints.
+///
+/// Unlike `std`'s default hasher, its output does not depend on the Rust release or on a random
+/// seed, so fingerprints can be persisted and compared across processes. It is not a
+/// cryptographic hash.
+#[derive(Clone, Copy, Debug)]
+pub struct FingerprintHasher {
+    state: u64,
+}
+
+impl FingerprintHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub const fn new() -> Self {
+        Self {
+            state: Self::OFFSET_BASIS,
+        }
+    }
+}
+
+impl Default for FingerprintHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for FingerprintHasher {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= u64::from(*byte);
+            self.state = self.state.wrapping_mul(Self::PRIME);
+        }
+    }
+}
+
+impl Scalar {
+    /// Feed the canonical encoding of this scalar into `hasher`.
+    ///
+    /// A leaf value which cannot be encoded (e.g. one which streams its contents) is hashed as a
+    /// marker followed by the encoder's error message.
+    pub fn hash_into<H: Hasher>(&self, hasher: &mut H) {
+        Canonical::new(&mut HasherSink(hasher)).scalar(self)
+    }
+
+    /// The canonical encoding of this scalar (see [`Scalar::hash_into`]).
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        Canonical::new(&mut bytes).scalar(self);
+        bytes
+    }
+
+    /// A stable 64-bit fingerprint of this scalar, computed with [`FingerprintHasher`].
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = FingerprintHasher::new();
+        self.hash_into(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl OpDef {
+    /// Feed the canonical encoding of this op definition into `hasher`.
+    pub fn hash_into<H: Hasher>(&self, hasher: &mut H) {
+        Canonical::new(&mut HasherSink(hasher)).op_def(self)
+    }
+
+    /// The canonical encoding of this op definition (see [`OpDef::hash_into`]).
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        Canonical::new(&mut bytes).op_def(self);
+        bytes
+    }
+
+    /// A stable 64-bit fingerprint of this op definition, computed with [`FingerprintHasher`].
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = FingerprintHasher::new();
+        self.hash_into(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// A [`Scalar`] which implements [`Eq`] and [`Hash`] by its canonical encoding (see
+/// [`Scalar::canonical_bytes`]), so it can key a `HashMap` or `HashSet`, e.g. to memoize an
+/// analysis pass.
+///
+/// Numbers are normalized before they are hashed, so `1`, `1u8` and `1.0` are the same key, as
+/// are `0.0` and `-0.0`. Other floats compare by bit pattern: unlike `Scalar`'s
+/// own `PartialEq`, a NaN equals every other NaN.
+#[derive(Clone, Debug)]
+pub struct HashableScalar(pub Scalar);
+
+impl HashableScalar {
+    /// Unwrap the scalar.
+    pub fn into_inner(self) -> Scalar {
+        self.0
+    }
+}
+
+impl From<Scalar> for HashableScalar {
+    fn from(scalar: Scalar) -> Self {
+        Self(scalar)
+    }
+}
+
+impl PartialEq for HashableScalar {
+    fn eq(&self, other: &Self) -> bool {
+        // hashing needs no buffer, and almost always tells two different scalars apart
+        if self.0.fingerprint() != other.0.fingerprint() {
+            return false;
+        }
+
+        let expected = self.0.canonical_bytes();
+        let mut sink = CompareSink::new(&expected);
+        Canonical::new(&mut sink).scalar(&other.0);
+        sink.matches()
+    }
+}
+
+impl Eq for HashableScalar {}
+
+impl Hash for HashableScalar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash_into(state)
+    }
+}
+
+trait Sink {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+impl Sink for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes)
+    }
+}
+
+/// A [`Sink`] which checks an encoding against an `expected` one as it is written, rather than
+/// buffering it.
+struct CompareSink<'a> {
+    rest: &'a [u8],
+    equal: bool,
+}
+
+impl<'a> CompareSink<'a> {
+    fn new(expected: &'a [u8]) -> Self {
+        Self {
+            rest: expected,
+            equal: true,
+        }
+    }
+
+    fn matches(&self) -> bool {
+        self.equal && self.rest.is_empty()
+    }
+}
+
+impl Sink for CompareSink<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        if !self.equal {
+            return;
+        }
+
+        match self.rest.strip_prefix(bytes) {
+            Some(rest) => self.rest = rest,
+            None => self.equal = false,
+        }
+    }
+}
+
+struct HasherSink<'a, H>(&'a mut H);
+
+impl<H: Hasher> Sink for HasherSink<'_, H> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.0.write(bytes)
+    }
+}
+
+/// Writes the canonical encoding of IR nodes by reference.
+struct Canonical<'s, S> {
+    sink: &'s mut S,
+}
+
+impl<'s, S: Sink> Canonical<'s, S> {
+    fn new(sink: &'s mut S) -> Self {
+        Self { sink }
+    }
+
+    fn tag(&mut self, tag: u8) {
+        self.sink.put(&[tag]);
+    }
+
+    fn len(&mut self, len: usize) {
+        self.sink.put(&(len as u64).to_be_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.tag(TAG_STR);
+        self.len(value.len());
+        self.sink.put(value.as_bytes());
+    }
+
+    fn id(&mut self, id: &Id) {
+        self.str(id.as_str());
+    }
+
+    fn scalar(&mut self, scalar: &Scalar) {
+        match scalar {
+            Scalar::Value(value) => {
+                if let Err(err) = value.clone().into_stream(Encoder::new(&mut *self.sink)) {
+                    self.tag(TAG_UNENCODABLE);
+                    self.str(&err.0);
+                }
+            }
+            Scalar::Ref(tc_ref) => self.tc_ref(tc_ref),
+            Scalar::Op(op_def) => self.op_def(op_def),
+            Scalar::Map(map) => {
+                self.tag(TAG_SCALAR_MAP);
+                self.len(map.len());
+                for (key, value) in map.iter() {
+                    self.id(key);
+                    self.scalar(value);
+                }
+            }
+            Scalar::Tuple(items) => {
+                self.tag(TAG_SCALAR_TUPLE);
+                self.items(items);
+            }
+            Scalar::Bytes(bytes) => {
+                self.tag(TAG_BYTES);
+                self.len(bytes.len());
+                self.sink.put(bytes);
+            }
+        }
+    }
+
+    fn items(&mut self, items: &[Scalar]) {
+        self.len(items.len());
+        for item in items {
+            self.scalar(item);
+        }
+    }
+
+    fn form(&mut self, form: &[(Id, Scalar)]) {
+        self.len(form.len());
+        for (id, scalar) in form {
+            self.id(id);
+            self.scalar(scalar);
+        }
+    }
+
+    fn subject(&mut self, subject: &Subject) {
+        match subject {
+            Subject::Link(link) => {
+                self.tag(TAG_SUBJECT_LINK);
+                self.str(&link.to_string());
+            }
+            Subject::Ref(id_ref, path) => {
+                self.tag(TAG_SUBJECT_REF);
+                self.id(id_ref.id());
+                self.str(&path.to_string());
+            }
+        }
+    }
+
+    fn tc_ref(&mut self, tc_ref: &TCRef) {
+        match tc_ref {
+            TCRef::Op(op_ref) => self.op_ref(op_ref),
+            TCRef::Id(id_ref) => {
+                self.tag(TAG_REF_ID);
+                self.id(id_ref.id());
+            }
+            TCRef::Cond(cond) => {
+                self.tag(TAG_REF_COND);
+                self.tc_ref(&cond.cond);
+                self.scalar(&cond.then);
+                self.scalar(&cond.or_else);
+            }
+            TCRef::While(while_ref) => {
+                self.tag(TAG_REF_WHILE);
+                self.scalar(&while_ref.cond);
+                self.scalar(&while_ref.closure);
+                self.scalar(&while_ref.state);
+            }
+            TCRef::ForEach(for_each) => {
+                self.tag(TAG_REF_FOR_EACH);
+                self.scalar(&for_each.items);
+                self.scalar(&for_each.op);
+                self.id(&for_each.item_name);
+            }
+        }
+    }
+
+    fn op_ref(&mut self, op_ref: &OpRef) {
+        match op_ref {
+            OpRef::Get((subject, key)) => {
+                self.tag(TAG_OPREF_GET);
+                self.subject(subject);
+                self.scalar(key);
+            }
+            OpRef::Put((subject, key, value)) => {
+                self.tag(TAG_OPREF_PUT);
+                self.subject(subject);
+                self.scalar(key);
+                self.scalar(value);
+            }
+            OpRef::Post((subject, params)) => {
+                self.tag(TAG_OPREF_POST);
+                self.subject(subject);
+                self.len(params.len());
+                for (key, value) in params.iter() {
+                    self.id(key);
+                    self.scalar(value);
+                }
+            }
+            OpRef::Delete((subject, key)) => {
+                self.tag(TAG_OPREF_DELETE);
+                self.subject(subject);
+                self.scalar(key);
+            }
+        }
+    }
+
+    fn op_def(&mut self, op_def: &OpDef) {
+        match op_def {
+            OpDef::Get((key_name, form)) => {
+                self.tag(TAG_OPDEF_GET);
+                self.id(key_name);
+                self.form(form);
+            }
+            OpDef::Put((key_name, value_name, form)) => {
+                self.tag(TAG_OPDEF_PUT);
+                self.id(key_name);
+                self.id(value_name);
+                self.form(form);
+            }
+            OpDef::Post(form) => {
+                self.tag(TAG_OPDEF_POST);
+                self.form(form);
+            }
+            OpDef::Delete((key_name, form)) => {
+                self.tag(TAG_OPDEF_DELETE);
+                self.id(key_name);
+                self.form(form);
+            }
+        }
+    }
+}
+
+/// The error returned when a leaf value cannot be written in the canonical encoding.
+#[derive(Debug)]
+struct Error(String);
+
+impl en::Error for Error {
+    fn custom<I: fmt::Display>(info: I) -> Self {
+        Self(info.to_string())
+    }
+}
+
+fn unsupported<T>(what: &str) -> Result<T, Error> {
+    Err(Error(format!(
+        "the canonical encoding does not support {what}"
+    )))
+}
+
+type Done = stream::Empty<()>;
+
+/// A [`destream`] encoder which writes leaf values in the canonical encoding.
+struct Encoder<'s, S> {
+    sink: &'s mut S,
+}
+
+impl<'s, S: Sink> Encoder<'s, S> {
+    fn new(sink: &'s mut S) -> Self {
+        Self { sink }
+    }
+
+    fn put(self, tag: u8, bytes: &[u8]) -> Result<Done, Error> {
+        self.sink.put(&[tag]);
+        self.sink.put(bytes);
+        Ok(stream::empty())
+    }
+
+    fn put_int(self, v: i64) -> Result<Done, Error> {
+        match u64::try_from(v) {
+            Ok(v) => self.put_uint(v),
+            Err(_) => self.put(TAG_INT, &v.to_be_bytes()),
+        }
+    }
+
+    fn put_uint(self, v: u64) -> Result<Done, Error> {
+        self.put(TAG_UINT, &v.to_be_bytes())
+    }
+
+    fn put_float(self, v: f64) -> Result<Done, Error> {
+        // 2^64 (the nearest float to `u64::MAX`) is the least integral float beyond a `u64`
+        const U64_END: f64 = 18_446_744_073_709_551_616.0;
+
+        if v.fract() == 0.0 && v >= i64::MIN as f64 && v < U64_END {
+            if v >= 0.0 {
+                self.put_uint(v as u64)
+            } else {
+                self.put_int(v as i64)
+            }
+        } else if v.is_nan() {
+            self.put(TAG_FLOAT, &f64::NAN.to_bits().to_be_bytes())
+        } else {
+            self.put(TAG_FLOAT, &v.to_bits().to_be_bytes())
+        }
+    }
+
+    fn put_sized(self, tag: u8, bytes: &[u8]) -> Result<Done, Error> {
+        self.sink.put(&[tag]);
+        self.sink.put(&(bytes.len() as u64).to_be_bytes());
+        self.sink.put(bytes);
+        Ok(stream::empty())
+    }
+}
+
+macro_rules! encode_widened {
+    ($($fn_name:ident($ty:ty) as $wide:ty => $put:ident),* $(,)?) => {
+        $(
+            fn $fn_name(self, v: $ty) -> Result<Self::Ok, Self::Error> {
+                self.$put(<$wide>::from(v))
+            }
+        )*
+    };
+}
+
+macro_rules! encode_array_unsupported {
+    ($($fn_name:ident($item:ty)),* $(,)?) => {
+        $(
+            fn $fn_name<T, A>(self, _chunks: A) -> Result<Self::Ok, Self::Error>
+            where
+                T: IntoIterator<Item = $item> + Send + Unpin + 'en,
+                A: Stream<Item = T> + Send + Unpin + 'en,
+                <T as IntoIterator>::IntoIter: Send + Unpin + 'en,
+            {
+                unsupported("array streams")
+            }
+        )*
+    };
+}
+
+impl<'en, 's, S: Sink> en::Encoder<'en> for Encoder<'s, S> {
+    type Ok = Done;
+    type Error = Error;
+    type EncodeMap = Nested<'s, S>;
+    type EncodeSeq = Nested<'s, S>;
+    type EncodeTuple = Nested<'s, S>;
+
+    fn encode_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        self.put(TAG_BOOL, &[u8::from(v)])
+    }
+
+    encode_widened! {
+        encode_i8(i8) as i64 => put_int,
+        encode_i16(i16) as i64 => put_int,
+        encode_i32(i32) as i64 => put_int,
+        encode_i64(i64) as i64 => put_int,
+        encode_u8(u8) as u64 => put_uint,
+        encode_u16(u16) as u64 => put_uint,
+        encode_u32(u32) as u64 => put_uint,
+        encode_u64(u64) as u64 => put_uint,
+    }
+
+    fn encode_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        // the shortest decimal form of a finite f32 always parses as an f64
+        let v = match v.to_string().parse::<f64>() {
+            Ok(wide) if v.is_finite() => wide,
+            _ => f64::from(v),
+        };
+
+        self.put_float(v)
+    }
+
+    fn encode_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        self.put_float(v)
+    }
+
+    encode_array_unsupported! {
+        encode_array_bool(bool),
+        encode_array_i8(i8),
+        encode_array_i16(i16),
+        encode_array_i32(i32),
+        encode_array_i64(i64),
+        encode_array_u8(u8),
+        encode_array_u16(u16),
+        encode_array_u32(u32),
+        encode_array_u64(u64),
+        encode_array_f32(f32),
+        encode_array_f64(f64),
+    }
+
+    fn encode_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        self.put_sized(TAG_STR, v.as_bytes())
+    }
+
+    fn encode_none(self) -> Result<Self::Ok, Self::Error> {
+        self.put(TAG_NONE, &[])
+    }
+
+    fn encode_some<T: IntoStream<'en> + 'en>(self, value: T) -> Result<Self::Ok, Self::Error> {
+        value.into_stream(self)
+    }
+
+    fn encode_unit(self) -> Result<Self::Ok, Self::Error> {
+        self.put(TAG_NONE, &[])
+    }
+
+    fn encode_bytes<B: Into<Bytes>>(self, bytes: B) -> Result<Self::Ok, Self::Error> {
+        self.put_sized(TAG_BYTES, &bytes.into())
+    }
+
+    fn encode_uuid(self, uuid: Uuid) -> Result<Self::Ok, Self::Error> {
+        self.put(TAG_UUID, uuid.as_bytes())
+    }
+
+    fn encode_map(self, _len: Option<usize>) -> Result<Self::EncodeMap, Self::Error> {
+        self.sink.put(&[TAG_MAP]);
+        Ok(Nested { sink: self.sink })
+    }
+
+    fn encode_map_stream<K, V, M>(self, _map: M) -> Result<Self::Ok, Self::Error>
+    where
+        K: IntoStream<'en> + 'en,
+        V: IntoStream<'en> + 'en,
+        M: Stream<Item = (K, V)> + Send + Unpin + 'en,
+    {
+        unsupported("map streams")
+    }
+
+    fn encode_seq(self, _len: Option<usize>) -> Result<Self::EncodeSeq, Self::Error> {
+        self.sink.put(&[TAG_SEQ]);
+        Ok(Nested { sink: self.sink })
+    }
+
+    fn encode_seq_stream<T, Q>(self, _seq: Q) -> Result<Self::Ok, Self::Error>
+    where
+        T: IntoStream<'en> + 'en,
+        Q: Stream<Item = T> + Send + Unpin + 'en,
+    {
+        unsupported("sequence streams")
+    }
+
+    fn encode_tuple(self, _len: usize) -> Result<Self::EncodeTuple, Self::Error> {
+        self.sink.put(&[TAG_SEQ]);
+        Ok(Nested { sink: self.sink })
+    }
+
+    fn collect_bytes<B: IntoIterator<Item = u8>>(self, bytes: B) -> Result<Self::Ok, Self::Error> {
+        let bytes = bytes.into_iter().collect::<Vec<u8>>();
+        self.put_sized(TAG_BYTES, &bytes)
+    }
+}
+
+/// The body of a map, sequence, or tuple, terminated by [`TAG_END`].
+struct Nested<'s, S> {
+    sink: &'s mut S,
+}
+
+impl<'s, S: Sink> Nested<'s, S> {
+    fn element<'en, T: IntoStream<'en> + 'en>(&mut self, value: T) -> Result<(), Error> {
+        value.into_stream(Encoder::new(&mut *self.sink)).map(|_| ())
+    }
+
+    fn finish(self) -> Result<Done, Error> {
+        self.sink.put(&[TAG_END]);
+        Ok(stream::empty())
+    }
+}
+
+impl<'en, 's, S: Sink> en::EncodeMap<'en> for Nested<'s, S> {
+    type Ok = Done;
+    type Error = Error;
+
+    fn encode_key<T: IntoStream<'en> + 'en>(&mut self, key: T) -> Result<(), Self::Error> {
+        self.element(key)
+    }
+
+    fn encode_value<T: IntoStream<'en> + 'en>(&mut self, value: T) -> Result<(), Self::Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'en, 's, S: Sink> en::EncodeSeq<'en> for Nested<'s, S> {
+    type Ok = Done;
+    type Error = Error;
+
+    fn encode_element<V: IntoStream<'en> + 'en>(&mut self, value: V) -> Result<(), Self::Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'en, 's, S: Sink> en::EncodeTuple<'en> for Nested<'s, S> {
+    type Ok = Done;
+    type Error = Error;
+
+    fn encode_element<V: IntoStream<'en> + 'en>(&mut self, value: V) -> Result<(), Self::Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
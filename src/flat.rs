@@ -0,0 +1,205 @@
+//! Conversion between a [`Scalar`] tree and a flat map of dotted paths, e.g. for a KV store.
+
+use std::collections::BTreeMap;
+
+use tc_error::{TCError, TCResult};
+use tc_value::Value;
+
+use crate::{Id, Map, Scalar};
+
+const SEPARATOR: char = '.';
+
+impl Scalar {
+    /// Flatten this tree of maps and tuples into a map from dotted paths (like `a.b.0`) to leaf
+    /// values, for storage in a flat key-value store. A bare value flattens to the empty path.
+    ///
+    /// Only data can be flattened: refs, ops, and byte strings are rejected rather than stored
+    /// as opaque leaves, since they have no [`Value`] representation. An empty map or tuple is
+    /// also rejected because it has no leaves to record, as is a map key which contains a `.` or
+    /// which is all digits (since [`Scalar::unflatten`] reads numeric keys as tuple indices).
+    pub fn flatten(&self) -> TCResult<BTreeMap<String, Value>> {
+        let mut flat = BTreeMap::new();
+        flatten_into(self, &mut String::new(), &mut flat)?;
+        Ok(flat)
+    }
+
+    /// Reconstruct a tree from the output of [`Scalar::flatten`].
+    ///
+    /// A collection whose keys are all numeric is a tuple, and must have exactly the indices
+    /// `0..n`; one whose keys are all non-numeric is a map. Mixing the two, or using a path as
+    /// both a leaf and a prefix (like `a` and `a.b`), is an error.
+    pub fn unflatten(flat: BTreeMap<String, Value>) -> TCResult<Scalar> {
+        if flat.is_empty() {
+            return Err(TCError::bad_request("cannot unflatten an empty map"));
+        }
+
+        let mut root = Node::Branch(BTreeMap::new());
+
+        for (path, value) in flat {
+            if path.is_empty() {
+                root = match root {
+                    Node::Branch(children) if children.is_empty() => Node::Leaf(value),
+                    _ => return Err(conflict("")),
+                };
+            } else {
+                root.insert(&path, value)?;
+            }
+        }
+
+        root.into_scalar()
+    }
+}
+
+fn flatten_into(
+    scalar: &Scalar,
+    path: &mut String,
+    flat: &mut BTreeMap<String, Value>,
+) -> TCResult<()> {
+    match scalar {
+        Scalar::Value(value) => {
+            flat.insert(path.clone(), value.clone());
+            Ok(())
+        }
+        Scalar::Map(map) if map.is_empty() => Err(empty(path, "map")),
+        Scalar::Tuple(items) if items.is_empty() => Err(empty(path, "tuple")),
+        Scalar::Map(map) => {
+            for (key, item) in map.iter() {
+                let key = key.as_str();
+
+                if key.contains(SEPARATOR) || is_index(key) {
+                    return Err(TCError::bad_request(format!(
+                        "cannot flatten the map key '{key}' (a flat key must not contain '.' or be all digits)"
+                    )));
+                }
+
+                descend(path, key, item, flat)?;
+            }
+
+            Ok(())
+        }
+        Scalar::Tuple(items) => items
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, item)| descend(path, &i.to_string(), item, flat)),
+        other => Err(TCError::unexpected(other, "a value, map, or tuple")),
+    }
+}
+
+fn descend(
+    path: &mut String,
+    segment: &str,
+    item: &Scalar,
+    flat: &mut BTreeMap<String, Value>,
+) -> TCResult<()> {
+    let len = path.len();
+
+    if !path.is_empty() {
+        path.push(SEPARATOR);
+    }
+
+    path.push_str(segment);
+    let result = flatten_into(item, path, flat);
+    path.truncate(len);
+    result
+}
+
+fn is_index(segment: &str) -> bool {
+    !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn empty(path: &str, kind: &str) -> TCError {
+    TCError::bad_request(format!("cannot flatten the empty {kind} at '{path}'"))
+}
+
+fn conflict(path: &str) -> TCError {
+    TCError::bad_request(format!("flat path '{path}' is both a leaf and a prefix"))
+}
+
+enum Node {
+    Leaf(Value),
+    Branch(BTreeMap<String, Node>),
+}
+
+impl Node {
+    fn insert(&mut self, path: &str, value: Value) -> TCResult<()> {
+        let mut node = self;
+
+        for segment in path.split(SEPARATOR) {
+            if segment.is_empty() {
+                return Err(TCError::bad_request(format!(
+                    "flat path '{path}' has an empty segment"
+                )));
+            }
+
+            node = match node {
+                Node::Branch(children) => children
+                    .entry(segment.to_string())
+                    .or_insert_with(|| Node::Branch(BTreeMap::new())),
+                Node::Leaf(_) => return Err(conflict(path)),
+            };
+        }
+
+        match node {
+            Node::Branch(children) if children.is_empty() => {
+                *node = Node::Leaf(value);
+                Ok(())
+            }
+            _ => Err(conflict(path)),
+        }
+    }
+
+    fn into_scalar(self) -> TCResult<Scalar> {
+        let children = match self {
+            Node::Leaf(value) => return Ok(Scalar::Value(value)),
+            Node::Branch(children) => children,
+        };
+
+        let indices = children.keys().filter(|key| is_index(key)).count();
+
+        if indices == 0 {
+            children
+                .into_iter()
+                .map(|(key, child)| {
+                    let id = key.parse::<Id>().map_err(|cause| {
+                        TCError::bad_request(format!("invalid flat key '{key}': {cause}"))
+                    })?;
+
+                    child.into_scalar().map(|item| (id, item))
+                })
+                .collect::<TCResult<Map<Scalar>>>()
+                .map(Scalar::Map)
+        } else if indices == children.len() {
+            let mut items = children
+                .into_iter()
+                .map(|(key, child)| {
+                    let i = key.parse::<usize>().map_err(|cause| {
+                        TCError::bad_request(format!("invalid tuple index '{key}': {cause}"))
+                    })?;
+
+                    Ok((i, child))
+                })
+                .collect::<TCResult<Vec<_>>>()?;
+
+            items.sort_by_key(|(i, _)| *i);
+
+            items
+                .into_iter()
+                .enumerate()
+                .map(|(expected, (i, child))| {
+                    if i == expected {
+                        child.into_scalar()
+                    } else {
+                        Err(TCError::bad_request(format!(
+                            "tuple index {i} is out of sequence (expected {expected})"
+                        )))
+                    }
+                })
+                .collect::<TCResult<Vec<_>>>()
+                .map(Scalar::Tuple)
+        } else {
+            Err(TCError::bad_request(
+                "a flat collection cannot mix tuple indices and map keys",
+            ))
+        }
+    }
+}
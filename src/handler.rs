@@ -1,13 +1,17 @@
-use std::future::Future;
+use std::{fmt, future::Future, str::FromStr, sync::Arc};
 
-use destream::de;
+use destream::{de, en};
+use futures::future::BoxFuture;
 use pathlink::PathSegment;
-use tc_error::{TCError, TCResult};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tc_error::{ErrorKind, TCError, TCResult};
 
-use crate::Transaction;
+use tc_value::Value;
+
+use crate::{Scalar, Transaction};
 
 /// HTTP-like verbs supported by TinyChain routers.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Method {
     Get,
     Put,
@@ -15,12 +19,225 @@ pub enum Method {
     Delete,
 }
 
+impl Method {
+    /// Every method, in a fixed order.
+    pub const ALL: [Method; 4] = [Self::Get, Self::Put, Self::Post, Self::Delete];
+
+    /// The uppercase verb of this method, e.g. `"GET"`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Put => "PUT",
+            Self::Post => "POST",
+            Self::Delete => "DELETE",
+        }
+    }
+
+    /// Return `true` if sending a request with this method twice has the same effect as sending
+    /// it once, by convention: GET, PUT, and DELETE are idempotent, POST is not.
+    pub fn is_idempotent(self) -> bool {
+        matches!(self, Self::Get | Self::Put | Self::Delete)
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Method {
+    type Err = TCError;
+
+    /// Parse an uppercase verb like `"GET"`; like HTTP methods, verbs are case-sensitive.
+    fn from_str(verb: &str) -> TCResult<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|method| method.as_str() == verb)
+            .ok_or_else(|| TCError::bad_request(format!("unknown method {verb:?}")))
+    }
+}
+
+impl Serialize for Method {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Method {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let verb = String::deserialize(deserializer)?;
+        verb.parse()
+            .map_err(|err: TCError| serde::de::Error::custom(err.message()))
+    }
+}
+
+impl de::FromStream for Method {
+    type Context = ();
+
+    async fn from_stream<D: de::Decoder>(
+        context: Self::Context,
+        decoder: &mut D,
+    ) -> Result<Self, D::Error> {
+        let verb = String::from_stream(context, decoder).await?;
+        verb.parse()
+            .map_err(|err: TCError| de::Error::custom(err.message()))
+    }
+}
+
+impl<'en> en::IntoStream<'en> for Method {
+    fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        self.as_str().into_stream(encoder)
+    }
+}
+
+impl<'en> en::ToStream<'en> for Method {
+    fn to_stream<E: en::Encoder<'en>>(&'en self, encoder: E) -> Result<E::Ok, E::Error> {
+        en::IntoStream::into_stream(self.as_str(), encoder)
+    }
+}
+
+/// The set of [`Method`]s a handler type supports.
+pub trait SupportedMethods {
+    const SUPPORTED_METHODS: &'static [Method];
+
+    /// Return `true` if a failed request with the given `method` is safe to retry automatically.
+    ///
+    /// Defaults to [`Method::is_idempotent`]; override this to advertise e.g. a POST handler which
+    /// happens to be idempotent, or a PUT handler which isn't.
+    fn is_idempotent(&self, method: Method) -> bool {
+        method.is_idempotent()
+    }
+
+    /// The encodings this handler can decode a request body from, for the given `method`, in
+    /// order of preference.
+    ///
+    /// Defaults to just [`ContentType::Json`].
+    fn accepts(&self, _method: Method) -> &[ContentType] {
+        &[ContentType::Json]
+    }
+
+    /// The encodings this handler can encode a response in, for the given `method`, in order of
+    /// preference.
+    ///
+    /// Defaults to just [`ContentType::Json`].
+    fn produces(&self, _method: Method) -> &[ContentType] {
+        &[ContentType::Json]
+    }
+}
+
+/// An encoding of a request or response body, for a transport to negotiate per handler; see
+/// [`SupportedMethods::accepts`] and [`SupportedMethods::produces`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    Json,
+    MessagePack,
+}
+
+impl ContentType {
+    /// The MIME type of this encoding, e.g. for an HTTP `Content-Type` header.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// The first of the `preferred` encodings (e.g. from an HTTP `Accept` header) which is also
+    /// `supported`, if any.
+    pub fn negotiate(preferred: &[ContentType], supported: &[ContentType]) -> Option<Self> {
+        preferred
+            .iter()
+            .copied()
+            .find(|content_type| supported.contains(content_type))
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mime_type())
+    }
+}
+
+/// A request used a [`Method`] which its handler does not support.
+///
+/// Unlike the [`TCError`] returned by [`Handler::method_not_supported`], this lists the methods
+/// which *are* allowed, e.g. to populate the `Allow` header of an HTTP 405 response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MethodNotAllowed {
+    method: Method,
+    handler: String,
+    allowed: Vec<Method>,
+}
+
+impl MethodNotAllowed {
+    pub fn new(method: Method, handler: impl fmt::Display, allowed: &[Method]) -> Self {
+        Self {
+            method,
+            handler: handler.to_string(),
+            allowed: allowed.to_vec(),
+        }
+    }
+
+    /// The method which was attempted.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// The name of the handler (or endpoint) which rejected the request.
+    pub fn handler(&self) -> &str {
+        &self.handler
+    }
+
+    /// The methods which the handler does support.
+    pub fn allowed(&self) -> &[Method] {
+        &self.allowed
+    }
+}
+
+impl fmt::Display for MethodNotAllowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "endpoint {} does not support {:?} (allowed: {:?})",
+            self.handler, self.method, self.allowed
+        )
+    }
+}
+
+impl std::error::Error for MethodNotAllowed {}
+
+impl From<MethodNotAllowed> for TCError {
+    fn from(err: MethodNotAllowed) -> Self {
+        TCError::new(ErrorKind::MethodNotAllowed, err)
+    }
+}
+
 /// IR analogue of `tc-transact`'s `Route` trait.
 pub trait Route {
     type Handler;
 
     /// Resolve the handler mounted at the given path.
     fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a Self::Handler>;
+
+    /// Return `true` if `path` leads to an interior directory rather than to a handler.
+    ///
+    /// A router which doesn't keep track of its directories may always return `false`.
+    fn is_directory(&self, _path: &[PathSegment]) -> bool {
+        false
+    }
+}
+
+impl<R: Route + ?Sized> Route for Arc<R> {
+    type Handler = R::Handler;
+
+    fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a Self::Handler> {
+        (**self).route(path)
+    }
+
+    fn is_directory(&self, path: &[PathSegment]) -> bool {
+        (**self).is_directory(path)
+    }
 }
 
 /// Marker trait implemented by every TinyChain handler.
@@ -31,6 +248,18 @@ where
     fn method_not_supported(method: Method) -> TCError {
         TCError::method_not_allowed(method, std::any::type_name::<Self>())
     }
+
+    /// Like [`Handler::method_not_supported`], but listing this handler's [`SupportedMethods`].
+    fn method_not_allowed(method: Method) -> MethodNotAllowed
+    where
+        Self: SupportedMethods + Sized,
+    {
+        MethodNotAllowed::new(
+            method,
+            std::any::type_name::<Self>(),
+            Self::SUPPORTED_METHODS,
+        )
+    }
 }
 
 impl<T, H> Handler<T> for H
@@ -110,3 +339,288 @@ define_verb_handler!(HandleGet, get, Method::Get);
 define_verb_handler!(HandlePut, put, Method::Put);
 define_verb_handler!(HandlePost, post, Method::Post);
 define_verb_handler!(HandleDelete, delete, Method::Delete);
+
+/// Implement one or more verb handler traits from an `async fn`, filling in the boxed future type.
+///
+/// The transaction type is taken from the trait in the `impl` header, and the request context is
+/// that of the request type (e.g. [`crate::DecodeOptions`] for a [`Scalar`] request):
+///
+/// ```
+/// use tc_error::TCError;
+/// use tc_ir::{tc_handler, Claim, NetworkTime, Transaction, TxnId};
+///
+/// struct Txn(Claim);
+///
+/// impl Transaction for Txn {
+///     fn id(&self) -> TxnId {
+///         TxnId::from_parts(NetworkTime::from_nanos(1), 0)
+///     }
+///
+///     fn timestamp(&self) -> NetworkTime {
+///         NetworkTime::from_nanos(1)
+///     }
+///
+///     fn claim(&self) -> &Claim {
+///         &self.0
+///     }
+/// }
+///
+/// struct Echo;
+///
+/// tc_handler! {
+///     impl HandleGet<Txn> for Echo {
+///         async fn get(&self, _txn, message: String) -> Result<String, TCError> {
+///             Ok(message)
+///         }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! tc_handler {
+    ($(
+        impl $trait_name:ident<$txn_ty:ty> for $handler:ty {
+            async fn $fn_name:ident(
+                &$self_:ident,
+                $txn:ident,
+                $request:ident: $request_ty:ty $(,)?
+            ) -> Result<$response:ty, $error:ty> $body:block
+        }
+    )+) => {
+        $(
+            impl $crate::$trait_name<$txn_ty> for $handler {
+                type Request = $request_ty;
+                type RequestContext =
+                    <$request_ty as $crate::__destream::de::FromStream>::Context;
+                type Response = $response;
+                type Error = $error;
+                type Fut<'a> = ::std::pin::Pin<
+                    ::std::boxed::Box<
+                        dyn ::std::future::Future<Output = ::std::result::Result<$response, $error>>
+                            + ::std::marker::Send
+                            + 'a,
+                    >,
+                >;
+
+                fn $fn_name<'a>(
+                    &'a $self_,
+                    $txn: &'a $txn_ty,
+                    $request: $request_ty,
+                ) -> $crate::__tc_error::TCResult<Self::Fut<'a>> {
+                    ::std::result::Result::Ok(::std::boxed::Box::pin(async move $body))
+                }
+            }
+        )+
+    };
+}
+
+/// Invoke a PUT handler and convert its response into a [`Scalar`].
+///
+/// Handlers which complete without a meaningful payload should declare `type Response = ();`,
+/// which is reported to the caller as [`Scalar::none`].
+pub fn dispatch_put<'a, T, H>(
+    handler: &'a H,
+    txn: &'a T,
+    request: H::Request,
+) -> TCResult<impl Future<Output = Result<Scalar, H::Error>> + Send + 'a>
+where
+    T: Transaction + ?Sized,
+    H: HandlePut<T>,
+    H::Response: Into<Scalar>,
+{
+    let fut = handler.put(txn, request)?;
+    Ok(async move { fut.await.map(Into::into) })
+}
+
+/// Invoke a DELETE handler and convert its response into a [`Scalar`].
+///
+/// As with [`dispatch_put`], a `()` response is reported as [`Scalar::none`].
+pub fn dispatch_delete<'a, T, H>(
+    handler: &'a H,
+    txn: &'a T,
+    request: H::Request,
+) -> TCResult<impl Future<Output = Result<Scalar, H::Error>> + Send + 'a>
+where
+    T: Transaction + ?Sized,
+    H: HandleDelete<T>,
+    H::Response: Into<Scalar>,
+{
+    let fut = handler.delete(txn, request)?;
+    Ok(async move { fut.await.map(Into::into) })
+}
+
+/// An object-safe handler which dispatches any [`Method`] on an untyped [`Scalar`] request.
+///
+/// The typed verb traits can't be used as trait objects since their request and response types
+/// vary by handler. A runtime which needs to store heterogeneous handlers (e.g. behind a
+/// [`crate::DynLibrary`]) can implement this instead, decoding the request itself.
+pub trait DynHandler<T>: Send + Sync
+where
+    T: Transaction + ?Sized,
+{
+    fn call<'a>(
+        &'a self,
+        method: Method,
+        txn: &'a T,
+        request: Scalar,
+    ) -> TCResult<BoxFuture<'a, TCResult<Scalar>>>;
+}
+
+/// A PUT handler which decodes its own request body, e.g. to write each element of a large tuple
+/// to storage as it arrives instead of decoding the whole body into memory first.
+///
+/// Unlike [`HandlePut`], the request is not decoded before the handler is called: the handler
+/// drives `body` itself, for example with [`decode_elements`]. A decode error is reported as a
+/// bad request.
+///
+/// Since `put_stream` is generic over the decoder, this trait is not object-safe, so a streaming
+/// handler can't be stored as a [`DynHandler`] or served by [`crate::LibraryRegistry::dispatch`],
+/// which both take an already-decoded [`Scalar`] request. A runtime must route a PUT to a
+/// streaming handler itself, before it decodes the request body.
+pub trait HandlePutStream<T>: Handler<T>
+where
+    T: Transaction + ?Sized,
+{
+    type Response;
+
+    fn put_stream<'a, D>(
+        &'a self,
+        txn: &'a T,
+        body: &'a mut D,
+    ) -> TCResult<BoxFuture<'a, TCResult<Self::Response>>>
+    where
+        D: de::Decoder + 'a,
+    {
+        let _ = (txn, body);
+        Err(Self::method_not_supported(Method::Put))
+    }
+}
+
+/// Decode a sequence from `decoder` one element at a time (each with the default context of its
+/// type), passing each to `f` as soon as it's decoded, and return the number of elements.
+///
+/// The next element is not decoded until the future returned by `f` completes, so only one
+/// element is held in memory at a time and `f` can apply backpressure, e.g. by awaiting a write
+/// to storage. If `f` fails, the rest of the sequence is skipped without being decoded and its
+/// error is returned.
+pub async fn decode_elements<D, E, F, Fut>(decoder: &mut D, f: F) -> TCResult<usize>
+where
+    D: de::Decoder,
+    E: de::FromStream,
+    E::Context: Default,
+    F: FnMut(E) -> Fut + Send,
+    Fut: Future<Output = TCResult<()>> + Send,
+{
+    struct ElementVisitor<E, F> {
+        f: F,
+        element: std::marker::PhantomData<fn() -> E>,
+    }
+
+    impl<E, F, Fut> de::Visitor for ElementVisitor<E, F>
+    where
+        E: de::FromStream,
+        E::Context: Default,
+        F: FnMut(E) -> Fut + Send,
+        Fut: Future<Output = TCResult<()>> + Send,
+    {
+        type Value = TCResult<usize>;
+
+        fn expecting() -> &'static str {
+            "a sequence"
+        }
+
+        async fn visit_seq<A: de::SeqAccess>(
+            mut self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut len = 0;
+
+            while let Some(element) = seq.next_element::<E>(E::Context::default()).await? {
+                if let Err(cause) = (self.f)(element).await {
+                    while seq.next_element::<de::IgnoredAny>(()).await?.is_some() {}
+                    return Ok(Err(cause));
+                }
+
+                len += 1;
+            }
+
+            Ok(Ok(len))
+        }
+    }
+
+    let visitor = ElementVisitor {
+        f,
+        element: std::marker::PhantomData,
+    };
+
+    decoder
+        .decode_seq(visitor)
+        .await
+        .map_err(|cause| TCError::bad_request(format!("invalid streamed request: {cause}")))?
+}
+
+/// Maps a failed request into the response body a server returns for it, so that every failure
+/// (an unknown route, a rejected request, or a handler error) is reported in the same shape.
+pub trait ErrorHandler: Send + Sync {
+    /// Produce the response body for a `method` request to `path` which failed with `err`.
+    ///
+    /// The default body is a map with the error's `code` (e.g. `"not_found"`) and `message`.
+    fn on_error(&self, method: Method, path: &[PathSegment], err: TCError) -> Scalar {
+        let _ = (method, path);
+
+        let body = [
+            ("code", Scalar::from(Value::String(err.code().to_string()))),
+            ("message", Scalar::from(Value::String(err.message().into()))),
+        ];
+
+        Scalar::Map(
+            body.into_iter()
+                .map(|(key, value)| (key.parse().expect("error body key"), value))
+                .collect(),
+        )
+    }
+}
+
+/// The [`ErrorHandler`] which produces the default `{code, message}` error body.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultErrorHandler;
+
+impl ErrorHandler for DefaultErrorHandler {}
+
+macro_rules! define_borrowed_verb_handler {
+    ($trait_name:ident, $fn_name:ident, $method:expr) => {
+        /// Zero-copy counterpart of the owned verb handler trait.
+        ///
+        /// The request may borrow from a buffer the runtime keeps alive for the duration of the
+        /// call, so it is not required to implement `FromStream`; the runtime is responsible for
+        /// parsing it in place.
+        pub trait $trait_name<T>: Handler<T>
+        where
+            T: Transaction + ?Sized,
+        {
+            type Request<'r>
+            where
+                Self: 'r,
+                T: 'r;
+            type Response;
+            type Error;
+            type Fut<'a>: Future<Output = Result<Self::Response, Self::Error>> + Send + 'a
+            where
+                Self: 'a,
+                T: 'a;
+
+            fn $fn_name<'a>(
+                &'a self,
+                txn: &'a T,
+                request: Self::Request<'a>,
+            ) -> TCResult<Self::Fut<'a>> {
+                let _ = (txn, request);
+                Err(Self::method_not_supported($method))
+            }
+        }
+    };
+}
+
+define_borrowed_verb_handler!(HandleGetBorrowed, get_borrowed, Method::Get);
+define_borrowed_verb_handler!(HandlePutBorrowed, put_borrowed, Method::Put);
+define_borrowed_verb_handler!(HandlePostBorrowed, post_borrowed, Method::Post);
+define_borrowed_verb_handler!(HandleDeleteBorrowed, delete_borrowed, Method::Delete);
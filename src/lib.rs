@@ -8,6 +8,16 @@
 pub use hr_id::Id;
 pub use tc_value::class::{Class, NativeClass};
 
+// used by the exported macros, so that callers don't need their own `tc-error` or `destream`
+// dependency
+#[doc(hidden)]
+pub use destream as __destream;
+#[doc(hidden)]
+pub use tc_error as __tc_error;
+
+pub mod claim;
+pub use claim::{Claim, DelegatedClaim};
+
 mod txn;
 pub use txn::*;
 
@@ -15,268 +25,3957 @@ mod handler;
 pub use handler::*;
 
 mod map;
-pub use map::Map;
+pub use map::{IdPolicy, Map};
 
 mod scalar;
 pub use scalar::*;
 
-mod op;
-pub use op::*;
+mod op;
+pub use op::*;
+
+mod tcref;
+pub use tcref::*;
+
+mod dir;
+pub use dir::*;
+
+mod library;
+pub use library::*;
+
+mod eval;
+pub use eval::*;
+
+mod decode;
+pub use decode::*;
+
+mod fingerprint;
+pub use fingerprint::{FingerprintHasher, HashableScalar};
+
+mod alpha;
+mod flat;
+
+mod size;
+pub use size::SizeReport;
+
+mod metrics;
+pub use metrics::{MeteredRouter, RouteMetrics, RouteOutcome};
+
+mod audit;
+pub use audit::FingerprintedLibrary;
+
+mod visit;
+pub use visit::ScalarVisitor;
+
+#[cfg(feature = "tc-transact-compat")]
+mod compat;
+
+#[cfg(feature = "ndjson")]
+mod ndjson;
+#[cfg(feature = "ndjson")]
+pub use ndjson::{decode_ndjson, encode_ndjson};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{collections::BTreeMap, future::Future, pin::Pin, str::FromStr};
+
+    use number_general::Number;
+    use pathlink::{Link, Path, PathBuf, PathSegment};
+    use tc_error::{TCError, TCResult};
+    use tc_value::Value;
+
+    fn encode_json<'en, T: destream::en::IntoStream<'en> + 'en>(value: T) -> String {
+        let chunks = destream_json::encode(value).expect("encode");
+        let bytes = futures::executor::block_on_stream(chunks)
+            .map(|chunk| chunk.expect("chunk").to_vec())
+            .collect::<Vec<_>>()
+            .concat();
+
+        String::from_utf8(bytes).expect("utf8")
+    }
+
+    /// Drop `scalar` one collection at a time, since the derived `Drop` of a deeply nested scalar
+    /// would overflow the stack.
+    fn drop_iteratively(scalar: Scalar) {
+        let mut stack = vec![scalar];
+
+        while let Some(scalar) = stack.pop() {
+            match scalar {
+                Scalar::Tuple(items) => stack.extend(items),
+                Scalar::Map(map) => stack.extend(map.into_iter().map(|(_, value)| value)),
+                _ => {}
+            }
+        }
+    }
+
+    fn decode_json<T>(json: &str) -> Result<T, destream_json::de::Error>
+    where
+        T: destream::de::FromStream,
+        T::Context: Default,
+    {
+        let source =
+            futures::stream::iter([Ok::<_, std::io::Error>(json.as_bytes().to_vec().into())]);
+
+        futures::executor::block_on(destream_json::try_decode(T::Context::default(), source))
+    }
+
+    #[derive(Clone)]
+    struct FakeTxn {
+        claim: Claim,
+        cancel: CancelToken,
+    }
+
+    impl FakeTxn {
+        fn new(claim: Claim) -> Self {
+            Self {
+                claim,
+                cancel: CancelToken::new(),
+            }
+        }
+    }
+
+    impl Transaction for FakeTxn {
+        fn id(&self) -> TxnId {
+            TxnId::from_parts(NetworkTime::from_nanos(42), 7)
+        }
+
+        fn timestamp(&self) -> NetworkTime {
+            NetworkTime::from_nanos(42)
+        }
+
+        fn claim(&self) -> &Claim {
+            &self.claim
+        }
+
+        fn cancel_token(&self) -> Option<&CancelToken> {
+            Some(&self.cancel)
+        }
+    }
+
+    struct HelloHandler;
+
+    impl<T: Transaction> HandleGet<T> for HelloHandler {
+        type Request = String;
+        type RequestContext = ();
+        type Response = String;
+        type Error = ();
+        type Fut<'a>
+            = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>
+        where
+            T: 'a;
+
+        fn get<'a>(&'a self, _txn: &'a T, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move { Ok(format!("hello {request}")) }))
+        }
+    }
+
+    struct Greeting<'r> {
+        name: &'r str,
+    }
+
+    struct BorrowedHelloHandler;
+
+    impl HandleGetBorrowed<FakeTxn> for BorrowedHelloHandler {
+        type Request<'r> = Greeting<'r>;
+        type Response = String;
+        type Error = ();
+        type Fut<'a> = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn get_borrowed<'a>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            request: Self::Request<'a>,
+        ) -> TCResult<Self::Fut<'a>> {
+            Ok(std::future::ready(Ok(format!("hello {}", request.name))))
+        }
+    }
+
+    #[test]
+    fn borrowed_handler_invocation() {
+        let claim = Claim::new(Link::from_str("/hello").unwrap(), umask::Mode::all());
+        let txn = FakeTxn::new(claim);
+
+        let buffer = String::from("name=world");
+        let request = Greeting {
+            name: buffer.strip_prefix("name=").expect("name"),
+        };
+
+        let fut = BorrowedHelloHandler
+            .get_borrowed(&txn, request)
+            .expect("GET supported");
+        assert_eq!(futures::executor::block_on(fut).unwrap(), "hello world");
+    }
+
+    impl SupportedMethods for HelloHandler {
+        const SUPPORTED_METHODS: &'static [Method] = &[Method::Get];
+    }
+
+    #[test]
+    fn method_not_allowed_lists_supported_methods() {
+        let err = <HelloHandler as Handler<FakeTxn>>::method_not_allowed(Method::Post);
+        assert_eq!(err.method(), Method::Post);
+        assert_eq!(err.allowed(), [Method::Get]);
+        assert!(err.handler().ends_with("HelloHandler"));
+        assert!(err
+            .to_string()
+            .ends_with("does not support Post (allowed: [Get])"));
+    }
+
+    struct LookupHandler;
+
+    impl SupportedMethods for LookupHandler {
+        const SUPPORTED_METHODS: &'static [Method] = &[Method::Get, Method::Post];
+
+        // this POST is a read-only query which takes its parameters in a request body
+        fn is_idempotent(&self, method: Method) -> bool {
+            method == Method::Post || method.is_idempotent()
+        }
+    }
+
+    struct BlobHandler;
+
+    impl SupportedMethods for BlobHandler {
+        const SUPPORTED_METHODS: &'static [Method] = &[Method::Get, Method::Put];
+
+        fn accepts(&self, method: Method) -> &[ContentType] {
+            match method {
+                Method::Put => &[ContentType::MessagePack, ContentType::Json],
+                _ => &[ContentType::Json],
+            }
+        }
+
+        fn produces(&self, _method: Method) -> &[ContentType] {
+            &[ContentType::MessagePack, ContentType::Json]
+        }
+    }
+
+    #[test]
+    fn handlers_advertise_content_types_per_method() {
+        assert_eq!(HelloHandler.accepts(Method::Get), [ContentType::Json]);
+        assert_eq!(HelloHandler.produces(Method::Get), [ContentType::Json]);
+
+        assert_eq!(BlobHandler.accepts(Method::Get), [ContentType::Json]);
+        assert_eq!(
+            BlobHandler.accepts(Method::Put)[0],
+            ContentType::MessagePack
+        );
+
+        let binary = [ContentType::MessagePack];
+        let produces = BlobHandler.produces(Method::Get);
+        assert_eq!(
+            ContentType::negotiate(&binary, produces),
+            Some(ContentType::MessagePack)
+        );
+        assert_eq!(
+            ContentType::negotiate(&binary, HelloHandler.produces(Method::Get)),
+            None
+        );
+        assert_eq!(ContentType::MessagePack.to_string(), "application/msgpack");
+    }
+
+    #[test]
+    fn handlers_can_advertise_an_idempotent_post() {
+        assert!(HelloHandler.is_idempotent(Method::Get));
+        assert!(HelloHandler.is_idempotent(Method::Delete));
+        assert!(!HelloHandler.is_idempotent(Method::Post));
+
+        assert!(LookupHandler.is_idempotent(Method::Get));
+        assert!(LookupHandler.is_idempotent(Method::Post));
+    }
+
+    #[test]
+    fn handler_invocation() {
+        let handler = HelloHandler;
+        let claim = Claim::new(Link::from_str("/hello").unwrap(), umask::Mode::all());
+        let txn = FakeTxn::new(claim);
+
+        let fut = handler.get(&txn, "world".into()).expect("GET supported");
+        let out = futures::executor::block_on(fut).unwrap();
+        assert_eq!(out, "hello world");
+    }
+
+    struct CounterHandler;
+
+    impl HandlePut<FakeTxn> for CounterHandler {
+        type Request = (String, u64);
+        type RequestContext = ();
+        type Response = ();
+        type Error = ();
+        type Fut<'a> = std::future::Ready<Result<(), ()>>;
+
+        fn put<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
+            let (key, value) = request;
+            assert_eq!((key.as_str(), value), ("count", 1));
+            Ok(std::future::ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn unit_put_response_dispatches_as_none() {
+        let claim = Claim::new(Link::from_str("/counter").unwrap(), umask::Mode::all());
+        let txn = FakeTxn::new(claim);
+
+        let fut = dispatch_put(&CounterHandler, &txn, ("count".to_string(), 1)).expect("PUT");
+        let response = futures::executor::block_on(fut).expect("response");
+        assert_eq!(response, Scalar::none());
+        assert!(response.is_none());
+        assert_eq!(encode_json(response), "null");
+    }
+
+    struct CountdownHandler;
+
+    impl HandleGet<FakeTxn> for CountdownHandler {
+        type Request = u64;
+        type RequestContext = ();
+        type Response = u64;
+        type Error = String;
+        type Fut<'a> = Pin<Box<dyn Future<Output = Result<u64, String>> + Send + 'a>>;
+
+        fn get<'a>(&'a self, txn: &'a FakeTxn, steps: Self::Request) -> TCResult<Self::Fut<'a>> {
+            Ok(Box::pin(async move {
+                for step in 0..steps {
+                    if txn.cancel_token().is_some_and(CancelToken::is_cancelled) {
+                        return Err(format!("cancelled at step {step}"));
+                    }
+                }
+
+                Ok(steps)
+            }))
+        }
+    }
+
+    #[test]
+    fn handler_observes_cancellation() {
+        let claim = Claim::new(Link::from_str("/countdown").unwrap(), umask::Mode::all());
+        let txn = FakeTxn::new(claim);
+
+        let fut = CountdownHandler.get(&txn, 3).expect("GET");
+        assert_eq!(futures::executor::block_on(fut), Ok(3));
+
+        let fut = CountdownHandler.get(&txn, 3).expect("GET");
+        txn.cancel.clone().cancel();
+        assert_eq!(
+            futures::executor::block_on(fut),
+            Err("cancelled at step 0".to_string())
+        );
+    }
+
+    #[test]
+    fn admin_claim_implies_every_capability() {
+        let link = Link::from_str("/lib/service").expect("link");
+        let admin = Claim::grant_admin(link.clone());
+
+        for required in [claim::READ, claim::WRITE, claim::EXECUTE, claim::ADMIN] {
+            assert!(admin.allows(&link, required));
+        }
+
+        let reader = Claim::new(link.clone(), claim::READ);
+        assert!(reader.allows(&link, claim::READ));
+        assert!(!reader.allows(&link, claim::WRITE));
+        assert!(!reader.allows(&link, claim::EXECUTE));
+        assert!(!reader.allows(&link, claim::ADMIN));
+    }
+
+    #[test]
+    fn fingerprinted_library_survives_a_json_round_trip() {
+        let schema = LibrarySchema::new(Link::from_str("/lib/acme").unwrap(), "1.0.0", vec![]);
+        let id = |id: &str| Id::from_str(id).expect("id");
+
+        // each of these literals decodes from JSON as a different number type than it was built as
+        let op = OpDef::Get((
+            id("key"),
+            vec![
+                (id("count"), Scalar::from(1u64)),
+                (id("ratio"), Scalar::from(2.0f64)),
+                (id("offset"), Scalar::from(-3.0f64)),
+                (id("zero"), Scalar::from(-0.0f64)),
+                (
+                    id("scale"),
+                    Scalar::from(Value::Number(Number::from(0.1f32))),
+                ),
+            ],
+        ));
+
+        let path = PathBuf::from_str("/numbers").expect("path");
+        let library = FingerprintedLibrary::new(schema, [(path.clone(), op)]);
+
+        let decoded: FingerprintedLibrary =
+            decode_json(&encode_json(library.clone())).expect("untampered library");
+
+        assert_eq!(decoded.fingerprints(), library.fingerprints());
+        assert_eq!(decoded.ops()[&path].form().len(), 5);
+    }
+
+    #[test]
+    fn fingerprinted_library_detects_a_tampered_op() {
+        let schema = LibrarySchema::new(Link::from_str("/lib/acme").unwrap(), "1.0.0", vec![]);
+        let op = |json: &str| decode_json::<OpDef>(json).expect("op");
+        let path = |path: &str| PathBuf::from_str(path).expect("path");
+
+        let library = FingerprintedLibrary::new(
+            schema,
+            [
+                (
+                    path("/hello"),
+                    op(r#"{"/state/scalar/op/get": ["key", [["greeting", "hello"]]]}"#),
+                ),
+                (
+                    path("/users/get"),
+                    op(
+                        r#"{"/state/scalar/op/get": ["key", [["user", {"/lib/users": [{"$key": []}]}]]]}"#,
+                    ),
+                ),
+            ],
+        );
+
+        let encoded = encode_json(library.clone());
+        let decoded: FingerprintedLibrary = decode_json(&encoded).expect("verified library");
+        assert_eq!(decoded, library);
+
+        let tampered = encoded.replace("\"hello\"", "\"goodbye\"");
+        assert_ne!(tampered, encoded);
+
+        let error = decode_json::<FingerprintedLibrary>(&tampered)
+            .expect_err("tampered library")
+            .to_string();
+        assert!(
+            error.contains("op /hello does not match its fingerprint"),
+            "{error}"
+        );
+
+        let fingerprint = format!("{:016x}", library.fingerprints()[&path("/hello")]);
+        let unlisted = encoded.replace(&format!("\"/hello\":\"{fingerprint}\","), "");
+        assert_ne!(unlisted, encoded);
+        assert!(decode_json::<FingerprintedLibrary>(&unlisted).is_err());
+    }
+
+    #[test]
+    fn library_schema_destream_roundtrip() {
+        let schema = LibrarySchema::new(
+            Link::from_str("/lib/service").expect("link"),
+            "0.1.0",
+            vec![
+                Link::from_str("/lib/dependency").expect("dep"),
+                Link::from_str("/lib/other").expect("dep"),
+            ],
+        );
+
+        let encoded = destream_json::encode(schema.clone()).expect("encode schema");
+        let decoded: LibrarySchema =
+            futures::executor::block_on(destream_json::try_decode((), encoded))
+                .expect("decode schema");
+
+        assert_eq!(decoded, schema);
+    }
+
+    #[cfg(feature = "jsonschema")]
+    #[test]
+    fn library_schema_json_schema_checks_manifests() {
+        use serde_json::{json, Value as Json};
+
+        let schema = LibrarySchema::json_schema();
+
+        assert_eq!(
+            schema,
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "title": "LibrarySchema",
+                "type": "object",
+                "properties": {
+                    "id": {
+                        "description": "Unique library identifier (usually a `tc://` link)",
+                        "type": "string",
+                    },
+                    "version": {
+                        "description": "Version string advertised to runtimes",
+                        "type": "string",
+                    },
+                    "dependencies": {
+                        "description": "Links to the libraries this module depends on",
+                        "type": "array",
+                        "items": { "type": "string" },
+                    },
+                    "_v": {
+                        "description": "Wire format version (1 if absent)",
+                        "const": 1,
+                    },
+                },
+                "required": ["id", "version"],
+                "additionalProperties": true,
+            })
+        );
+
+        // every field of an encoded manifest is described by the schema
+        let known_good = LibrarySchema::new(
+            Link::from_str("/lib/service").expect("link"),
+            "0.1.0",
+            vec![Link::from_str("/lib/dependency").expect("dep")],
+        );
+        let manifest: Json = serde_json::from_str(&encode_json(known_good)).expect("json");
+        let fields = manifest.as_object().expect("manifest object");
+
+        for (name, value) in fields {
+            let property = &schema["properties"][name];
+            assert!(property.is_object(), "undescribed field {name}");
+
+            match property["type"].as_str() {
+                Some("string") => assert!(value.is_string(), "{name}"),
+                Some("array") => assert!(value
+                    .as_array()
+                    .is_some_and(|items| items.iter().all(Json::is_string))),
+                _ => assert_eq!(value, &property["const"], "{name}"),
+            }
+        }
+
+        for name in schema["required"].as_array().expect("required") {
+            assert!(fields.contains_key(name.as_str().expect("name")));
+        }
+    }
+
+    #[test]
+    fn schema_cache_hits_misses_and_evicts() {
+        let link = |path: &str| Link::from_str(path).expect("link");
+        let load_count = std::cell::Cell::new(0);
+        let load = |path: &str| {
+            load_count.set(load_count.get() + 1);
+            Ok(LibrarySchema::new(link(path), "0.1.0", vec![]))
+        };
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SchemaCache>();
+
+        let cache = SchemaCache::new(2);
+
+        let a = cache
+            .get_or_insert_with(&link("/lib/a"), || load("/lib/a"))
+            .expect("miss");
+        assert_eq!(a.id(), &link("/lib/a"));
+        assert_eq!(load_count.get(), 1);
+
+        cache
+            .get_or_insert_with(&link("/lib/a"), || load("/lib/a"))
+            .expect("hit");
+        assert_eq!(load_count.get(), 1);
+
+        cache
+            .get_or_insert_with(&link("/lib/b"), || load("/lib/b"))
+            .expect("miss");
+        assert!(cache.get(&link("/lib/a")).is_some());
+
+        // "/lib/b" is now the least-recently used entry
+        cache
+            .get_or_insert_with(&link("/lib/c"), || load("/lib/c"))
+            .expect("miss");
+        assert_eq!(load_count.get(), 3);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&link("/lib/a")).is_some());
+        assert!(cache.get(&link("/lib/b")).is_none());
+        assert!(cache.get(&link("/lib/c")).is_some());
+    }
+
+    #[test]
+    fn library_registry_routes_to_the_right_library() {
+        struct Greeter(&'static str);
+
+        impl DynHandler<FakeTxn> for Greeter {
+            fn call<'a>(
+                &'a self,
+                method: Method,
+                _txn: &'a FakeTxn,
+                request: Scalar,
+            ) -> TCResult<futures::future::BoxFuture<'a, TCResult<Scalar>>> {
+                assert_eq!(method, Method::Get);
+                let name = match request {
+                    Scalar::Value(Value::String(name)) => name,
+                    other => return Err(TCError::unexpected(other, "a name")),
+                };
+
+                let greeting = format!("{} {name}", self.0);
+                Ok(Box::pin(async move {
+                    Ok(Scalar::from(Value::String(greeting)))
+                }))
+            }
+        }
+
+        let module = |id: &str, greeting: &'static str| {
+            let schema = LibrarySchema::new(Link::from_str(id).expect("id"), "0.1.0", vec![]);
+            let routes = Dir::from_routes([(vec![segment("hello")], Greeter(greeting))]);
+            Box::new(LibraryModule::<FakeTxn, _>::new(
+                schema,
+                routes.expect("routes"),
+            ))
+        };
+
+        let mut registry = LibraryRegistry::new();
+        registry
+            .register(module("/lib/en", "hello"))
+            .expect("register");
+        registry
+            .register(module("/lib/en/formal", "good day"))
+            .expect("register");
+        assert!(registry.register(module("/lib/en", "hi")).is_err());
+        assert_eq!(registry.len(), 2);
+
+        let id = Link::from_str("/lib/en/formal").expect("id");
+        assert_eq!(registry.get(&id).expect("library").schema().id(), &id);
+
+        let txn = FakeTxn::new(Claim::new(id, umask::USER_READ));
+        let call = |path: &str| {
+            let link = Link::from_str(path).expect("link");
+            let handler = registry.route(&link).expect("handler");
+            let response = handler.call(
+                Method::Get,
+                &txn,
+                Scalar::from(Value::String("world".into())),
+            );
+            futures::executor::block_on(response.expect("call")).expect("response")
+        };
+
+        assert_eq!(
+            call("/lib/en/hello"),
+            Scalar::from(Value::String("hello world".into()))
+        );
+        assert_eq!(
+            call("/lib/en/formal/hello"),
+            Scalar::from(Value::String("good day world".into()))
+        );
+        assert!(registry
+            .route(&Link::from_str("/lib/fr/hello").expect("link"))
+            .is_none());
+
+        // dispatch returns a success body as `Ok` and an error body with its kind as `Err`
+        let link = Link::from_str("/lib/en/hello").expect("link");
+
+        assert_eq!(
+            futures::executor::block_on(registry.dispatch(
+                Method::Get,
+                &txn,
+                &link,
+                Scalar::from(Value::String("world".into()))
+            )),
+            Ok(Scalar::from(Value::String("hello world".into())))
+        );
+
+        let (kind, body) = futures::executor::block_on(registry.dispatch(
+            Method::Get,
+            &txn,
+            &link,
+            Scalar::from(1u64),
+        ))
+        .expect_err("not a name");
+        let Scalar::Map(mut body) = body else {
+            panic!("expected an error map, not {body:?}");
+        };
+
+        assert_eq!(
+            body.require("code").expect("code"),
+            Scalar::from(Value::String(kind.to_string()))
+        );
+    }
+
+    #[test]
+    fn txn_header_destream_roundtrip() {
+        let claim = Claim::new(Link::from_str("/lib/service").unwrap(), umask::Mode::all());
+        let header = TxnHeader::new(
+            TxnId::from_parts(NetworkTime::from_nanos(7), 1),
+            NetworkTime::from_nanos(7),
+            claim,
+        );
+
+        let encoded = destream_json::encode(header.clone()).expect("encode header");
+        let decoded: TxnHeader =
+            futures::executor::block_on(destream_json::try_decode((), encoded))
+                .expect("decode header");
+
+        assert_eq!(decoded, header);
+    }
+
+    #[test]
+    fn wire_format_version_tag_defaults_to_v1() {
+        let schema = r#"{"id": "/lib/service", "version": "0.1.0"}"#;
+        let untagged = decode_json::<LibrarySchema>(schema).expect("untagged v1 schema");
+        let tagged = r#"{"_v": 1, "id": "/lib/service", "version": "0.1.0"}"#;
+        assert_eq!(decode_json::<LibrarySchema>(tagged).expect("v1"), untagged);
+
+        let v2 = r#"{"_v": 2, "id": "/lib/service", "version": "0.1.0"}"#;
+        let err = decode_json::<LibrarySchema>(v2).expect_err("v2 schema");
+        assert!(
+            err.to_string().contains("unsupported format version 2"),
+            "{err}"
+        );
+
+        let claim = Claim::new(Link::from_str("/lib/service").unwrap(), umask::Mode::all());
+        let header = TxnHeader::new(
+            TxnId::from_parts(NetworkTime::from_nanos(7), 1),
+            NetworkTime::from_nanos(7),
+            claim,
+        );
+        let encoded = encode_json(header.clone());
+        assert!(!encoded.contains("_v"), "{encoded}");
+        assert_eq!(
+            decode_json::<TxnHeader>(&encoded).expect("v1 header"),
+            header
+        );
+
+        let v2 = encoded.replacen('{', r#"{"_v": 2, "#, 1);
+        let err = decode_json::<TxnHeader>(&v2).expect_err("v2 header");
+        assert!(
+            err.to_string().contains("unsupported format version 2"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn txn_header_target_roundtrips_independently_of_the_claim() {
+        let scope = Link::from_str("/lib/service").unwrap();
+        let target = Link::from_str("/lib/service/users/get").unwrap();
+
+        let header = TxnHeader::new(
+            TxnId::from_parts(NetworkTime::from_nanos(7), 1),
+            NetworkTime::from_nanos(7),
+            Claim::new(scope.clone(), claim::READ),
+        );
+        assert_eq!(header.target(), &scope);
+        assert!(!encode_json(header.clone()).contains("target"));
+
+        let header = header.with_target(target.clone());
+        assert_eq!(header.claim().link, scope);
+
+        let decoded = decode_json::<TxnHeader>(&encode_json(header.clone())).expect("decode");
+        assert_eq!(decoded.target(), &target);
+        assert_eq!(decoded.claim().link, scope);
+        assert_eq!(decoded, header);
+
+        let txn = FakeTxn::new(Claim::new(scope.clone(), claim::READ));
+        assert_eq!(txn.target(), &scope);
+        assert_eq!(TxnHeader::from_transaction(&txn).target(), &scope);
+    }
+
+    #[test]
+    fn redacted_claims_and_headers_omit_links() {
+        let link = Link::from_str("/lib/acme/secret-project").unwrap();
+        let claim = Claim::new(link.clone(), claim::READ | claim::EXECUTE);
+
+        let redacted = claim.redacted();
+        assert!(!redacted.contains("secret-project"), "{redacted}");
+        assert!(redacted.ends_with(" r-x"), "{redacted}");
+        assert_eq!(redacted, claim.clone().redacted());
+
+        let other = Claim::new(Link::from_str("/lib/acme/other").unwrap(), claim.mask);
+        assert_ne!(other.redacted(), redacted);
+
+        let id = TxnId::from_parts(NetworkTime::from_nanos(7), 1);
+        let header = TxnHeader::new(id, NetworkTime::from_nanos(7), claim)
+            .with_target(Link::from_str("/lib/acme/secret-project/users").unwrap());
+
+        let redacted = header.redacted();
+        assert!(!redacted.contains("secret-project"), "{redacted}");
+        assert!(redacted.contains(&id.to_string()), "{redacted}");
+        assert_eq!(redacted, header.redacted());
+    }
+
+    #[test]
+    fn txn_id_bounds_bracket_a_timestamp() {
+        use std::collections::BTreeMap;
+
+        let at = |nanos| NetworkTime::from_nanos(nanos);
+
+        let mut pending = BTreeMap::new();
+        for nanos in [9, 10, 11, 12] {
+            for nonce in [0, 1, u16::MAX] {
+                for trace in [[0; 32], [0x7f; 32], [u8::MAX; 32]] {
+                    let id = TxnId::from_parts(at(nanos), nonce).with_trace(trace);
+                    assert!(TxnId::min_for(at(nanos)) <= id && id <= TxnId::max_for(at(nanos)));
+                    assert!(TxnId::max_for(at(nanos - 1)) < id);
+                    assert!(id < TxnId::min_for(at(nanos + 1)));
+                    pending.insert(id, nanos);
+                }
+            }
+        }
+
+        let window = pending
+            .range(TxnId::min_for(at(10))..=TxnId::max_for(at(11)))
+            .map(|(_, nanos)| *nanos)
+            .collect::<Vec<_>>();
+
+        assert_eq!(window.len(), 18);
+        assert!(window.iter().all(|nanos| *nanos == 10 || *nanos == 11));
+    }
+
+    #[test]
+    fn simple_txn_calls_a_handler() {
+        let link = Link::from_str("/lib/hello").unwrap();
+        let before = NetworkTime::now();
+
+        let txn = SimpleTxn::new(Claim::new(link.clone(), claim::READ));
+        assert!(txn.timestamp() >= before && txn.timestamp() <= NetworkTime::now());
+        assert_eq!(txn.id(), TxnId::from_parts(txn.timestamp(), 0));
+        assert_eq!(txn.target(), &link);
+
+        let response = futures::executor::block_on(
+            HelloHandler
+                .get(&txn, "world".to_string())
+                .expect("handler"),
+        );
+        assert_eq!(response, Ok("hello world".to_string()));
+
+        txn.cancel();
+        assert!(txn.cancel_token().expect("token").is_cancelled());
+    }
+
+    #[test]
+    fn txn_header_merge_combines_partial_headers() {
+        let link = Link::from_str("/lib/service").unwrap();
+        let id = TxnId::from_parts(NetworkTime::from_nanos(7), 1);
+        let parent = TxnId::from_parts(NetworkTime::from_nanos(3), 0);
+        let header = |mask| {
+            TxnHeader::new(
+                id,
+                NetworkTime::from_nanos(7),
+                Claim::new(link.clone(), mask),
+            )
+        };
+
+        let with_deadline =
+            header(claim::READ | claim::WRITE).with_deadline(NetworkTime::from_nanos(100));
+        let with_parent = header(claim::READ)
+            .with_parent(parent)
+            .with_deadline(NetworkTime::from_nanos(200));
+
+        let merged = with_deadline
+            .clone()
+            .merge(with_parent.clone())
+            .expect("merge");
+        assert_eq!(merged.deadline(), Some(NetworkTime::from_nanos(100)));
+        assert_eq!(merged.parent(), Some(parent));
+        assert_eq!(merged.claim().mask, claim::READ);
+        assert_eq!(merged.id(), id);
+
+        let decoded = decode_json::<TxnHeader>(&encode_json(merged.clone())).expect("decode");
+        assert_eq!(decoded, merged);
+        assert_eq!(
+            serde_json::from_str::<TxnHeader>(&serde_json::to_string(&merged).unwrap()).unwrap(),
+            merged
+        );
+
+        let traced = TxnHeader::new(
+            id.with_trace([1; 32]),
+            NetworkTime::from_nanos(7),
+            Claim::new(link.clone(), claim::READ),
+        );
+        let merged = with_parent.clone().merge(traced).expect("merge trace");
+        assert_eq!(merged.id().trace_bytes(), &[1; 32]);
+
+        let other = TxnHeader::new(
+            id.advance(),
+            NetworkTime::from_nanos(7),
+            Claim::new(link.clone(), claim::READ),
+        );
+        assert!(with_deadline.clone().merge(other).is_err());
+
+        let other_parent = header(claim::READ).with_parent(id.advance());
+        assert!(with_parent.merge(other_parent).is_err());
+
+        let elsewhere = TxnHeader::new(
+            id,
+            NetworkTime::from_nanos(7),
+            Claim::new(Link::from_str("/lib/other").unwrap(), claim::READ),
+        );
+        let err = with_deadline.merge(elsewhere).expect_err("different links");
+        assert_eq!(err.code(), tc_error::ErrorKind::Forbidden);
+    }
+
+    #[test]
+    fn txn_header_attenuation_narrows_only() {
+        let link = Link::from_str("/lib/service").unwrap();
+        let header = TxnHeader::new(
+            TxnId::from_parts(NetworkTime::from_nanos(7), 1),
+            NetworkTime::from_nanos(7),
+            Claim::new(link.clone(), claim::READ | claim::EXECUTE),
+        );
+
+        let narrowed = header
+            .clone()
+            .attenuate(link.clone(), claim::READ)
+            .expect("attenuate");
+        assert_eq!(narrowed.id(), header.id());
+        assert_eq!(narrowed.timestamp(), header.timestamp());
+        assert_eq!(narrowed.claim(), &Claim::new(link.clone(), claim::READ));
+
+        let err = header
+            .clone()
+            .attenuate(link.clone(), claim::READ | claim::WRITE)
+            .unwrap_err();
+        assert_eq!(err.code(), tc_error::ErrorKind::Forbidden);
+
+        let other = Link::from_str("/lib/other").unwrap();
+        assert!(header
+            .clone()
+            .attenuate(other.clone(), claim::READ)
+            .is_err());
+
+        let replaced = header.with_claim(Claim::new(other.clone(), claim::ADMIN));
+        assert_eq!(replaced.claim().link, other);
+    }
+
+    #[test]
+    fn txn_id_round_trips_with_trace() {
+        let txn_id = TxnId::from_parts(NetworkTime::from_nanos(7), 1).with_trace([3; 32]);
+        let parsed = TxnId::from_str(&txn_id.to_string()).expect("parse txn id");
+
+        assert_eq!(parsed, txn_id);
+    }
+
+    #[test]
+    fn txn_id_binary_layout_is_stable() {
+        let mut trace = [0u8; 32];
+        for (i, byte) in trace.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        let txn_id = TxnId::from_parts(NetworkTime::from_nanos(0x0102_0304_0506_0708), 0x090a)
+            .with_trace(trace);
+        let bytes = txn_id.to_bytes();
+
+        let mut golden = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        golden.extend(0..32u8);
+        assert_eq!(bytes.as_slice(), golden.as_slice());
+        assert_eq!(bytes.len(), TXN_ID_LEN);
+
+        assert_eq!(TxnId::from_bytes(&bytes), txn_id);
+    }
+
+    #[test]
+    fn txn_id_rejects_partial_wire_id_without_trace() {
+        assert!(TxnId::from_str("7-1").is_err());
+    }
+
+    fn segment(name: &str) -> PathSegment {
+        PathSegment::from_str(name).expect("path segment")
+    }
+
+    #[test]
+    fn dir_routes_nested_handler() {
+        let path = vec![segment("library"), segment("status")];
+        let dir = Dir::from_routes(vec![(path.clone(), HelloHandler)]).expect("dir");
+
+        let claim = Claim::new(Link::from_str("/lib").unwrap(), umask::Mode::all());
+        let txn = FakeTxn::new(claim);
+
+        let handler = dir.route(&path).expect("handler resolved");
+        let fut = handler.get(&txn, "tinychain".into()).expect("GET");
+        let out = futures::executor::block_on(fut).unwrap();
+        assert_eq!(out, "hello tinychain");
+    }
+
+    #[test]
+    fn dir_detects_conflicts() {
+        let path = vec![segment("library"), segment("status")];
+
+        match Dir::from_routes(vec![
+            (path.clone(), HelloHandler),
+            (path.clone(), HelloHandler),
+        ]) {
+            Ok(_) => panic!("expected conflict inserting duplicate handler"),
+            Err(err) => assert!(err.message().contains("already mounted")),
+        }
+    }
+
+    #[test]
+    fn dir_insert_or_replace_overwrites_leaves_only() {
+        let status = [segment("library"), segment("status")];
+        let mut dir = Dir::new();
+
+        assert_eq!(dir.insert_or_replace(&status, "v1").expect("mount"), None);
+        assert_eq!(
+            dir.insert_or_replace(&status, "v2").expect("replace"),
+            Some("v1")
+        );
+        assert_eq!(dir.route(&status), Some(&"v2"));
+
+        let err = dir.insert_or_replace(&status[..1], "v3").unwrap_err();
+        assert!(err.message().contains("over a directory"), "{err}");
+
+        let below = [segment("library"), segment("status"), segment("detail")];
+        let err = dir.insert_or_replace(&below, "v3").unwrap_err();
+        assert!(err.message().contains("below a leaf handler"), "{err}");
+
+        assert_eq!(dir.route(&status), Some(&"v2"));
+    }
+
+    #[test]
+    fn dir_alias_shares_the_aliased_handler() {
+        let status = [segment("status")];
+        let health = [segment("health")];
+        let live = [segment("probe"), segment("live")];
+        let mut dir = Dir::new();
+
+        dir.insert(&status, "v1").expect("mount");
+        dir.alias(&status, &health).expect("alias");
+        dir.alias(&health, &live).expect("alias of alias");
+        assert_eq!(dir.route(&health), Some(&"v1"));
+        assert_eq!(dir.route(&live), Some(&"v1"));
+
+        dir.insert_or_replace(&status, "v2").expect("replace");
+        assert_eq!(dir.route(&health), Some(&"v2"));
+        assert_eq!(dir.route(&live), Some(&"v2"));
+
+        let missing = [segment("missing")];
+        assert!(dir.alias(&missing, &[segment("other")]).is_err());
+        assert!(dir.alias(&status, &health).is_err());
+        assert!(dir.insert(&health, "v3").is_err());
+    }
+
+    #[test]
+    fn dir_rejects_mounts_at_reserved_prefixes() {
+        let state_scalar = parse_route_path("/state/scalar").expect("path");
+        let err = Dir::from_routes([(state_scalar.clone(), "v1")]).unwrap_err();
+        assert!(
+            err.message().contains("the prefix /state is reserved"),
+            "{err}"
+        );
+
+        let err = tc_library_routes!("/state/scalar/value" => "v1").unwrap_err();
+        assert!(err.message().contains("reserved"), "{err}");
+
+        let mut dir = Dir::new();
+        dir.insert(&[segment("status")], HelloHandler)
+            .expect("mount");
+        assert!(dir.alias(&[segment("status")], &state_scalar).is_err());
+
+        let admin = parse_route_path("/admin").expect("path");
+        let mut dir = Dir::new().with_reserved_prefixes([admin.clone()]);
+        dir.insert(&state_scalar, HelloHandler)
+            .expect("/state is no longer reserved");
+        assert!(dir
+            .insert(
+                &parse_route_path("/admin/users").expect("path"),
+                HelloHandler
+            )
+            .is_err());
+        assert_eq!(dir.reserved_prefixes(), [admin]);
+    }
+
+    #[test]
+    fn validate_route_path_matches_parse_route_path() {
+        let valid = ["/status", "status", "/lib/acme/hello", " /padded "];
+        for path in valid {
+            assert!(is_valid_route_path(path), "{path:?}");
+            assert!(validate_route_path(path).is_ok(), "{path:?}");
+            assert!(parse_route_path(path).is_ok(), "{path:?}");
+        }
+
+        let invalid = [
+            "", "/", "//", "/a//b", "/a/b/", "/a\nb", "/a\tb", "/a b", "/a/../b",
+        ];
+        for path in invalid {
+            assert!(!is_valid_route_path(path), "{path:?}");
+            let err = validate_route_path(path).expect_err("invalid path");
+            let parse_err = parse_route_path(path).expect_err("invalid path");
+            assert_eq!(err.message(), parse_err.message());
+        }
+    }
+
+    #[test]
+    fn value_eq_coerces_numbers_to_a_common_type() {
+        let number = |n: Number| Scalar::Value(Value::Number(n));
+
+        let one = [
+            Number::from(1u64),
+            Number::from(1i64),
+            Number::from(1.0f64),
+            Number::from(true),
+        ];
+
+        for l in one {
+            for r in one {
+                assert!(number(l).value_eq(&number(r)), "{l} != {r}");
+            }
+        }
+
+        let two = number(Number::from(2.0f64));
+        assert!(two.value_eq(&number(Number::from(2i64))));
+        assert!(!two.value_eq(&number(Number::from(2.5f64))));
+        assert!(!two.value_eq(&number(Number::from(1u64))));
+
+        let nested = |n: Number| -> Scalar {
+            Scalar::Tuple(vec![
+                number(n),
+                Scalar::Map(Map::from_iter([(
+                    "n".parse::<Id>().expect("id"),
+                    number(n),
+                )])),
+            ])
+        };
+
+        assert!(nested(Number::from(3u64)).value_eq(&nested(Number::from(3.0f64))));
+        assert!(!nested(Number::from(3u64)).value_eq(&nested(Number::from(4i64))));
+        assert!(!number(Number::from(1u64)).value_eq(&Scalar::from(Value::from("1"))));
+    }
+
+    #[test]
+    fn flatten_roundtrips_a_nested_map_with_a_tuple() {
+        let scalar: Scalar = decode_json(
+            r#"{"server": {"name": "alpha", "ports": [80, 443, {"tls": "on"}]}, "debug": 1}"#,
+        )
+        .expect("scalar");
+
+        let flat = scalar.flatten().expect("flatten");
+        assert_eq!(
+            flat.keys().map(String::as_str).collect::<Vec<_>>(),
+            [
+                "debug",
+                "server.name",
+                "server.ports.0",
+                "server.ports.1",
+                "server.ports.2.tls",
+            ]
+        );
+        assert_eq!(flat["server.ports.2.tls"], Value::from("on"));
+
+        assert_eq!(Scalar::unflatten(flat).expect("unflatten"), scalar);
+
+        let leaf = Scalar::from(Value::from("x"));
+        let flat = leaf.flatten().expect("flatten leaf");
+        assert_eq!(flat.keys().collect::<Vec<_>>(), [""]);
+        assert_eq!(Scalar::unflatten(flat).expect("unflatten leaf"), leaf);
+
+        let opref: Scalar =
+            decode_json(r#"{"child": {"/lib/acme/hello": ["key"]}}"#).expect("op ref");
+        assert!(opref.flatten().is_err());
+
+        let gap = BTreeMap::from([
+            ("a.0".to_string(), Value::from(1u64)),
+            ("a.2".to_string(), Value::from(2u64)),
+        ]);
+        assert!(Scalar::unflatten(gap).is_err());
+
+        let overlap = BTreeMap::from([
+            ("a".to_string(), Value::from(1u64)),
+            ("a.b".to_string(), Value::from(2u64)),
+        ]);
+        assert!(Scalar::unflatten(overlap).is_err());
+    }
+
+    #[test]
+    fn concurrent_txn_ids_share_a_timestamp_and_nonce() {
+        let at = |nanos, nonce, trace: u8| {
+            TxnId::from_parts(NetworkTime::from_nanos(nanos), nonce).with_trace([trace; 32])
+        };
+
+        let a = at(10, 0, 1);
+        let b = at(10, 0, 2);
+        let c = at(10, 1, 1);
+        let d = at(11, 0, 0);
+
+        assert!(a.is_concurrent_with(&b));
+        assert!(b.is_concurrent_with(&a));
+        assert!(!a.is_concurrent_with(&a));
+        assert!(!a.is_concurrent_with(&c));
+        assert!(!c.is_concurrent_with(&d));
+
+        let mut batch = [d, c, b, a];
+        sort_txn_ids(&mut batch);
+        assert_eq!(batch, [a, b, c, d]);
+    }
+
+    #[test]
+    fn opdef_forms_decode_from_ordered_pairs_preserving_order() {
+        let form = r#"[["zeta", 1], ["alpha", {"$zeta": []}], ["mid", 2], ["alpha", 3]]"#;
+
+        for (verb, json) in [
+            (
+                Method::Get,
+                format!(r#"{{"/state/scalar/op/get": ["key", {form}]}}"#),
+            ),
+            (
+                Method::Put,
+                format!(r#"{{"/state/scalar/op/put": ["key", "value", {form}]}}"#),
+            ),
+            (
+                Method::Post,
+                format!(r#"{{"/state/scalar/op/post": {form}}}"#),
+            ),
+            (
+                Method::Delete,
+                format!(r#"{{"/state/scalar/op/delete": ["key", {form}]}}"#),
+            ),
+        ] {
+            let op: OpDef = decode_json(&json).expect(&json);
+            assert_eq!(op.verb(), verb, "{json}");
+
+            let ids = op
+                .form()
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, ["zeta", "alpha", "mid", "alpha"], "{json}");
+            assert_eq!(op.form()[3].1, scalar!(3));
+
+            // re-encoding keeps the pairs in order (a map would sort or dedupe them)
+            let encoded = encode_json(op.clone());
+            assert_eq!(decode_json::<OpDef>(&encoded).expect("roundtrip"), op);
+            assert!(
+                encoded.find("\"zeta\"") < encoded.find("\"mid\""),
+                "{encoded}"
+            );
+        }
+
+        let map_form = r#"{"/state/scalar/op/post": {"zeta": 1, "alpha": 2}}"#;
+        assert!(decode_json::<OpDef>(map_form).is_err());
+    }
+
+    #[test]
+    fn evaluate_op_def_resolves_bindings_in_order() {
+        struct Doubler;
+
+        #[async_trait::async_trait]
+        impl Evaluator for Doubler {
+            async fn resolve(
+                &self,
+                _txn: &dyn Transaction,
+                subject: &Subject,
+                args: &OpArgs,
+            ) -> TCResult<Scalar> {
+                assert_eq!(subject.to_string(), "/lib/math/double");
+
+                match args {
+                    OpArgs::Seq(args) => match args.as_slice() {
+                        [Scalar::Value(Value::Number(n))] => {
+                            Ok(Scalar::Value(Value::Number(*n + *n)))
+                        }
+                        other => panic!("unexpected args {other:?}"),
+                    },
+                    OpArgs::Map(_) => panic!("unexpected POST"),
+                }
+            }
+        }
+
+        let op: OpDef = decode_json(
+            r#"{"/state/scalar/op/post": [
+                ["a", {"/lib/math/double": [2]}],
+                ["b", {"/lib/math/double": [{"$a": []}]}]
+            ]}"#,
+        )
+        .expect("op def");
+
+        let txn = FakeTxn::new(Claim::new(
+            Link::from_str("/lib/math").expect("link"),
+            umask::Mode::all(),
+        ));
+
+        let result = futures::executor::block_on(evaluate_op_def(&Doubler, &txn, &op, Map::new()));
+        assert_eq!(
+            result.expect("evaluate"),
+            Scalar::Value(Value::Number(Number::from(8u64)))
+        );
+
+        let unbound: OpDef = decode_json(
+            r#"{"/state/scalar/op/post": [["b", {"/lib/math/double": [{"$a": []}]}]]}"#,
+        )
+        .expect("op def");
+
+        let err =
+            futures::executor::block_on(evaluate_op_def(&Doubler, &txn, &unbound, Map::new()))
+                .expect_err("unbound id");
+        assert_eq!(err.code(), tc_error::ErrorKind::NotFound);
+
+        // the op's parameters are in scope
+        let get: OpDef = decode_json(
+            r#"{"/state/scalar/op/get": ["key", [
+                ["doubled", {"/lib/math/double": [{"$key": []}]}]
+            ]]}"#,
+        )
+        .expect("op def");
+
+        let params = [(Id::from_str("key").expect("id"), scalar!(5))]
+            .into_iter()
+            .collect::<Map<_>>();
+
+        let result = futures::executor::block_on(evaluate_op_def(&Doubler, &txn, &get, params));
+        assert_eq!(result.expect("evaluate"), scalar!(10u64));
+
+        // a `$id/path` subject must be bound to a link
+        let not_a_link: OpDef = decode_json(
+            r#"{"/state/scalar/op/post": [
+                ["math", 1],
+                ["b", {"$math/double": [2]}]
+            ]}"#,
+        )
+        .expect("op def");
+
+        let err =
+            futures::executor::block_on(evaluate_op_def(&Doubler, &txn, &not_a_link, Map::new()))
+                .expect_err("subject bound to a number");
+        assert!(err.message().contains("not a link"), "{err}");
+    }
+
+    #[test]
+    fn txn_id_advance_rolls_over_the_nonce_space() {
+        let start =
+            TxnId::from_parts(NetworkTime::from_nanos(100), u16::MAX - 2).with_trace([9u8; 32]);
+
+        let mut ids = vec![start];
+        for _ in 0..5 {
+            let next = ids.last().expect("id").advance();
+            ids.push(next);
+        }
+
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| id.trace_bytes() == &[9u8; 32]));
+
+        assert_eq!(ids[2].timestamp(), NetworkTime::from_nanos(100));
+        assert_eq!(ids[2].nonce(), u16::MAX);
+        assert_eq!(ids[3].timestamp(), NetworkTime::from_nanos(101));
+        assert_eq!(ids[3].nonce(), 0);
+        assert_eq!(ids[5].nonce(), 2);
+    }
+
+    #[test]
+    fn map_try_map_converts_values_and_short_circuits() {
+        let as_u64 = |scalar: Scalar| -> TCResult<u64> {
+            match scalar {
+                Scalar::Value(Value::Number(Number::UInt(n))) => Ok(u64::from(n)),
+                Scalar::Value(Value::Number(Number::Int(n))) => u64::try_from(i64::from(n))
+                    .map_err(|_| TCError::unexpected(n, "a non-negative integer")),
+                other => Err(TCError::unexpected(other, "a non-negative integer")),
+            }
+        };
+
+        let params: Map<Scalar> = decode_json(r#"{"limit": 10, "offset": 20}"#).expect("params");
+        let params = params.try_map(as_u64).expect("u64 params");
+        assert_eq!(
+            params.into_inner().into_values().collect::<Vec<_>>(),
+            [10, 20]
+        );
+
+        let mut calls = 0;
+        let params: Map<Scalar> = decode_json(r#"{"a": 1, "b": "two", "c": 3}"#).expect("params");
+        let result = params.try_map(|scalar| {
+            calls += 1;
+            as_u64(scalar)
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn shared_dir_snapshot_is_unaffected_by_reload() {
+        let shared = SharedDir::new(tc_library_routes!("/hello" => "v1").expect("routes"));
+        let hello = parse_route_path("/hello").expect("path");
+        let bye = parse_route_path("/bye").expect("path");
+
+        let snapshot = shared.snapshot();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let dir = tc_library_routes!("/hello" => "v2", "/bye" => "v2").expect("routes");
+                shared.reload(dir.snapshot());
+            });
+        });
+
+        assert_eq!(snapshot.route(&hello), Some(&"v1"));
+        assert_eq!(snapshot.route(&bye), None);
+
+        assert_eq!(shared.route(&hello), Some("v2"));
+        assert_eq!(shared.route(&bye), Some("v2"));
+    }
+
+    #[test]
+    fn as_u64_lossy_accepts_only_integral_floats() {
+        let number = |n: Number| Scalar::Value(Value::Number(n));
+
+        assert_eq!(number(Number::from(3u64)).as_u64_lossy(), Some(3));
+        assert_eq!(number(Number::from(3i64)).as_u64_lossy(), Some(3));
+        assert_eq!(number(Number::from(-3i64)).as_u64_lossy(), None);
+        assert_eq!(number(Number::from(3.0f64)).as_u64_lossy(), Some(3));
+        assert_eq!(number(Number::from(3.5f64)).as_u64_lossy(), None);
+        assert_eq!(number(Number::from(-3.0f64)).as_u64_lossy(), None);
+        assert_eq!(number(Number::from(f64::NAN)).as_u64_lossy(), None);
+        assert_eq!(number(Number::from(1e20f64)).as_u64_lossy(), None);
+        assert_eq!(Scalar::from(Value::from("3")).as_u64_lossy(), None);
+
+        assert_eq!(u64::try_from(number(Number::from(3u64))).expect("u64"), 3);
+        assert!(u64::try_from(number(Number::from(3.0f64))).is_err());
+        assert!(u64::try_from(number(Number::from(-3i64))).is_err());
+    }
+
+    #[test]
+    fn claim_builder_matches_symbolic_modes() {
+        let link = Link::from_str("/lib/acme").expect("link");
+
+        let rwx = Claim::builder(link.clone())
+            .read()
+            .write()
+            .execute()
+            .build();
+        assert_eq!(rwx.mask, Claim::parse_mode("rwx").expect("rwx"));
+        assert!(rwx.allows(&link, claim::READ));
+        assert!(rwx.allows(&link, claim::WRITE));
+        assert!(rwx.allows(&link, claim::EXECUTE));
+
+        let read_only = Claim::builder(link.clone()).read().build();
+        assert_eq!(read_only.mask, Claim::parse_mode("r--").expect("r--"));
+        assert!(read_only.allows(&link, claim::READ));
+        assert!(!read_only.allows(&link, claim::WRITE));
+        assert!(!read_only.allows(&link, claim::EXECUTE));
+
+        for symbolic in ["rwx", "r--", "-w-", "r-x", "---"] {
+            let mask = Claim::parse_mode(symbolic).expect("mode");
+            assert_eq!(Claim::format_mode(mask), symbolic);
+        }
+
+        for invalid in ["", "rw", "rwxr", "wrx", "R--", "rwx------", "rwxrwxrw"] {
+            assert!(Claim::parse_mode(invalid).is_err(), "{invalid:?}");
+        }
+
+        assert_eq!(Claim::format_mode(claim::ADMIN), "rwxrwxrwx");
+        assert_eq!(Claim::parse_mode("rwxrwxrwx").expect("admin"), claim::ADMIN);
+        assert_ne!(Claim::parse_mode("rwx").expect("rwx"), claim::ADMIN);
+
+        // every mask of permission bits round-trips, and so does its symbolic form
+        for bits in 0..=0o777_u32 {
+            let mask = umask::Mode::from(bits);
+            let symbolic = Claim::format_mode(mask);
+            assert_eq!(
+                Claim::parse_mode(&symbolic).expect("mode"),
+                mask,
+                "{bits:o}"
+            );
+            assert_eq!(symbolic.len() == 3, bits & 0o077 == 0, "{symbolic}");
+        }
+    }
+
+    #[test]
+    fn registry_dispatch_formats_a_missing_route_as_an_error_body() {
+        let registry = LibraryRegistry::<FakeTxn>::new();
+        let txn = FakeTxn::new(Claim::new(
+            Link::from_str("/lib/en").expect("link"),
+            umask::Mode::all(),
+        ));
+
+        let link = Link::from_str("/lib/en/missing").expect("link");
+        let (kind, body) = futures::executor::block_on(registry.dispatch(
+            Method::Get,
+            &txn,
+            &link,
+            Scalar::none(),
+        ))
+        .expect_err("missing route");
+
+        assert_eq!(kind, tc_error::ErrorKind::NotFound);
+
+        let Scalar::Map(mut body) = body else {
+            panic!("expected an error map, not {body:?}");
+        };
+
+        assert_eq!(
+            body.require("code").expect("code"),
+            Scalar::from(Value::from("not_found"))
+        );
+        assert!(matches!(
+            body.require("message").expect("message"),
+            Scalar::Value(Value::String(message)) if message.contains("/lib/en/missing")
+        ));
+
+        struct PathOnly;
+
+        impl ErrorHandler for PathOnly {
+            fn on_error(&self, method: Method, path: &[PathSegment], err: TCError) -> Scalar {
+                assert_eq!(method, Method::Put);
+                assert_eq!(err.code(), tc_error::ErrorKind::NotFound);
+                Scalar::from(Value::String(Path::from(path).to_string()))
+            }
+        }
+
+        let registry = LibraryRegistry::<FakeTxn>::new().with_error_handler(PathOnly);
+        let (kind, body) = futures::executor::block_on(registry.dispatch(
+            Method::Put,
+            &txn,
+            &link,
+            Scalar::none(),
+        ))
+        .expect_err("missing route");
+
+        assert_eq!(kind, tc_error::ErrorKind::NotFound);
+        assert_eq!(body, Scalar::from(Value::from("/lib/en/missing")));
+    }
+
+    #[test]
+    fn interned_dir_shares_segment_allocations() {
+        let routes = || {
+            (0..1000).map(|i| {
+                let path = format!("/lib/acme/v{}/item{}/get", i % 10, i / 10);
+                (parse_route_path(&path).expect("path"), i)
+            })
+        };
+
+        let allocations = |dir: &Dir<i32>| {
+            dir.paths()
+                .flatten()
+                .map(|segment| segment.as_str().as_ptr())
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+        };
+
+        let mut interned = Dir::new().with_interned_segments();
+        interned.try_extend(routes()).expect("routes");
+
+        let plain = Dir::from_routes(routes()).expect("routes");
+
+        // one each for "lib", "acme", and "get", plus "v0".."v9" and "item0".."item99"
+        assert_eq!(allocations(&interned), 113);
+        // "get" is repeated under every item, and each item under every version
+        assert_eq!(allocations(&plain), 2012);
+
+        let path = parse_route_path("/lib/acme/v3/item42/get").expect("path");
+        assert_eq!(interned.route(&path), plain.route(&path));
+        assert_eq!(
+            interned.paths().collect::<Vec<_>>(),
+            plain.paths().collect::<Vec<_>>()
+        );
+    }
+
+    /// Sums a PUT body of unsigned integers as it streams in.
+    struct SumHandler;
+
+    impl HandlePutStream<FakeTxn> for SumHandler {
+        type Response = (usize, u64);
+
+        fn put_stream<'a, D>(
+            &'a self,
+            _txn: &'a FakeTxn,
+            body: &'a mut D,
+        ) -> TCResult<futures::future::BoxFuture<'a, TCResult<Self::Response>>>
+        where
+            D: destream::de::Decoder + 'a,
+        {
+            Ok(Box::pin(async move {
+                let mut sum = 0;
+                let len = decode_elements(body, |n: u64| {
+                    sum += n;
+                    futures::future::ready(Ok(()))
+                })
+                .await?;
+
+                Ok((len, sum))
+            }))
+        }
+    }
+
+    #[test]
+    fn streaming_put_handler_consumes_elements_as_they_arrive() {
+        use futures::StreamExt;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        const N: u64 = 1_000;
+
+        // deliver the body one element per chunk, counting how many chunks have been read
+        let body = |pulled: Arc<AtomicUsize>, last: &str| {
+            let chunks = (0..N)
+                .map(|i| format!("{}{i}", if i == 0 { '[' } else { ',' }))
+                .chain([last.to_string()])
+                .collect::<Vec<_>>();
+
+            let source = futures::stream::iter(chunks).map(move |chunk| {
+                pulled.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, destream_json::de::Error>(bytes::Bytes::from(chunk))
+            });
+
+            destream_json::de::Decoder::from_stream(source)
+        };
+
+        let txn = FakeTxn::new(Claim::new(
+            Link::from_str("/lib/acme").expect("link"),
+            claim::WRITE,
+        ));
+
+        let mut decoder = body(Arc::default(), "]");
+        let fut = SumHandler.put_stream(&txn, &mut decoder).expect("PUT");
+        let (len, sum) = futures::executor::block_on(fut).expect("sum");
+        assert_eq!((len, sum), (N as usize, N * (N - 1) / 2));
+
+        // the first element is handled long before the last chunk is read
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let mut decoder = body(pulled.clone(), "]");
+        let mut pulled_at_first = None;
+        let len = futures::executor::block_on(decode_elements(&mut decoder, |_: u64| {
+            pulled_at_first.get_or_insert(pulled.load(Ordering::SeqCst));
+            futures::future::ready(Ok(()))
+        }))
+        .expect("len");
+
+        assert_eq!(len, N as usize);
+        assert!(pulled_at_first.expect("first element") < 10);
+
+        let mut decoder = body(Arc::default(), ",\"x\"]");
+        let fut = SumHandler.put_stream(&txn, &mut decoder).expect("PUT");
+        let err = futures::executor::block_on(fut).expect_err("invalid element");
+        assert!(err.message().contains("invalid streamed request"), "{err}");
+
+        // the callback's future is awaited before the next element is decoded
+        let mut decoder = body(Arc::default(), "]");
+        let mut handled = 0;
+        let err = futures::executor::block_on(decode_elements(&mut decoder, |n: u64| {
+            handled += 1;
+            async move {
+                if n < 3 {
+                    Ok(())
+                } else {
+                    Err(TCError::bad_request("storage is full"))
+                }
+            }
+        }))
+        .expect_err("full");
+
+        assert_eq!(handled, 4);
+        assert!(err.message().contains("storage is full"), "{err}");
+    }
+
+    #[test]
+    fn strict_decode_rejects_unknown_scalar_classes() {
+        let decode = |options: DecodeOptions, json: &str| {
+            let source = futures::stream::iter([Ok::<_, destream_json::de::Error>(
+                bytes::Bytes::from(json.to_string()),
+            )]);
+
+            let mut decoder = destream_json::de::Decoder::from_stream(source);
+            futures::executor::block_on(<Scalar as destream::de::FromStream>::from_stream(
+                options,
+                &mut decoder,
+            ))
+        };
+
+        let typo = r#"{"/state/scalar/value/numbr": [3]}"#;
+
+        let lenient = decode(DecodeOptions::default(), typo).expect("op ref");
+        assert!(matches!(lenient, Scalar::Ref(ref r) if matches!(**r, TCRef::Op(_))));
+
+        let err = decode(DecodeOptions::strict(), typo).expect_err("strict");
+        assert!(
+            err.to_string().contains("/state/scalar/value/numbr"),
+            "{err}"
+        );
+
+        let nested = r#"{"x": [{"/lib/acme": {"n": {"/state/scalar/value/numbr": [3]}}}]}"#;
+        let err = decode(DecodeOptions::strict(), nested).expect_err("nested");
+        assert!(err.to_string().starts_with("at x[0].n: "), "{err}");
+
+        // the options are the decode context, so they also apply to a nested type like an OpDef
+        let op =
+            r#"{"/state/scalar/op/get": ["key", [["n", {"/state/scalar/value/numbr": [3]}]]]}"#;
+        let source = futures::stream::iter([Ok::<_, std::io::Error>(bytes::Bytes::from(op))]);
+        let err = futures::executor::block_on(destream_json::try_decode::<_, _, OpDef>(
+            DecodeOptions::strict(),
+            source,
+        ))
+        .expect_err("strict op");
+        assert!(err.to_string().starts_with("at form[0]: unknown"), "{err}");
+
+        let typo = r#"{"/state/scalar/ref/op/get": ["/state/scalar/value/number/ad", [1, 2]]}"#;
+        let err = decode(DecodeOptions::strict(), typo).expect_err("typo");
+        assert!(err.to_string().contains("number/ad"), "{err}");
+        decode(DecodeOptions::default(), typo).expect("lenient");
+
+        for known in [
+            r#"{"/state/scalar/value/number": 3}"#,
+            r#"{"/state/scalar/value/number/add": [[1, 2]]}"#,
+            r#"{"/state/scalar/ref/op/get": ["/state/scalar/value/number/pow", [2, 3]]}"#,
+            r#"{"/state/scalar/reflect/class": [{"$x": []}]}"#,
+            r#"{"/state/collection/table": [{}]}"#,
+            r#"{"/lib/acme/hello": ["world"]}"#,
+        ] {
+            decode(DecodeOptions::strict(), known).expect(known);
+        }
+    }
+
+    #[test]
+    fn decode_without_control_flow_rejects_control_flow_refs() {
+        let decode = |options: DecodeOptions, json: &str| {
+            let source = futures::stream::iter([Ok::<_, destream_json::de::Error>(
+                bytes::Bytes::from(json.to_string()),
+            )]);
+
+            let mut decoder = destream_json::de::Decoder::from_stream(source);
+            futures::executor::block_on(<Scalar as destream::de::FromStream>::from_stream(
+                options,
+                &mut decoder,
+            ))
+        };
+
+        let options = DecodeOptions::default().without_control_flow();
+
+        for (json, path) in [
+            (
+                r#"{"/state/scalar/ref/if": [{"$x": []}, 1, 2]}"#,
+                "/state/scalar/ref/if",
+            ),
+            (
+                r#"{"/state/scalar/op/post": [["i", {"/state/scalar/ref/while": [true, 1, 2]}]]}"#,
+                "/state/scalar/ref/while",
+            ),
+            (
+                r#"{"x": [{"/state/scalar/ref/for_each": [[1, 2], {"$f": []}, "i"]}]}"#,
+                "/state/scalar/ref/for_each",
+            ),
+        ] {
+            let err = decode(options, json).expect_err(json);
+            assert!(err.to_string().contains(path), "{err}");
+            decode(DecodeOptions::default(), json).expect(json);
+        }
+
+        // the key is rejected before its arguments are decoded, so a bad argument list is moot
+        let err = decode(options, r#"{"/state/scalar/ref/while": 5}"#).expect_err("while");
+        assert!(err.to_string().contains("control-flow"), "{err}");
+
+        // the policy travels with the decode context into nested types like an OpDef
+        let op =
+            r#"{"/state/scalar/op/get": ["key", [["n", {"/state/scalar/ref/if": [true, 1, 2]}]]]}"#;
+        let source = futures::stream::iter([Ok::<_, std::io::Error>(bytes::Bytes::from(op))]);
+        let err =
+            futures::executor::block_on(destream_json::try_decode::<_, _, OpDef>(options, source))
+                .expect_err("op");
+        assert!(
+            err.to_string().starts_with("at form[0]: control-flow"),
+            "{err}"
+        );
+
+        let straight_line = r#"{"/state/scalar/op/post": [
+            ["user", {"/lib/users": ["alice"]}],
+            ["name", {"$user/name": ["key"]}]
+        ]}"#;
+
+        let op = decode(options, straight_line).expect("op refs");
+        assert!(matches!(op, Scalar::Op(_)));
+    }
+
+    #[test]
+    fn form_builder_chains_op_refs() {
+        let id = |name: &str| name.parse::<Id>().expect("id");
+        let link = |link: &str| Subject::Link(Link::from_str(link).expect("link"));
+
+        let mut form = FormBuilder::new();
+        let user = form.bind(
+            id("user"),
+            OpRef::Get((link("/lib/users"), Scalar::from(Value::from("alice")))),
+        );
+        let greeting = form.bind(
+            id("greeting"),
+            OpRef::Post((link("/lib/greet"), Map::one(id("user"), user.into()))),
+        );
+        assert_eq!(greeting.id(), &id("greeting"));
+        assert_eq!(form.form().len(), 2);
+
+        let op = form.into_post();
+        let expected: OpDef = decode_json(
+            r#"{"/state/scalar/op/post": [
+                ["user", {"/lib/users": ["alice"]}],
+                ["greeting", {"/lib/greet": {"user": {"$user": []}}}]
+            ]}"#,
+        )
+        .expect("op def");
+
+        assert_eq!(op, expected);
+        assert_eq!(
+            decode_json::<OpDef>(&encode_json(op.clone())).expect("roundtrip"),
+            op
+        );
+    }
+
+    #[test]
+    fn parameterless_post_roundtrips_as_a_post() {
+        let scalar: Scalar = decode_json(r#"{"/lib/foo": {}}"#).expect("post");
+        let Scalar::Ref(tc_ref) = &scalar else {
+            panic!("expected an op ref, not {scalar:?}");
+        };
+        let TCRef::Op(op_ref) = &**tc_ref else {
+            panic!("expected an op ref, not {tc_ref:?}");
+        };
+
+        assert!(matches!(op_ref, OpRef::Post((_, params)) if params.is_empty()));
+        assert!(op_ref.is_parameterless());
+
+        assert_eq!(encode_json(scalar.clone()), r#"{"/lib/foo":{}}"#);
+        assert_eq!(
+            decode_json::<Scalar>(&encode_json(scalar.clone())).expect("roundtrip"),
+            scalar
+        );
+
+        let subject = Subject::Link(Link::from_str("/lib/foo").expect("link"));
+        assert!(OpRef::Get((subject.clone(), Scalar::none())).is_parameterless());
+        assert!(
+            !OpRef::Get((subject.clone(), Scalar::from(Value::from("key")))).is_parameterless()
+        );
+        assert!(!OpRef::Put((subject, Scalar::none(), Scalar::none())).is_parameterless());
+    }
+
+    #[test]
+    fn id_policy_reports_the_first_violation() {
+        let lowercase = |id: &Id| {
+            if id.as_str().chars().any(|c| c.is_ascii_uppercase()) {
+                Err(TCError::bad_request(format!("id {id} must be lowercase")))
+            } else {
+                Ok(())
+            }
+        };
+
+        let id = |name: &str| Id::from_str(name).expect("id");
+        let none = || Scalar::Value(Value::None);
+
+        let op = OpDef::Get((
+            id("key"),
+            vec![
+                (id("first"), none()),
+                (id("Second"), none()),
+                (id("Third"), none()),
+            ],
+        ));
+
+        let err = op.validate_ids(&lowercase).expect_err("uppercase id");
+        assert!(err.message().contains("Second"), "{err}");
+
+        let nested = OpDef::Post(vec![(
+            id("inner"),
+            Scalar::Op(OpDef::Put((id("key"), id("Value"), vec![]))),
+        )]);
+
+        let err = nested
+            .validate_ids(&lowercase)
+            .expect_err("nested uppercase id");
+        assert!(err.message().contains("Value"), "{err}");
+
+        let ok = OpDef::Post(vec![(id("data"), Scalar::Map(Map::one(id("Key"), none())))]);
+
+        assert!(ok.validate_ids(&lowercase).is_ok());
+
+        let params = Map::<Scalar>::from_iter([(id("a"), none()), (id("B"), none())]);
+        assert!(params.validate_keys(&lowercase).is_err());
+    }
+
+    #[test]
+    fn size_report_attributes_string_bytes_to_strings() {
+        let text = "lorem ipsum dolor sit amet ".repeat(40);
+        let scalar: Scalar = decode_json(&format!(
+            r#"{{"title": "{text}", "body": ["{text}", "{text}"], "count": 3, "next": {{"/lib/foo": {{"n": 1}}}}}}"#
+        ))
+        .expect("scalar");
+
+        let report = scalar.size_report();
+        let encoded = encode_json(scalar).len();
+
+        assert!(report.strings * 10 > report.total() * 9, "{report}");
+        assert!(report.numbers > 0 && report.refs > 0 && report.map_keys > 0);
+
+        // not byte-exact, but close to the real encoding
+        assert!(
+            report.total().abs_diff(encoded) * 20 < encoded,
+            "{report}\nvs {encoded}"
+        );
+    }
+
+    #[test]
+    fn subject_parse_errors_name_the_problem() {
+        for (subject, message) in [
+            ("", "empty subject"),
+            ("$", "ref id must follow '$'"),
+            ("$/foo", "missing ref id before '/'"),
+            ("$/", "missing ref id before '/'"),
+        ] {
+            let err = scalar::subject_from_str(subject).expect_err(subject);
+            assert_eq!(err.message(), message, "{subject:?}");
+        }
+
+        let err = decode_json::<Subject>(r#""$/foo""#).expect_err("subject");
+        assert!(
+            err.to_string().contains("missing ref id before '/'"),
+            "{err}"
+        );
+
+        let err = decode_json::<Scalar>(r#"{"$/foo": [1]}"#).expect_err("op ref");
+        assert!(
+            err.to_string().contains("missing ref id before '/'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn subject_from_link_and_id_ref() {
+        let link = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
+        let subject = Subject::from(link.clone());
+        assert_eq!(subject, Subject::Link(link));
+        assert_eq!(subject.to_string(), "/lib/acme/foo/1.0.0");
+
+        let id_ref = IdRef::from_str("$self").expect("id ref");
+        let subject = Subject::from(id_ref.clone());
+        assert_eq!(subject, Subject::Ref(id_ref, PathBuf::default()));
+        assert_eq!(subject.to_string(), "$self");
+
+        for subject in [
+            Subject::from(Link::from_str("/lib/acme/foo/1.0.0").expect("link")),
+            Subject::from(IdRef::from_str("$self").expect("id ref")),
+        ] {
+            let encoded = encode_json(subject.clone());
+            assert_eq!(decode_json::<Subject>(&encoded).expect("subject"), subject);
+        }
+    }
+
+    #[test]
+    fn decode_stats_count_collections_and_depth() {
+        let decode = |json: &str| {
+            let source = futures::stream::iter([Ok::<_, destream_json::de::Error>(
+                bytes::Bytes::from(json.to_string()),
+            )]);
+
+            let mut decoder = destream_json::de::Decoder::from_stream(source);
+            futures::executor::block_on(Scalar::from_stream_with_stats(
+                DecodeOptions::default(),
+                &mut decoder,
+            ))
+        };
+
+        let json = r#"{"a": [1, {"b": []}], "c": {"/lib/foo": [2]}}"#;
+        let (scalar, stats) = decode(json).expect("scalar");
+        assert_eq!(scalar, decode_json::<Scalar>(json).expect("scalar"));
+
+        assert_eq!(
+            stats,
+            DecodeStats {
+                maps: 3,
+                seqs: 3,
+                max_depth: 4,
+            }
+        );
+
+        let (_, stats) = decode("\"flat\"").expect("string");
+        assert_eq!(stats, DecodeStats::default());
+
+        // two decodes joined on the same thread keep their own counts
+        let chunked = |json: &'static str| {
+            let source = futures::stream::iter(json.as_bytes().chunks(1).map(|chunk| {
+                Ok::<_, destream_json::de::Error>(bytes::Bytes::copy_from_slice(chunk))
+            }));
+
+            async move {
+                let mut decoder = destream_json::de::Decoder::from_stream(source);
+                Scalar::from_stream_with_stats(DecodeOptions::default(), &mut decoder).await
+            }
+        };
+
+        let (deep, flat) = futures::executor::block_on(futures::future::join(
+            chunked("[[[[1]]]]"),
+            chunked("{\"a\": 1, \"b\": 2}"),
+        ));
+
+        let (_, deep) = deep.expect("deep");
+        let (_, flat) = flat.expect("flat");
+
+        assert_eq!(
+            deep,
+            DecodeStats {
+                maps: 0,
+                seqs: 4,
+                max_depth: 4,
+            }
+        );
+
+        assert_eq!(
+            flat,
+            DecodeStats {
+                maps: 1,
+                seqs: 0,
+                max_depth: 1,
+            }
+        );
+    }
+
+    mod shadowed {
+        // shadows the `tc_error` and `destream` crates, which the exported macros must not
+        // depend on
+        #[allow(dead_code)]
+        mod tc_error {}
+        #[allow(dead_code)]
+        mod destream {}
+
+        pub struct Echo;
+
+        crate::tc_handler! {
+            impl HandleGet<super::FakeTxn> for Echo {
+                async fn get(&self, _txn, message: String) -> Result<String, ::tc_error::TCError> {
+                    Ok(message)
+                }
+            }
+
+            impl HandlePost<super::FakeTxn> for Echo {
+                async fn post(
+                    &self,
+                    _txn,
+                    scalar: crate::Scalar,
+                ) -> Result<crate::Scalar, ::tc_error::TCError> {
+                    Ok(scalar)
+                }
+            }
+        }
+
+        pub fn routes() -> ::tc_error::TCResult<crate::Dir<&'static str>> {
+            crate::tc_library_routes!("/echo" => "v1")
+        }
+    }
+
+    #[test]
+    fn exported_macros_need_no_tc_error_in_scope() {
+        let txn = FakeTxn::new(Claim::new(
+            Link::from_str("/lib/acme").expect("link"),
+            claim::READ,
+        ));
+
+        let echo = shadowed::Echo.get(&txn, "hi".to_string()).expect("GET");
+        assert_eq!(futures::executor::block_on(echo).expect("echo"), "hi");
+
+        // a request is decoded with the context of its own type, e.g. a scalar's decode options
+        let _: <shadowed::Echo as HandlePost<FakeTxn>>::RequestContext = DecodeOptions::strict();
+
+        let dir = shadowed::routes().expect("routes");
+        let path = parse_route_path("/echo").expect("path");
+        assert!(dir.route(&path).is_some());
+    }
+
+    #[test]
+    fn method_roundtrips_as_an_uppercase_verb() {
+        for method in Method::ALL {
+            let verb = method.to_string();
+            assert_eq!(verb, verb.to_uppercase());
+            assert_eq!(verb.parse::<Method>().expect("verb"), method);
+
+            let json = serde_json::to_string(&method).expect("serde encode");
+            assert_eq!(json, format!("\"{verb}\""));
+            assert_eq!(
+                serde_json::from_str::<Method>(&json).expect("serde decode"),
+                method
+            );
+
+            assert_eq!(encode_json(method), json);
+            assert_eq!(
+                decode_json::<Method>(&json).expect("destream decode"),
+                method
+            );
+        }
+
+        assert!("get".parse::<Method>().is_err());
+        assert!(serde_json::from_str::<Method>("\"PATCH\"").is_err());
+        assert!(decode_json::<Method>("\"PATCH\"").is_err());
+    }
+
+    #[test]
+    fn subject_resolve_ref_appends_the_suffix() {
+        let host = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
+        let bindings = Map::one(Id::from_str("self").expect("id"), host.clone());
+
+        let subject = Subject::Ref(
+            IdRef::from_str("$self").expect("id ref"),
+            PathBuf::from_str("/bar/baz").expect("path"),
+        );
+
+        assert_eq!(
+            subject.resolve_ref(&bindings).expect("resolve").to_string(),
+            "/lib/acme/foo/1.0.0/bar/baz"
+        );
+
+        let bare = Subject::from(IdRef::from_str("$self").expect("id ref"));
+        assert_eq!(bare.resolve_ref(&bindings).expect("resolve"), host);
+
+        let link = Subject::from(Link::from_str("/lib/other").expect("link"));
+        assert_eq!(
+            link.resolve_ref(&Map::new()).expect("link").to_string(),
+            "/lib/other"
+        );
+
+        let unbound = Subject::from(IdRef::from_str("$peer").expect("id ref"));
+        let err = unbound.resolve_ref(&bindings).expect_err("unbound");
+        assert_eq!(err.code(), tc_error::ErrorKind::NotFound);
+        assert!(err.message().contains("$peer"), "{err}");
+    }
+
+    #[test]
+    fn network_time_converts_millis_and_secs_with_overflow_checks() {
+        let max_secs = u64::MAX / 1_000_000_000;
+        let max_millis = u64::MAX / 1_000_000;
+
+        let time = NetworkTime::from_secs(max_secs).expect("max secs");
+        assert_eq!(time.as_nanos(), max_secs * 1_000_000_000);
+        assert_eq!(time.as_secs(), max_secs);
+        assert_eq!(NetworkTime::from_secs(max_secs + 1), None);
+
+        let time = NetworkTime::from_millis(max_millis).expect("max millis");
+        assert_eq!(time.as_millis(), max_millis);
+        assert_eq!(NetworkTime::from_millis(max_millis + 1), None);
+
+        let time = NetworkTime::from_nanos(1_999_999_999);
+        assert_eq!(time.as_secs(), 1);
+        assert_eq!(time.as_millis(), 1_999);
+        assert_eq!(
+            NetworkTime::from_millis(1_500),
+            Some(NetworkTime::from_nanos(1_500_000_000))
+        );
+    }
+
+    #[test]
+    fn claim_maps_named_capabilities_to_mask_bits() {
+        let link = Link::from_str("/lib/acme").expect("link");
+
+        let claim = Claim::from_capabilities(link.clone(), &["read", "write"]).expect("claim");
+        assert_eq!(
+            claim.mask,
+            Claim::builder(link.clone()).read().write().build().mask
+        );
+        assert_eq!(claim.capabilities(), ["read", "write"]);
+
+        let admin = Claim::from_capabilities(link.clone(), &["admin", "read"]).expect("admin");
+        assert_eq!(admin, Claim::grant_admin(link.clone()));
+        assert_eq!(admin.capabilities(), ["admin"]);
+
+        let none = Claim::from_capabilities(link.clone(), &[]).expect("none");
+        assert!(none.capabilities().is_empty());
+
+        let err = Claim::from_capabilities(link, &["read", "deploy"]).expect_err("unknown");
+        assert!(err.message().contains("deploy"), "{err}");
+    }
+
+    #[test]
+    fn scalar_macro_matches_the_hand_built_scalar() {
+        let id = |name: &str| Id::from_str(name).expect("id");
+        let op = TCRef::Op(OpRef::Get((
+            Subject::Link(Link::from_str("/lib/acme/foo").expect("link")),
+            Scalar::from(1_u64),
+        )));
+
+        let built = scalar!({
+            "x": 1,
+            "items": [1, -2, 3.5, "four", true, null, [], [[5]]],
+            "nested": { "empty": {}, "name": "acme" },
+            "op": (op.clone()),
+        });
+
+        let expected = Scalar::Map(Map::from_iter([
+            (id("x"), Scalar::from(1_u64)),
+            (
+                id("items"),
+                Scalar::Tuple(vec![
+                    Scalar::from(1_u64),
+                    Scalar::from(-2_i64),
+                    Scalar::from(3.5),
+                    Scalar::from(Value::from("four")),
+                    Scalar::from(Value::from(true)),
+                    Scalar::none(),
+                    Scalar::Tuple(vec![]),
+                    Scalar::Tuple(vec![Scalar::Tuple(vec![Scalar::from(5_u64)])]),
+                ]),
+            ),
+            (
+                id("nested"),
+                Scalar::Map(Map::from_iter([
+                    (id("empty"), Scalar::Map(Map::new())),
+                    (id("name"), Scalar::from(Value::from("acme"))),
+                ])),
+            ),
+            (id("op"), Scalar::from(op)),
+        ]));
+
+        assert_eq!(built, expected);
+        assert_eq!(
+            scalar!({ "x": 1, "items": [1, 2, 3] }),
+            decode_json::<Scalar>(r#"{"x": 1, "items": [1, 2, 3]}"#).expect("json")
+        );
+
+        assert_eq!(scalar!(null), Scalar::none());
+        assert_eq!(scalar!("s"), Scalar::from(Value::from("s")));
+        assert_eq!(scalar!([]), Scalar::Tuple(vec![]));
+    }
+
+    #[test]
+    fn map_require_all_names_every_missing_param() {
+        let mut params = match scalar!({ "x": 1, "y": 2 }) {
+            Scalar::Map(params) => params,
+            other => panic!("expected a map, not {other:?}"),
+        };
+
+        let err = params
+            .require_all(&["x", "z", "w"])
+            .expect_err("missing params");
+        assert_eq!(err.code(), tc_error::ErrorKind::NotFound);
+        assert!(err.message().contains("z, w"), "{err}");
+        assert_eq!(params.len(), 2);
+
+        assert!(params.require_all(&["x", "x"]).is_err());
+
+        let values = params.require_all(&["y", "x"]).expect("params");
+        assert_eq!(values, [Scalar::from(2), Scalar::from(1)]);
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn delegated_claim_records_its_grantors_in_order() {
+        let link = |link: &str| Link::from_str(link).expect("link");
+
+        let root = DelegatedClaim::from(Claim::grant_admin(link("/lib/acme")));
+        assert!(root.grantors().is_empty());
+        assert_eq!(
+            serde_json::to_string(&root).expect("json"),
+            serde_json::to_string(root.claim()).expect("json")
+        );
+
+        let delegated = root
+            .attenuate(link("/iam/ops"), claim::READ | claim::EXECUTE)
+            .attenuate(link("/iam/team"), claim::READ);
+
+        assert_eq!(delegated.claim().mask, claim::READ);
+        assert_eq!(delegated.grantors(), [link("/iam/ops"), link("/iam/team")]);
+
+        let json = serde_json::to_string(&delegated).expect("json");
+        assert_eq!(json, r#"["/lib/acme",256,["/iam/ops","/iam/team"]]"#);
+        assert_eq!(
+            serde_json::from_str::<DelegatedClaim>(&json).expect("claim"),
+            delegated
+        );
+
+        let other = Claim::new(link("/lib/other"), claim::READ);
+        assert_eq!(delegated.intersect(link("/iam/x"), &other), None);
+
+        let narrowed = delegated
+            .intersect(link("/iam/x"), &Claim::new(link("/lib/acme"), claim::WRITE))
+            .expect("same link");
+        assert_eq!(narrowed.grantors().len(), 3);
+        assert_eq!(u32::from(narrowed.claim().mask), 0);
+    }
+
+    #[test]
+    fn dir_iterates_in_sorted_path_order() {
+        let mut dir = tc_library_routes!(
+            "/status" => "status",
+            "/lib/beta" => "beta",
+            "/lib/acme/hello" => "hello",
+            "/alpha" => "alpha",
+            "/lib/acme/bye" => "bye",
+        )
+        .expect("routes");
+        dir.alias(&[segment("status")], &[segment("health")])
+            .expect("alias");
+
+        let entries = dir
+            .iter()
+            .map(|(path, handler)| (Path::from(path.as_slice()).to_string(), *handler))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            entries,
+            [
+                ("/alpha".to_string(), "alpha"),
+                ("/health".to_string(), "status"),
+                ("/lib/acme/bye".to_string(), "bye"),
+                ("/lib/acme/hello".to_string(), "hello"),
+                ("/lib/beta".to_string(), "beta"),
+                ("/status".to_string(), "status"),
+            ]
+        );
+
+        assert_eq!(dir.paths().count(), entries.len());
+        assert!(Dir::<()>::new().iter().next().is_none());
+    }
+
+    #[test]
+    fn frozen_dir_routes_like_dir() {
+        let mut dir = tc_library_routes!(
+            "/status" => "status",
+            "/lib/acme/hello" => "hello",
+            "/lib/acme/v1/deep/nested/op" => "deep",
+        )
+        .expect("routes");
+
+        dir.alias(&[segment("status")], &[segment("health")])
+            .expect("alias");
+
+        let probes = [
+            "/status",
+            "/health",
+            "/lib/acme/hello",
+            "/lib/acme/v1/deep/nested/op",
+            "/lib/acme",
+            "/lib/acme/hello/extra",
+            "/missing",
+        ]
+        .map(|path| parse_route_path(path).expect("path"));
+
+        let frozen = dir.clone().freeze();
+        assert_eq!(frozen.len(), dir.paths().count());
+
+        for path in probes.iter().chain([&vec![]]) {
+            assert_eq!(frozen.route(path), dir.route(path), "{path:?}");
+        }
+
+        assert_eq!(frozen.route(&probes[1]), Some(&"status"));
+        assert!(Dir::<()>::new().freeze().is_empty());
+    }
+
+    #[test]
+    fn metered_router_counts_route_outcomes() {
+        use std::{cell::RefCell, collections::HashMap, sync::Arc};
+
+        let dir = tc_library_routes! {
+            "/lib/acme/hello" => "hello",
+            "/lib/acme/v1/op" => "op",
+        }
+        .expect("dir");
+
+        let probes = [
+            "/lib/acme/hello",
+            "/lib/acme",
+            "/missing",
+            "/lib/acme/v1/op",
+        ]
+        .map(|path| parse_route_path(path).expect("path"));
+
+        let counts = RefCell::new(HashMap::new());
+        let sink = |outcome| *counts.borrow_mut().entry(outcome).or_insert(0) += 1;
+
+        let expected = HashMap::from([
+            (RouteOutcome::Hit, 2),
+            (RouteOutcome::Directory, 1),
+            (RouteOutcome::NotFound, 1),
+        ]);
+
+        let metered = MeteredRouter::new(Arc::new(dir.clone())).with_metrics(&sink);
+        for path in &probes {
+            metered.route(path);
+        }
+
+        assert_eq!(*counts.borrow(), expected);
+        counts.borrow_mut().clear();
+
+        let frozen = MeteredRouter::new(dir.freeze()).with_metrics(&sink);
+        for path in &probes {
+            frozen.route(path);
+        }
+
+        assert_eq!(*counts.borrow(), expected);
+        assert_eq!(MeteredRouter::new(Dir::<()>::new()).route(&probes[0]), None);
+    }
+
+    #[test]
+    fn dir_explains_where_a_route_diverges() {
+        let dir = tc_library_routes! {
+            "/lib/acme/hello" => "hello",
+            "/lib/beta/hello" => "beta",
+            "/lib/core/status" => "status",
+        }
+        .expect("dir");
+
+        let path = parse_route_path("/lib/acmee/hello").expect("path");
+        let explanation = dir.explain_route(&path);
+
+        assert_eq!(explanation.matched, [segment("lib")]);
+        assert_eq!(explanation.node, RouteNode::Directory);
+        assert_eq!(explanation.unmatched, Some(segment("acmee")));
+        assert_eq!(
+            explanation.siblings,
+            [segment("acme"), segment("beta"), segment("core")]
+        );
+        assert!(!explanation.is_routed());
+        assert_eq!(
+            explanation.to_string(),
+            "nothing is mounted at acmee in /lib (expected one of acme, beta, core)"
+        );
+
+        let routed = dir.explain_route(&parse_route_path("/lib/acme/hello").expect("path"));
+        assert!(routed.is_routed());
+        assert!(routed.siblings.is_empty());
+
+        let too_long = dir.explain_route(&parse_route_path("/lib/acme/hello/x").expect("path"));
+        assert_eq!(too_long.node, RouteNode::Handler);
+        assert_eq!(too_long.matched.len(), 3);
+        assert_eq!(too_long.unmatched, Some(segment("x")));
+
+        let interior = dir.explain_route(&parse_route_path("/lib/core").expect("path"));
+        assert_eq!(interior.unmatched, None);
+        assert_eq!(interior.siblings, [segment("status")]);
+    }
+
+    #[test]
+    fn dir_diff_lists_added_removed_and_changed_routes() {
+        let old = tc_library_routes! {
+            "/lib/acme/hello" => "hello",
+            "/lib/acme/legacy" => "legacy",
+            "/lib/acme/status" => "status",
+        }
+        .expect("old");
+
+        let new = tc_library_routes! {
+            "/lib/acme/hello" => "hello",
+            "/lib/acme/status" => "status v2",
+            "/lib/acme/v2/hello" => "hello v2",
+        }
+        .expect("new");
+
+        let path = |path| parse_route_path(path).expect("path");
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, [path("/lib/acme/v2/hello")]);
+        assert_eq!(diff.removed, [path("/lib/acme/legacy")]);
+        assert_eq!(diff.changed, [path("/lib/acme/status")]);
+        assert_eq!(
+            diff.to_string(),
+            "+ /lib/acme/v2/hello\n- /lib/acme/legacy\n~ /lib/acme/status"
+        );
+
+        let paths = old.diff_paths(&new);
+        assert_eq!((paths.added, paths.removed), (diff.added, diff.removed));
+        assert!(paths.changed.is_empty());
+
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn dir_collects_routes_from_iterator() {
+        let names = ["status", "", "health"];
+        let dir: Dir<HelloHandler> = names
+            .iter()
+            .filter_map(|name| parse_route_path(name).ok())
+            .map(|path| (path, HelloHandler))
+            .collect();
+
+        assert!(dir.route(&[segment("status")]).is_some());
+        assert!(dir.route(&[segment("health")]).is_some());
+
+        let mut dir = dir;
+        dir.extend([(vec![segment("lib"), segment("status")], HelloHandler)]);
+        assert!(dir.route(&[segment("lib"), segment("status")]).is_some());
+
+        let err = dir
+            .try_extend([(vec![segment("status")], HelloHandler)])
+            .unwrap_err();
+        assert!(err.message().contains("already mounted"));
+    }
+
+    #[test]
+    fn check_references_reports_dangling_link() {
+        let dir = tc_library_routes! {
+            "/lib/acme/foo" => HelloHandler,
+        }
+        .expect("routes");
+
+        let get = |path: &str| {
+            let link = Link::from_str(path).expect("link");
+            Scalar::from(TCRef::Op(OpRef::Get((
+                Subject::Link(link),
+                Scalar::default(),
+            ))))
+        };
+
+        let mounted = OpDef::Post(vec![
+            ("a".parse().expect("Id"), get("/lib/acme/foo")),
+            (
+                "b".parse().expect("Id"),
+                Scalar::from(TCRef::Op(OpRef::Get((
+                    Subject::Ref("$a".parse().expect("IdRef"), PathBuf::default()),
+                    Scalar::default(),
+                )))),
+            ),
+        ]);
+        check_references(&mounted, &dir).expect("all links mounted");
+
+        let dangling = OpDef::Post(vec![
+            ("a".parse().expect("Id"), get("/lib/acme/foo")),
+            ("b".parse().expect("Id"), get("/lib/acme/missing")),
+        ]);
+        let err = check_references(&dangling, &dir).unwrap_err();
+        assert!(err.message().contains("/lib/acme/missing"));
+    }
+
+    #[test]
+    fn macro_builds_routes() {
+        let dir = tc_library_routes! {
+            "/lib/status" => HelloHandler,
+        }
+        .expect("macro routes");
+
+        let claim = Claim::new(Link::from_str("/lib").unwrap(), umask::Mode::all());
+        let txn = FakeTxn::new(claim);
+        let path = [segment("lib"), segment("status")];
+        let handler = dir.route(&path).expect("handler");
+        let fut = handler.get(&txn, "macro".into()).expect("GET");
+        let out = futures::executor::block_on(fut).unwrap();
+        assert_eq!(out, "hello macro");
+    }
+
+    #[test]
+    fn scalar_map_roundtrip() {
+        let mut inner = Map::new();
+        inner.insert(
+            "signed".parse().expect("Id"),
+            Scalar::from(Value::Number(Number::Bool(true.into()))),
+        );
+        inner.insert("bits".parse().expect("Id"), Scalar::from(16_u64));
+
+        let mut outer = Map::new();
+        outer.insert(
+            "dtype".parse().expect("Id"),
+            Scalar::from(Value::from("f32")),
+        );
+        outer.insert("encoding".parse().expect("Id"), Scalar::Map(inner));
+
+        let scalar = Scalar::Map(outer);
+
+        let encoded = destream_json::encode(scalar.clone()).expect("encode scalar map");
+        let decoded: Scalar =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode scalar map");
+
+        assert_eq!(decoded, scalar);
+    }
+
+    #[test]
+    fn scalar_walk_paths_reports_breadcrumbs() {
+        let target = Scalar::from(Value::from("bad"));
+        let params = Scalar::Map(Map::one("x".parse::<Id>().expect("Id"), target.clone()));
+        let form = Scalar::Tuple(vec![Scalar::from(1_u64), params]);
+        let scalar = Scalar::Map(Map::one("form".parse::<Id>().expect("Id"), form));
+
+        let (path, _) = scalar
+            .walk_paths()
+            .find(|(_, node)| *node == &target)
+            .expect("nested node");
+
+        assert_eq!(
+            path.steps(),
+            &[
+                ScalarPathStep::Key("form".parse().expect("Id")),
+                ScalarPathStep::Index(1),
+                ScalarPathStep::Key("x".parse().expect("Id")),
+            ]
+        );
+        assert_eq!(path.to_string(), "form[1].x");
+
+        let (root, _) = scalar.walk_paths().next().expect("root");
+        assert!(root.is_root());
+        assert_eq!(scalar.walk_paths().count(), scalar.walk().count());
+    }
+
+    #[test]
+    fn fold_constants_folds_literal_arithmetic_only() {
+        let number_op = |name: &str, args: Vec<Scalar>| {
+            let link = Link::from_str(&format!("/state/scalar/value/number/{name}")).expect("link");
+            Scalar::from(TCRef::Op(OpRef::Get((
+                Subject::Link(link),
+                Scalar::Tuple(args),
+            ))))
+        };
+
+        let add = number_op("add", vec![Scalar::from(1_u64), Scalar::from(2_u64)]);
+        assert_eq!(add.clone().fold_constants(), Scalar::from(3_u64));
+
+        let nested = Scalar::Tuple(vec![number_op(
+            "mul",
+            vec![
+                number_op("add", vec![Scalar::from(1_u64), Scalar::from(2_u64)]),
+                Scalar::from(4_u64),
+            ],
+        )]);
+        assert_eq!(
+            nested.fold_constants(),
+            Scalar::Tuple(vec![Scalar::from(12_u64)])
+        );
+
+        let variable = number_op(
+            "add",
+            vec![
+                Scalar::from(TCRef::Id("$x".parse().expect("IdRef"))),
+                Scalar::from(2_u64),
+            ],
+        );
+        assert_eq!(variable.clone().fold_constants(), variable);
+
+        let overflow = number_op("add", vec![Scalar::from(u64::MAX), Scalar::from(1_u64)]);
+        assert_eq!(overflow.clone().fold_constants(), overflow);
+
+        // the documented wire form, which must be explicit since the subject is under /state/scalar
+        let json = r#"{"/state/scalar/ref/op/get": ["/state/scalar/value/number/add", [1, 2]]}"#;
+        let decoded = decode_json::<Scalar>(json).expect("add");
+        assert_eq!(decoded, add);
+        assert_eq!(decoded.fold_constants(), Scalar::from(3_u64));
+        assert_eq!(encode_json(&add), json.replace(' ', ""));
+    }
+
+    #[test]
+    fn duplicate_keys_fail_to_decode() {
+        let err = decode_json::<Scalar>(r#"{"/lib/acme/greet": {"x": 1, "x": 2}}"#)
+            .expect_err("duplicate parameter");
+        assert!(err.to_string().contains("duplicate parameter 'x'"), "{err}");
+
+        let err = decode_json::<Scalar>(r#"{"x": 1, "y": 2, "x": 3}"#).expect_err("duplicate key");
+        assert!(err.to_string().contains("duplicate key 'x'"), "{err}");
+
+        assert!(decode_json::<Scalar>(r#"{"x": 1, "y": 2}"#).is_ok());
+    }
+
+    #[test]
+    fn alpha_eq_ignores_bound_names_only() {
+        let op = |json: &str| match decode_json::<Scalar>(json).expect("op") {
+            Scalar::Op(op) => op,
+            other => panic!("expected an op, found {other:?}"),
+        };
+
+        let original = op(r#"{"/state/scalar/op/get": ["key", [
+            ["a", {"$self/lookup": [{"$key": []}]}],
+            ["b", {"/state/scalar/op/get": ["x", [["out", {"$a/add": [{"$x": []}]}]]]}],
+            ["result", {"$b": [{"$a": []}]}]
+        ]]}"#);
+
+        let renamed = op(r#"{"/state/scalar/op/get": ["k", [
+            ["tmp", {"$self/lookup": [{"$k": []}]}],
+            ["f", {"/state/scalar/op/get": ["y", [["z", {"$tmp/add": [{"$y": []}]}]]]}],
+            ["out", {"$f": [{"$tmp": []}]}]
+        ]]}"#);
+
+        assert_ne!(original, renamed);
+        assert!(original.alpha_eq(&renamed));
+        assert!(renamed.alpha_eq(&original));
+        assert!(original.alpha_eq(&original));
+
+        // `$self` is free, so renaming it changes the program
+        let free_renamed = op(r#"{"/state/scalar/op/get": ["key", [
+            ["a", {"$other/lookup": [{"$key": []}]}],
+            ["b", {"/state/scalar/op/get": ["x", [["out", {"$a/add": [{"$x": []}]}]]]}],
+            ["result", {"$b": [{"$a": []}]}]
+        ]]}"#);
+        assert!(!original.alpha_eq(&free_renamed));
+
+        // swapping which binding a ref points to is not a renaming
+        let rewired = op(r#"{"/state/scalar/op/get": ["key", [
+            ["a", {"$self/lookup": [{"$key": []}]}],
+            ["b", {"/state/scalar/op/get": ["x", [["out", {"$a/add": [{"$x": []}]}]]]}],
+            ["result", {"$a": ["$b"]}]
+        ]]}"#);
+        assert!(!original.alpha_eq(&rewired));
+    }
+
+    #[test]
+    fn referenced_links_are_deduplicated_and_sorted() {
+        let op = |json: &str| match decode_json::<Scalar>(json).expect("op") {
+            Scalar::Op(op) => op,
+            other => panic!("expected an op, found {other:?}"),
+        };
+
+        let greet = op(r#"{"/state/scalar/op/post": [
+            ["user", {"/lib/users": [{"$name": []}]}],
+            ["audit", {"/lib/audit/log": [{"$user": []}]}],
+            ["again", {"/lib/users": ["admin"]}],
+            ["local", {"$self/greet": [{"$user": []}]}]
+        ]}"#);
+
+        let link = |s: &str| Link::from_str(s).expect("link");
+        let links = greet.referenced_links();
+        assert_eq!(
+            links.into_iter().collect::<Vec<_>>(),
+            [link("/lib/audit/log"), link("/lib/users"),]
+        );
+
+        let notify = op(r#"{"/state/scalar/op/get": ["key", [
+            ["sent", {"/lib/mail/send": [{"$key": []}]}]
+        ]]}"#);
+
+        let dir = Dir::from_routes([
+            (vec![segment("greet")], Some(greet)),
+            (vec![segment("notify")], Some(notify)),
+            (vec![segment("native")], None),
+        ])
+        .expect("dir");
+
+        let schema = LibrarySchema::new(link("/lib/acme"), "0.1.0", vec![]);
+        let library = LibraryModule::<FakeTxn, _>::new(schema, dir);
+        let links = library.referenced_links(Option::as_ref);
+        assert_eq!(links.len(), 3);
+        assert!(links.contains(&link("/lib/mail/send")));
+    }
+
+    #[test]
+    fn opref_from_subject_args_builds_each_verb() {
+        let subject = || Subject::Link(Link::from_str("/lib/acme/counter").expect("link"));
+        let key = Scalar::from(Value::String("hits".into()));
+
+        let get = OpArgs::from(vec![key.clone()]);
+        assert_eq!(
+            OpRef::from_subject_args(subject(), get).expect("GET"),
+            OpRef::Get((subject(), key.clone()))
+        );
+
+        let put = OpArgs::from(vec![key.clone(), Scalar::from(1)]);
+        assert_eq!(
+            OpRef::from_subject_args(subject(), put).expect("PUT"),
+            OpRef::Put((subject(), key.clone(), Scalar::from(1)))
+        );
+
+        let params = Map::one("by".parse::<Id>().expect("id"), Scalar::from(2));
+        let post = OpArgs::from(params.clone());
+        assert_eq!(
+            OpRef::from_subject_args(subject(), post).expect("POST"),
+            OpRef::Post((subject(), params))
+        );
+
+        for items in [vec![], vec![key.clone(), key.clone(), key]] {
+            let err = OpRef::from_subject_args(subject(), OpArgs::from(items)).unwrap_err();
+            assert!(err.message().contains("expected 1 or 2 elements"), "{err}");
+        }
+    }
+
+    #[test]
+    fn rewrite_links_promotes_staging_to_prod() {
+        let link = |s: &str| Link::from_str(s).expect("link");
+        let promote = |l: &Link| {
+            let path = l.to_string();
+            path.strip_prefix("http://staging.example.com")
+                .map(|rest| link(&format!("http://prod.example.com{rest}")))
+        };
+
+        let id = |s: &str| s.parse::<Id>().expect("Id");
+        let op = OpDef::Post(vec![
+            (
+                id("user"),
+                Scalar::from(TCRef::Op(OpRef::Get((
+                    Subject::Link(link("http://staging.example.com/lib/users")),
+                    Scalar::from(Value::Link(link("http://staging.example.com/lib/auth"))),
+                )))),
+            ),
+            (
+                id("upstream"),
+                Scalar::from(Value::Link(link("http://other.example.com/lib/auth"))),
+            ),
+            (
+                id("profile"),
+                Scalar::from(TCRef::Op(OpRef::Get((
+                    Subject::Ref("$user".parse().expect("IdRef"), PathBuf::default()),
+                    Scalar::default(),
+                )))),
+            ),
+            (
+                id("callback"),
+                Scalar::Op(OpDef::Get((
+                    id("key"),
+                    vec![(
+                        id("sent"),
+                        Scalar::from(TCRef::Op(OpRef::Put((
+                            Subject::Link(link("http://staging.example.com/lib/mail")),
+                            Scalar::default(),
+                            Scalar::Tuple(vec![Scalar::from(Value::Link(link(
+                                "http://staging.example.com/lib/templates",
+                            )))]),
+                        )))),
+                    )],
+                ))),
+            ),
+        ]);
+
+        let expected = OpDef::Post(vec![
+            (
+                id("user"),
+                Scalar::from(TCRef::Op(OpRef::Get((
+                    Subject::Link(link("http://prod.example.com/lib/users")),
+                    Scalar::from(Value::Link(link("http://prod.example.com/lib/auth"))),
+                )))),
+            ),
+            (
+                id("upstream"),
+                Scalar::from(Value::Link(link("http://other.example.com/lib/auth"))),
+            ),
+            (
+                id("profile"),
+                Scalar::from(TCRef::Op(OpRef::Get((
+                    Subject::Ref("$user".parse().expect("IdRef"), PathBuf::default()),
+                    Scalar::default(),
+                )))),
+            ),
+            (
+                id("callback"),
+                Scalar::Op(OpDef::Get((
+                    id("key"),
+                    vec![(
+                        id("sent"),
+                        Scalar::from(TCRef::Op(OpRef::Put((
+                            Subject::Link(link("http://prod.example.com/lib/mail")),
+                            Scalar::default(),
+                            Scalar::Tuple(vec![Scalar::from(Value::Link(link(
+                                "http://prod.example.com/lib/templates",
+                            )))]),
+                        )))),
+                    )],
+                ))),
+            ),
+        ]);
+
+        assert_eq!(op.clone().rewrite_links(promote), expected);
+        assert_eq!(Scalar::Op(op).rewrite_links(promote), Scalar::Op(expected));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn library_schema_from_toml_manifest() {
+        let manifest = r#"
+            id = "/lib/acme"
+            version = "1.2.3"
+            description = "ignored"
+
+            [dependencies]
+            users = "/lib/users"
+            billing = "/lib/billing/0.1.0"
+        "#;
+
+        let link = |link: &str| Link::from_str(link).expect("link");
+
+        let schema = LibrarySchema::from_toml_str(manifest).expect("manifest");
+        assert_eq!(schema.id(), &link("/lib/acme"));
+        assert_eq!(schema.version(), "1.2.3");
+        assert_eq!(
+            schema.dependencies(),
+            [link("/lib/billing/0.1.0"), link("/lib/users")]
+        );
+
+        let minimal = LibrarySchema::from_toml_str("id = \"/lib/acme\"\nversion = \"0.1.0-rc.1\"");
+        assert!(minimal.expect("minimal").dependencies().is_empty());
+
+        for invalid in [
+            "id = \"/lib/acme\"",
+            "id = \"/lib/acme\"\nversion = \"one\"",
+            "id = \"/lib/acme\"\nversion = \"1.2\"",
+            "id = \"/lib/acme\"\nversion = \"1.0.0\"\n[dependencies]\nx = \"not a link!\"",
+        ] {
+            assert!(LibrarySchema::from_toml_str(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[cfg(feature = "ndjson")]
+    #[test]
+    fn ndjson_round_trips_a_batch_of_scalars() {
+        let items = [
+            scalar!({"name": "line\nbreak", "path": "C:\\new", "n": 1}),
+            decode_json::<Scalar>(r#"{"/lib/acme/foo": [{"$key": []}]}"#).expect("op ref"),
+            scalar!([1, 2.5, null]),
+        ];
+
+        use futures::{executor::block_on, StreamExt, TryStreamExt};
+
+        let decode = |ndjson: &str| -> Vec<TCResult<Scalar>> {
+            block_on(decode_ndjson(ndjson.as_bytes()).collect())
+        };
+
+        let mut encoded = Vec::new();
+        block_on(encode_ndjson(items.clone(), &mut encoded)).expect("encode");
+
+        let encoded = String::from_utf8(encoded).expect("utf8");
+        assert_eq!(encoded.lines().count(), 3, "{encoded}");
+
+        // each line decodes on its own
+        let second = decode(encoded.lines().nth(1).expect("line"))
+            .into_iter()
+            .collect::<TCResult<Vec<_>>>()
+            .expect("decode line");
+        assert_eq!(second, [items[1].clone()]);
+
+        let ndjson = format!("{encoded}\n");
+        let decoded =
+            block_on(decode_ndjson(ndjson.as_bytes()).try_collect::<Vec<_>>()).expect("decode");
+        assert_eq!(decoded, items);
+
+        let mut lines = decode("1\n{oops\n").into_iter();
+        assert_eq!(
+            lines.next().expect("line 1").expect("valid line"),
+            scalar!(1)
+        );
+        let error = lines.next().expect("line 2").expect_err("invalid line");
+        assert!(error.message().contains("line 2"), "{error}");
+
+        // another producer's escapes are restored before decoding
+        let foreign = r#"{"s": "tab\there \u00e9 \ud83d\ude00 \/ \u0022q\" \\n"}"#;
+        let decoded = decode(foreign)
+            .into_iter()
+            .collect::<TCResult<Vec<_>>>()
+            .expect("foreign escapes");
+        assert_eq!(decoded, [scalar!({"s": "tab\there é 😀 / \"q\" \\n"})]);
+
+        for invalid in [r#""\x""#, r#""\ud83d""#, r#""\u12""#] {
+            let error = decode(invalid)
+                .pop()
+                .expect("line")
+                .expect_err("invalid escape");
+            assert!(error.message().contains("line 1"), "{error}");
+        }
+
+        let nan = [scalar!(1), Scalar::from(f64::NAN)];
+        let error = block_on(encode_ndjson(nan, &mut Vec::new())).expect_err("NaN");
+        assert!(error.message().contains("item 1"), "{error}");
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn scalar_from_yaml_matches_json() {
+        let yaml = "
+/lib/acme/greet:
+  name: world
+  count: 2
+";
+        let json = r#"{"/lib/acme/greet": {"name": "world", "count": 2}}"#;
+        let from_yaml = futures::executor::block_on(Scalar::from_yaml_str(yaml)).expect("YAML");
+        assert_eq!(from_yaml, decode_json::<Scalar>(json).expect("JSON"));
+        assert!(matches!(
+            &from_yaml,
+            Scalar::Ref(r) if matches!(**r, TCRef::Op(OpRef::Post(_)))
+        ));
+
+        let typed = "
+/state/scalar/ref/op/post:
+  - /lib/acme/greet
+  - name: world
+    count: 2
+";
+        assert_eq!(
+            futures::executor::block_on(Scalar::from_yaml_str(typed)).expect("typed YAML"),
+            from_yaml
+        );
+
+        let err = futures::executor::block_on(Scalar::from_yaml_str("key: [unclosed")).unwrap_err();
+        assert!(err.message().contains("invalid YAML scalar"), "{err}");
+    }
+
+    #[test]
+    fn node_count_counts_every_nested_node() {
+        let scalar: Scalar = decode_json(
+            r#"{"a": [1, 2], "b": {"$x": [3]}, "c": {"/state/scalar/op/post": [["y", 4]]}}"#,
+        )
+        .expect("decode");
+
+        // root map, tuple + 2 elements, GET ref + key, POST op + 1 binding
+        assert_eq!(scalar.node_count(), 8);
+        assert_eq!(Scalar::from(1).node_count(), 1);
+
+        let mut deep = Scalar::from(0);
+        for _ in 0..100_000 {
+            deep = Scalar::Tuple(vec![deep]);
+        }
+
+        assert_eq!(deep.node_count(), 100_001);
+        drop_iteratively(deep);
+    }
+
+    #[test]
+    fn heterogeneous_tuple_decodes_each_element_in_tuple_context() {
+        let json = r#"[{"/state/scalar/value/number": 3}, "s", {"/lib/foo": [1]}, [1, [2]]]"#;
+        let tuple: Scalar = decode_json(json).expect("tuple");
+
+        let Scalar::Tuple(items) = &tuple else {
+            panic!("expected a tuple, not {tuple:?}");
+        };
+
+        let get = |link: &str, key: Scalar| {
+            let link = Link::from_str(link).expect("link");
+            Scalar::from(TCRef::Op(OpRef::Get((Subject::Link(link), key))))
+        };
+
+        assert_eq!(
+            items,
+            &[
+                Scalar::from(3_u64),
+                Scalar::from(Value::from("s")),
+                get("/lib/foo", Scalar::from(1_u64)),
+                Scalar::Tuple(vec![
+                    Scalar::from(1_u64),
+                    Scalar::Tuple(vec![Scalar::from(2_u64)]),
+                ]),
+            ]
+        );
+
+        let encoded = encode_json(tuple.clone());
+        assert_eq!(encoded, r#"[3,"s",{"/lib/foo":[1]},[1,[2]]]"#);
+        assert_eq!(decode_json::<Scalar>(&encoded).expect("roundtrip"), tuple);
+
+        // a single-element array is a one-tuple, whatever its element is
+        let single = [
+            (
+                r#"[{"/lib/foo": [1]}]"#,
+                get("/lib/foo", Scalar::from(1_u64)),
+            ),
+            (
+                r#"[{"/lib/foo": [[1]]}]"#,
+                get("/lib/foo", Scalar::Tuple(vec![Scalar::from(1_u64)])),
+            ),
+            (r#"[[1]]"#, Scalar::Tuple(vec![Scalar::from(1_u64)])),
+            (
+                r#"[{"/state/scalar/value/number": 3}]"#,
+                Scalar::from(3_u64),
+            ),
+            (
+                r#"[{"$x": []}]"#,
+                Scalar::from(IdRef::from_str("$x").expect("id ref")),
+            ),
+        ];
+
+        for (json, element) in single {
+            let decoded: Scalar = decode_json(json).expect(json);
+            assert_eq!(decoded, Scalar::Tuple(vec![element]), "{json}");
+
+            let encoded = encode_json(decoded.clone());
+            assert_eq!(
+                decode_json::<Scalar>(&encoded).expect(json),
+                decoded,
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn scalar_replace_at_swaps_one_leaf() {
+        let original: Scalar = decode_json(
+            r#"{"servers": [{"name": "alpha", "port": 8080}, {"name": "beta"}], "debug": 1}"#,
+        )
+        .expect("config");
+
+        let mut config = original.clone();
+        let old = config
+            .replace_at("/servers/0/port", Scalar::from(9090))
+            .expect("replace");
+        assert_eq!(old, Scalar::from(8080));
+
+        let expected: Scalar = decode_json(
+            r#"{"servers": [{"name": "alpha", "port": 9090}, {"name": "beta"}], "debug": 1}"#,
+        )
+        .expect("expected");
+        assert_eq!(config, expected);
+        assert_eq!(
+            config.get_path("/servers/1"),
+            original.get_path("/servers/1")
+        );
+
+        let err = config
+            .replace_at("/servers/0/host", Scalar::none())
+            .expect_err("missing key");
+        assert!(err.message().contains("/servers/0/host"), "{err}");
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn scalar_get_path_descends_maps_and_tuples() {
+        let mut config: Scalar = decode_json(
+            r#"{"servers": [{"name": "alpha", "port": 8080}, {"name": "beta"}], "debug": 1}"#,
+        )
+        .expect("config");
+
+        assert_eq!(config.get_path(""), Some(&config));
+        assert_eq!(
+            config.get_path("/servers/1/name"),
+            Some(&Scalar::from(Value::String("beta".into())))
+        );
+        assert_eq!(
+            config.get_path("/servers/0/port"),
+            Some(&Scalar::from(8080))
+        );
+
+        assert_eq!(config.get_path("/servers/2/name"), None);
+        assert_eq!(config.get_path("/servers/+1/name"), None);
+        assert_eq!(config.get_path("/servers/name"), None);
+        assert_eq!(config.get_path("/debug/0"), None);
+        assert_eq!(config.get_path("/missing"), None);
+        assert_eq!(config.get_path("servers"), None);
+
+        *config.get_path_mut("/servers/1/name").expect("name") =
+            Scalar::from(Value::String("gamma".into()));
+        assert_eq!(
+            config.get_path("/servers/1/name"),
+            Some(&Scalar::from(Value::String("gamma".into())))
+        );
+    }
+
+    #[test]
+    fn scalar_depth_counts_nested_collections() {
+        assert_eq!(Scalar::from(1).depth(), 1);
+        assert_eq!(Scalar::Tuple(vec![]).depth(), 1);
+        assert_eq!(Scalar::Map(Map::new()).depth(), 1);
+
+        let map: Scalar = decode_json(r#"{"a": 1, "b": "two"}"#).expect("map");
+        assert_eq!(map.depth(), 2);
+
+        let nested: Scalar = decode_json(r#"[1, [2, [3]], []]"#).expect("tuple");
+        assert_eq!(nested.depth(), 4);
+
+        let mut deep = Scalar::from(0);
+        for _ in 0..100_000 {
+            deep = Scalar::Tuple(vec![deep]);
+        }
+        assert_eq!(deep.depth(), 100_001);
+        drop_iteratively(deep);
+    }
+
+    #[test]
+    fn scalar_into_entries_and_elements() {
+        let map: Scalar = decode_json(r#"{"b": 2, "a": 1}"#).expect("map");
+        let entries = map
+            .into_entries()
+            .expect("entries")
+            .map(|(key, value)| (key.to_string(), value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                ("a".to_string(), Scalar::from(1)),
+                ("b".to_string(), Scalar::from(2))
+            ]
+        );
+
+        let tuple: Scalar = decode_json("[3, 4]").expect("tuple");
+        let elements = tuple.clone().into_elements().expect("elements");
+        assert_eq!(
+            elements.collect::<Vec<_>>(),
+            [Scalar::from(3), Scalar::from(4)]
+        );
+
+        let err = tuple.into_entries().err().expect("not a map");
+        assert!(err.message().contains("expected a map"), "{err}");
+
+        let err = Scalar::from(5).into_elements().err().expect("not a tuple");
+        assert!(err.message().contains("expected a tuple"), "{err}");
+    }
+
+    #[test]
+    fn scalar_bytes_round_trip() {
+        let blob = Scalar::from(vec![0xde, 0xad, 0xbe, 0xef, 0x00, 0xff]);
+        assert!(String::from_utf8(blob.as_bytes().expect("bytes").to_vec()).is_err());
+
+        let encoded = encode_json(blob.clone());
+        assert_eq!(encoded, r#"{"/state/scalar/value/bytes":"3q2+7wD/"}"#);
+        assert_eq!(decode_json::<Scalar>(&encoded).expect("decode"), blob);
+
+        let nested = Scalar::Tuple(vec![blob.clone(), Scalar::from(1)]);
+        let decoded = decode_json::<Scalar>(&encode_json(nested.clone())).expect("decode");
+        assert_eq!(decoded, nested);
+        assert_eq!(format!("{blob:?}"), r#"b"\xde\xad\xbe\xef\x00\xff""#);
+    }
+
+    #[test]
+    fn scalar_try_fold_sums_a_tuple() {
+        let sum = |acc: Number, item: Scalar| match item {
+            Scalar::Value(Value::Number(n)) => Ok(acc + n),
+            other => Err(TCError::unexpected(other, "a number")),
+        };
+
+        let tuple: Scalar = decode_json("[1, 2, 3]").expect("tuple");
+        let total = tuple.try_fold(Number::from(0_u64), sum).expect("sum");
+        assert_eq!(total, Number::from(6_u64));
+
+        let mixed: Scalar = decode_json(r#"[1, "two"]"#).expect("tuple");
+        let err = mixed.try_fold(Number::from(0_u64), sum).unwrap_err();
+        assert!(err.message().contains("expected a number"), "{err}");
+
+        let err = Scalar::from(5)
+            .try_fold(Number::from(0_u64), sum)
+            .unwrap_err();
+        assert!(err.message().contains("expected a tuple"), "{err}");
+    }
+
+    #[test]
+    fn partial_cmp_value_orders_numbers_and_strings() {
+        use std::cmp::Ordering;
+
+        let one = Scalar::from(1);
+        let two_and_a_half = Scalar::from(Value::from(2.5));
+        assert_eq!(one.partial_cmp_value(&two_and_a_half), Some(Ordering::Less));
+        assert_eq!(
+            two_and_a_half.partial_cmp_value(&one),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            one.partial_cmp_value(&Scalar::from(1)),
+            Some(Ordering::Equal)
+        );
+
+        let apple = Scalar::from(Value::from("apple"));
+        let banana = Scalar::from(Value::from("banana"));
+        assert_eq!(apple.partial_cmp_value(&banana), Some(Ordering::Less));
+
+        let map = Scalar::Map(Map::one("x".parse::<Id>().expect("Id"), one.clone()));
+        assert_eq!(map.partial_cmp_value(&one), None);
+        assert_eq!(one.partial_cmp_value(&apple), None);
+    }
+
+    #[test]
+    fn scalar_tuple_roundtrip() {
+        let scalar = Scalar::Tuple(vec![Scalar::from(7_u64), Scalar::from(Value::from("x"))]);
+
+        let encoded = destream_json::encode(scalar.clone()).expect("encode scalar tuple");
+        let decoded: Scalar =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode scalar tuple");
+
+        assert_eq!(decoded, scalar);
+    }
+
+    #[test]
+    fn scalar_opref_decodes_as_ref() {
+        let link = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
+        let op = OpRef::Get((Subject::Link(link), Scalar::default()));
+        let scalar = Scalar::from(TCRef::Op(op));
+
+        let encoded = destream_json::encode(scalar.clone()).expect("encode scalar ref");
+        let decoded: Scalar =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode scalar ref");
+
+        assert_eq!(decoded, scalar);
+    }
+
+    #[test]
+    fn keyless_get_and_link_value_decode_distinctly() {
+        let link = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
+        let value = Scalar::from(Value::Link(link.clone()));
+        let get = Scalar::from(TCRef::Op(OpRef::Get((
+            Subject::Link(link),
+            Scalar::default(),
+        ))));
+
+        let encoded = destream_json::encode(value.clone()).expect("encode link value");
+        let decoded_value: Scalar =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode link value");
+
+        let encoded = destream_json::encode(get.clone()).expect("encode keyless get");
+        let decoded_get: Scalar =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode keyless get");
+
+        assert_eq!(decoded_value, value);
+        assert_eq!(decoded_get, get);
+        assert_ne!(decoded_value, decoded_get);
+    }
+
+    #[test]
+    fn opref_with_reserved_or_host_subject_round_trips() {
+        let subjects = [
+            "/state/scalar/value/link",
+            "/state/scalar/ref/if",
+            "/state/scalar/op/get",
+            "http://host:8702/lib/acme",
+        ];
+
+        for subject in subjects {
+            let subject = Subject::Link(Link::from_str(subject).expect("link"));
+            let key = Scalar::Tuple(vec![Scalar::from(1), Scalar::from(2)]);
+            let ops = [
+                OpRef::Get((subject.clone(), key.clone())),
+                OpRef::Put((subject.clone(), key.clone(), Scalar::from(3))),
+                OpRef::Post((
+                    subject.clone(),
+                    Map::one("x".parse::<Id>().expect("Id"), key),
+                )),
+            ];
+
+            for op in ops {
+                let scalar = Scalar::from(TCRef::Op(op));
+                let encoded = encode_json(scalar.clone());
+                assert!(encoded.starts_with("{\"/state/scalar/ref/op/"), "{encoded}");
+
+                let decoded: Scalar = decode_json(&encoded).expect("decode");
+                assert_eq!(decoded, scalar, "{encoded}");
+            }
+        }
+    }
+
+    #[test]
+    fn scalar_rejects_non_finite_floats() {
+        let err = decode_json::<Scalar>("[1, 2e999]").unwrap_err();
+        assert!(err.to_string().contains("expected a finite float"), "{err}");
+    }
+
+    #[test]
+    fn v1_opref_payload_round_trips() {
+        let v1 = r#"{"/lib/acme/foo/1.0.0":["k"]}"#;
+        let scalar: Scalar = decode_json(v1).expect("decode v1 GET ref");
+
+        let link = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
+        let get = OpRef::Get((Subject::Link(link), Scalar::from(Value::from("k"))));
+        assert_eq!(scalar, Scalar::from(TCRef::Op(get)));
+        assert_eq!(encode_json(scalar), v1);
+    }
+
+    #[test]
+    fn v1_only_refs_are_rejected_by_name() {
+        let err = decode_json::<Scalar>(r#"{"/state/scalar/ref/after": [null, null]}"#)
+            .expect_err("After is not a v2 ref");
+
+        assert!(err.to_string().contains("/state/scalar/ref/after"));
+    }
+
+    #[cfg(feature = "tc-transact-compat")]
+    #[test]
+    fn v1_get_opref_converts_to_v2_and_back() {
+        let subject = tc_scalar::Subject::from_str("/lib/acme/foo/1.0.0").expect("v1 subject");
+        let key = tc_scalar::Scalar::from(1u64);
+        let v1 = tc_scalar::OpRef::Get((subject, key));
+
+        let v2 = OpRef::try_from(v1.clone()).expect("v1 to v2");
+        let link = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
+        assert_eq!(v2, OpRef::Get((Subject::Link(link), Scalar::from(1u64))));
+
+        let back = tc_scalar::OpRef::try_from(v2).expect("v2 to v1");
+        assert!(back == v1);
+    }
+
+    #[cfg(feature = "tc-transact-compat")]
+    #[test]
+    fn v1_op_def_and_txn_id_convert_to_v2_and_back() {
+        let key = tcgeneric::Id::from_str("key").expect("id");
+        let get_key = tc_scalar::TCRef::Id(tc_scalar::IdRef::from(key.clone()));
+        let form = vec![(
+            tcgeneric::Id::from_str("out").expect("id"),
+            tc_scalar::Scalar::Ref(Box::new(get_key)),
+        )];
+        let v1 = tc_scalar::OpDef::Get((key, form));
+
+        let v2 = OpDef::try_from(v1.clone()).expect("v1 to v2");
+        let out = Scalar::from(TCRef::Id(IdRef::new(Id::from_str("key").expect("id"))));
+        let expected = vec![(Id::from_str("out").expect("id"), out)];
+        assert_eq!(v2, OpDef::Get((Id::from_str("key").expect("id"), expected)));
+        assert!(tc_scalar::OpDef::try_from(v2).expect("v2 to v1") == v1);
+
+        let txn_id = tc_transact::TxnId::from_str("1700000000000000000-7").expect("v1 txn id");
+        let v2 = TxnId::try_from(txn_id).expect("v1 to v2");
+        assert_eq!(v2.timestamp().as_nanos(), 1_700_000_000_000_000_000);
+        assert_eq!(v2.nonce(), 7);
+        assert_eq!(tc_transact::TxnId::try_from(v2).expect("v2 to v1"), txn_id);
+    }
+
+    #[cfg(feature = "tc-transact-compat")]
+    #[test]
+    fn v1_and_v2_only_constructs_are_rejected_by_name() {
+        let range =
+            tc_scalar::Scalar::Range((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded));
+        let err = Scalar::try_from(range).expect_err("a range has no v2 counterpart");
+        assert!(err.message().contains("v1 range"));
+
+        let for_each =
+            decode_json::<Scalar>(r#"{"/state/scalar/ref/for_each": [[1, 2], {"$f": []}, "i"]}"#)
+                .expect("for_each ref");
+
+        let err = tc_scalar::Scalar::try_from(for_each).expect_err("ForEach has no v1 counterpart");
+        assert!(err.message().contains("ForEach ref"));
+    }
+
+    #[test]
+    fn scalar_typed_opref_get_key_decodes_as_ref() {
+        let subject = Subject::Link(Link::from_str("/lib/acme/foo/1.0.0").expect("link"));
+        let key = Scalar::from(Value::from("k"));
+        let mut encoded_map = BTreeMap::new();
+        encoded_map.insert(
+            PathBuf::from(OPREF_GET).to_string(),
+            (subject.clone(), key.clone()),
+        );
+
+        let encoded = destream_json::encode(encoded_map).expect("encode typed opref get");
+        let decoded: Scalar =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode typed opref get as scalar");
+
+        assert_eq!(decoded, Scalar::from(TCRef::Op(OpRef::Get((subject, key)))));
+    }
 
-mod tcref;
-pub use tcref::*;
+    #[test]
+    fn opdef_roundtrip() {
+        let form = vec![
+            ("x".parse().expect("Id"), Scalar::from(7_u64)),
+            ("y".parse().expect("Id"), Scalar::from(Value::from("z"))),
+        ];
+        let op = OpDef::Post(form);
 
-mod dir;
-pub use dir::*;
+        let encoded = destream_json::encode(op.clone()).expect("encode opdef");
+        let decoded: OpDef =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode opdef");
 
-mod library;
-pub use library::*;
+        assert_eq!(decoded, op);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn hashable_scalar_keys_a_hash_map() {
+        use std::collections::HashMap;
 
-    use std::{collections::BTreeMap, future::Future, pin::Pin, str::FromStr};
+        let json = r#"{"b": [1, 2.5, "x"], "a": {"$self": []}}"#;
+        let first: Scalar = decode_json(json).expect("scalar");
+        let second: Scalar = decode_json(json).expect("scalar");
 
-    use number_general::Number;
-    use pathlink::{Link, PathBuf, PathSegment};
-    use tc_error::TCResult;
-    use tc_value::Value;
+        let mut memo = HashMap::new();
+        memo.insert(HashableScalar(first), 1);
+        *memo.entry(HashableScalar(second.clone())).or_insert(0) += 1;
 
-    #[derive(Clone)]
-    struct FakeTxn {
-        claim: Claim,
-    }
+        assert_eq!(memo.len(), 1);
+        assert_eq!(memo[&HashableScalar(second)], 2);
 
-    impl FakeTxn {
-        fn new(claim: Claim) -> Self {
-            Self { claim }
-        }
+        // numbers are normalized, and other floats compare by bit pattern
+        let nan = HashableScalar(Scalar::from(f64::NAN));
+        assert_eq!(nan, HashableScalar(Scalar::from(-f64::NAN)));
+        assert_eq!(HashableScalar(scalar!(0.0)), HashableScalar(scalar!(-0.0)));
+        assert_eq!(HashableScalar(scalar!(1)), HashableScalar(scalar!(1.0)));
+        assert_ne!(HashableScalar(scalar!(0.5)), HashableScalar(scalar!(-0.5)));
     }
 
-    impl Transaction for FakeTxn {
-        fn id(&self) -> TxnId {
-            TxnId::from_parts(NetworkTime::from_nanos(42), 7)
-        }
+    #[test]
+    fn fingerprints_normalize_numbers() {
+        let one = Scalar::from(1u64).fingerprint();
+        assert_eq!(Scalar::from(1i64).fingerprint(), one);
+        assert_eq!(
+            Scalar::from(Value::Number(Number::from(1u8))).fingerprint(),
+            one
+        );
+        assert_eq!(Scalar::from(1.0f64).fingerprint(), one);
+        assert_ne!(Scalar::from(true).fingerprint(), one);
 
-        fn timestamp(&self) -> NetworkTime {
-            NetworkTime::from_nanos(42)
-        }
+        assert_eq!(
+            Scalar::from(0.0f64).fingerprint(),
+            Scalar::from(-0.0f64).fingerprint()
+        );
+        assert_eq!(
+            Scalar::from(-2i64).fingerprint(),
+            Scalar::from(-2.0f64).fingerprint()
+        );
 
-        fn claim(&self) -> &Claim {
-            &self.claim
+        let tenth = Scalar::from(Value::Number(Number::from(0.1f32)));
+        assert_eq!(tenth.fingerprint(), Scalar::from(0.1f64).fingerprint());
+
+        // every number fingerprints the same after a JSON round trip
+        for number in [
+            scalar!(1u64),
+            scalar!(-3i64),
+            scalar!(2.0),
+            scalar!(-0.0),
+            scalar!(2.5),
+        ] {
+            let decoded: Scalar = decode_json(&encode_json(number.clone())).expect("number");
+            assert_eq!(decoded.fingerprint(), number.fingerprint(), "{number:?}");
         }
     }
 
-    struct HelloHandler;
+    #[test]
+    fn fingerprints_match_golden_values() {
+        assert_eq!(
+            scalar!(1).canonical_bytes(),
+            [0x03, 0, 0, 0, 0, 0, 0, 0, 1],
+            "an unsigned integer is a tag and eight big-endian bytes"
+        );
+        assert_eq!(
+            scalar!(-1).canonical_bytes(),
+            [0x02, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            scalar!("ab").canonical_bytes(),
+            [0x05, 0, 0, 0, 0, 0, 0, 0, 2, b'a', b'b']
+        );
 
-    impl HandleGet<FakeTxn> for HelloHandler {
-        type Request = String;
-        type RequestContext = ();
-        type Response = String;
-        type Error = ();
-        type Fut<'a> =
-            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'a>>;
+        let op: Scalar = decode_json(
+            r#"{"/state/scalar/op/get": ["key", [
+                ["sum", {"/state/scalar/value/number/add": [["$key", 2.5]]}]
+            ]]}"#,
+        )
+        .expect("op");
 
-        fn get<'a>(&'a self, _txn: &'a FakeTxn, request: Self::Request) -> TCResult<Self::Fut<'a>> {
-            Ok(Box::pin(async move { Ok(format!("hello {request}")) }))
+        let golden = [
+            (Scalar::none(), 0xaf63_bd4c_8601_b7df),
+            (scalar!(1), 0x796e_d897_b92b_2185),
+            (scalar!(2.5), 0x6c56_dd2f_d546_251f),
+            (scalar!("hello"), 0x3934_80d7_a440_1225),
+            (scalar!({"a": [1, "b"]}), 0x0e0a_5f9d_3522_2764),
+            (op, 0xee77_b97a_7ec8_e5e1),
+        ];
+
+        // these must only change with a deliberate change to the canonical encoding
+        for (scalar, fingerprint) in golden {
+            assert_eq!(scalar.fingerprint(), fingerprint, "{scalar:?}");
         }
     }
 
     #[test]
-    fn handler_invocation() {
-        let handler = HelloHandler;
-        let claim = Claim::new(Link::from_str("/hello").unwrap(), umask::Mode::all());
-        let txn = FakeTxn::new(claim);
+    fn hash_into_agrees_with_buffered_fingerprint() {
+        use std::hash::Hasher;
 
-        let fut = handler.get(&txn, "world".into()).expect("GET supported");
-        let out = futures::executor::block_on(fut).unwrap();
-        assert_eq!(out, "hello world");
+        let scalar: Scalar = decode_json(
+            r#"{"/state/scalar/op/get": ["key", [
+                ["sum", {"/state/scalar/value/number/add": [["$key", 2.5]]}],
+                ["label", {"name": "acme", "tags": ["a", "b"]}]
+            ]]}"#,
+        )
+        .expect("decode");
+
+        let mut streamed = FingerprintHasher::new();
+        scalar.hash_into(&mut streamed);
+
+        let mut buffered = FingerprintHasher::new();
+        buffered.write(&scalar.canonical_bytes());
+
+        assert_eq!(streamed.finish(), buffered.finish());
+        assert_eq!(scalar.fingerprint(), buffered.finish());
+
+        let Scalar::Op(op) = &scalar else {
+            panic!("expected an op definition, found {scalar:?}");
+        };
+
+        let mut buffered = FingerprintHasher::new();
+        buffered.write(&op.canonical_bytes());
+        assert_eq!(op.fingerprint(), buffered.finish());
+
+        let other: Scalar = decode_json(r#"{"name": "acme", "tags": ["a", "c"]}"#).expect("decode");
+        assert_ne!(other.fingerprint(), scalar.fingerprint());
+        assert_eq!(other.fingerprint(), other.clone().fingerprint());
     }
 
     #[test]
-    fn library_schema_destream_roundtrip() {
-        let schema = LibrarySchema::new(
-            Link::from_str("/lib/service").expect("link"),
-            "0.1.0",
-            vec![
-                Link::from_str("/lib/dependency").expect("dep"),
-                Link::from_str("/lib/other").expect("dep"),
-            ],
+    fn opdef_complexity_counts_bindings_refs_and_loops() {
+        let link = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
+        let get = OpRef::Get((Subject::Link(link), Scalar::from(Value::from("k"))));
+        let closure = Scalar::Op(OpDef::Post(vec![(
+            "next".parse().expect("Id"),
+            Scalar::from(1_u64),
+        )]));
+        let while_ref = While::new(
+            Scalar::from(TCRef::Id("$a".parse().expect("IdRef"))),
+            closure,
+            Scalar::from(0_u64),
         );
 
-        let encoded = destream_json::encode(schema.clone()).expect("encode schema");
-        let decoded: LibrarySchema =
-            futures::executor::block_on(destream_json::try_decode((), encoded))
-                .expect("decode schema");
+        let op = OpDef::Post(vec![
+            ("a".parse().expect("Id"), Scalar::from(TCRef::Op(get))),
+            (
+                "b".parse().expect("Id"),
+                Scalar::from(TCRef::While(Box::new(while_ref))),
+            ),
+        ]);
 
-        assert_eq!(decoded, schema);
+        assert_eq!(
+            op.complexity(),
+            Complexity {
+                bindings: 2,
+                op_refs: 1,
+                has_loops: true,
+                max_depth: 3,
+            }
+        );
     }
 
     #[test]
-    fn txn_header_destream_roundtrip() {
-        let claim = Claim::new(Link::from_str("/lib/service").unwrap(), umask::Mode::all());
-        let header = TxnHeader::new(
-            TxnId::from_parts(NetworkTime::from_nanos(7), 1),
-            NetworkTime::from_nanos(7),
-            claim,
+    fn opdef_estimated_cost_charges_remote_ops_more() {
+        struct RemoteIsExpensive;
+
+        impl CostModel for RemoteIsExpensive {
+            fn estimate(&self, op: &OpRef) -> u64 {
+                match op.subject() {
+                    Subject::Link(link) if link.host().is_some() => 100,
+                    _ => 1,
+                }
+            }
+
+            fn loop_bound(&self) -> u64 {
+                5
+            }
+        }
+
+        let id = |name: &str| Id::from_str(name).expect("id");
+        let get = |link: &str| {
+            let link = Link::from_str(link).expect("link");
+            Scalar::from(TCRef::Op(OpRef::Get((
+                Subject::Link(link),
+                Scalar::from(Value::from("k")),
+            ))))
+        };
+
+        let local = "/state/scalar/value/number/add";
+        let remote = "http://peer.example.com/lib/acme/foo";
+
+        let body = |scalar| Scalar::Op(OpDef::Post(vec![(id("out"), scalar)]));
+
+        let for_each = ForEach::new(
+            Scalar::Tuple(vec![
+                Scalar::from(1_u64),
+                Scalar::from(2_u64),
+                Scalar::from(3_u64),
+            ]),
+            body(get(remote)),
+            id("item"),
         );
 
-        let encoded = destream_json::encode(header.clone()).expect("encode header");
-        let decoded: TxnHeader =
-            futures::executor::block_on(destream_json::try_decode((), encoded))
-                .expect("decode header");
+        let while_ref = While::new(
+            Scalar::from(TCRef::Id("$done".parse().expect("IdRef"))),
+            body(get(local)),
+            Scalar::from(0_u64),
+        );
 
-        assert_eq!(decoded, header);
+        let op = OpDef::Post(vec![
+            (id("a"), get(local)),
+            (id("b"), get(remote)),
+            (id("c"), Scalar::from(TCRef::ForEach(Box::new(for_each)))),
+            (id("d"), Scalar::from(TCRef::While(Box::new(while_ref)))),
+            // a closure is a value, so it costs nothing until it's called
+            (id("e"), body(get(remote))),
+        ]);
+
+        assert_eq!(op.estimated_cost(&RemoteIsExpensive), 1 + 100 + 3 * 100 + 5);
     }
 
     #[test]
-    fn txn_id_round_trips_with_trace() {
-        let txn_id = TxnId::from_parts(NetworkTime::from_nanos(7), 1).with_trace([3; 32]);
-        let parsed = TxnId::from_str(&txn_id.to_string()).expect("parse txn id");
+    fn opdef_reports_its_own_and_referenced_verbs() {
+        let op: OpDef = decode_json(
+            r#"{"/state/scalar/op/post": [
+                ["a", {"/lib/acme/foo": ["key"]}],
+                ["b", {"/lib/acme/bar": [{"$a": []}]}],
+                ["c", {"/lib/acme/baz": ["key", {"$b": []}]}]
+            ]}"#,
+        )
+        .expect("op");
 
-        assert_eq!(parsed, txn_id);
+        assert_eq!(op.verb(), Method::Post);
+        assert_eq!(
+            op.referenced_verbs(),
+            BTreeMap::from([(Method::Get, 2), (Method::Put, 1)])
+        );
+
+        assert!(OpDef::Get((Id::from_str("key").expect("id"), vec![]))
+            .referenced_verbs()
+            .is_empty());
     }
 
     #[test]
-    fn txn_id_rejects_partial_wire_id_without_trace() {
-        assert!(TxnId::from_str("7-1").is_err());
-    }
+    fn scalar_tuple_destructures_into_a_rust_tuple() {
+        let (n, name): (u64, String) = scalar!([3, "three"]).try_into().expect("pair");
+        assert_eq!((n, name.as_str()), (3, "three"));
 
-    fn segment(name: &str) -> PathSegment {
-        PathSegment::from_str(name).expect("path segment")
+        let triple = <(u64, u64, String)>::try_from(scalar!([1, 2, "x"])).expect("triple");
+        assert_eq!(triple, (1, 2, "x".to_string()));
+
+        let err = <(u64, String)>::try_from(scalar!([1, "two", 3])).expect_err("arity");
+        assert!(err.message().contains("2 elements but found 3"), "{err}");
+
+        let err = <(u64, String)>::try_from(scalar!(["one", "two"])).expect_err("element");
+        assert!(err.message().contains("tuple element 0"), "{err}");
+
+        assert!(<(u64, u64)>::try_from(scalar!(1)).is_err());
     }
 
     #[test]
-    fn dir_routes_nested_handler() {
-        let path = vec![segment("library"), segment("status")];
-        let dir = Dir::from_routes(vec![(path.clone(), HelloHandler)]).expect("dir");
+    fn canonicalize_refs_unwraps_op_refs_from_single_entry_maps() {
+        let wrapped = |key: &'static str, value: Scalar| {
+            Scalar::Map([(Id::from(hr_id::label(key)), value)].into_iter().collect())
+        };
 
-        let claim = Claim::new(Link::from_str("/lib").unwrap(), umask::Mode::all());
-        let txn = FakeTxn::new(claim);
+        let get: Scalar = decode_json(r#"{"/lib/acme/foo": ["key"]}"#).expect("get");
+        let post: Scalar = decode_json(r#"{"$self/bar": {"x": 1}}"#).expect("post");
+        assert!(matches!(&get, Scalar::Ref(_)));
 
-        let handler = dir.route(&path).expect("handler resolved");
-        let fut = handler.get(&txn, "tinychain".into()).expect("GET");
-        let out = futures::executor::block_on(fut).unwrap();
-        assert_eq!(out, "hello tinychain");
+        // a wrapped op ref is unwrapped, even deep within a tuple
+        let nested = Scalar::Tuple(vec![wrapped("/lib/acme/foo", scalar!(["key"]))]);
+        assert_eq!(nested.canonicalize_refs(), Scalar::Tuple(vec![get.clone()]));
+        assert_eq!(
+            wrapped("$self/bar", scalar!({ "x": 1 })).canonicalize_refs(),
+            post
+        );
+
+        // these are not unambiguously op refs, so they're left alone
+        let ambiguous = [
+            wrapped("/lib/acme/foo", scalar!([])),
+            wrapped("/lib/acme/foo", scalar!([1, 2, 3])),
+            wrapped("/lib/acme/foo", scalar!("key")),
+            wrapped("/state/scalar/op/get", scalar!(["key", []])),
+            wrapped("lib", scalar!(["key"])),
+            scalar!({ "a": ["key"], "b": ["key"] }),
+        ];
+
+        for scalar in ambiguous {
+            assert_eq!(scalar.clone().canonicalize_refs(), scalar);
+        }
+
+        // a canonical scalar is unchanged
+        assert_eq!(get.clone().canonicalize_refs(), get);
     }
 
     #[test]
-    fn dir_detects_conflicts() {
-        let path = vec![segment("library"), segment("status")];
+    fn scalar_visit_reports_nesting_with_enter_and_leave() {
+        /// Renders the nesting of a scalar, e.g. `{a: [v v] b: v}`.
+        #[derive(Default)]
+        struct Outline(String);
 
-        match Dir::from_routes(vec![
-            (path.clone(), HelloHandler),
-            (path.clone(), HelloHandler),
-        ]) {
-            Ok(_) => panic!("expected conflict inserting duplicate handler"),
-            Err(err) => assert!(err.message().contains("already mounted")),
+        impl ScalarVisitor for Outline {
+            fn enter_map(&mut self, _map: &Map<Scalar>) {
+                self.0.push('{');
+            }
+
+            fn visit_key(&mut self, key: &Id) {
+                self.0.push_str(&format!(" {key}:"));
+            }
+
+            fn leave_map(&mut self, _map: &Map<Scalar>) {
+                self.0.push_str(" }");
+            }
+
+            fn enter_tuple(&mut self, _items: &[Scalar]) {
+                self.0.push_str(" [");
+            }
+
+            fn leave_tuple(&mut self, _items: &[Scalar]) {
+                self.0.push_str(" ]");
+            }
+
+            fn visit_value(&mut self, _scalar: &Scalar) {
+                self.0.push_str(" v");
+            }
+        }
+
+        /// Tracks the path of map keys to each value, like a scoped symbol table.
+        #[derive(Default)]
+        struct Scopes {
+            keys: Vec<Option<Id>>,
+            pending: Option<Id>,
+            values: Vec<String>,
+        }
+
+        impl ScalarVisitor for Scopes {
+            fn enter_map(&mut self, _map: &Map<Scalar>) {
+                self.keys.push(self.pending.take());
+            }
+
+            fn visit_key(&mut self, key: &Id) {
+                self.pending = Some(key.clone());
+            }
+
+            fn leave_map(&mut self, _map: &Map<Scalar>) {
+                self.keys.pop();
+            }
+
+            fn visit_value(&mut self, _scalar: &Scalar) {
+                let scope = self.keys.iter().flatten().chain(self.pending.as_ref());
+                let scope = scope.map(Id::as_str).collect::<Vec<_>>();
+                self.values.push(scope.join("."));
+            }
         }
+
+        let scalar = scalar!({ "a": [1, { "b": 2 }], "c": { "d": [] }, "e": 3 });
+
+        let mut outline = Outline::default();
+        scalar.visit(&mut outline);
+        assert_eq!(outline.0, "{ a: [ v{ b: v } ] c:{ d: [ ] } e: v }");
+
+        let mut scopes = Scopes::default();
+        scalar.visit(&mut scopes);
+        assert_eq!(scopes.values, ["a", "a.b", "e"]);
     }
 
     #[test]
-    fn macro_builds_routes() {
-        let dir = tc_library_routes! {
-            "/lib/status" => HelloHandler,
-        }
-        .expect("macro routes");
+    fn opdef_prune_dead_bindings_keeps_live_and_effectful_bindings() {
+        let op: OpDef = decode_json(
+            r#"{"/state/scalar/op/get": ["key", [
+                ["tmp", {"unused": 1}],
+                ["log", {"/lib/acme/log": [{"$key": []}]}],
+                ["base", [1, 2]],
+                ["shadowed", "never read"],
+                ["shadowed", {"/state/scalar/ref/if": [{"$base": []}, "a", "b"]}],
+                ["result", {"$shadowed": []}]
+            ]]}"#,
+        )
+        .expect("op");
 
-        let claim = Claim::new(Link::from_str("/lib").unwrap(), umask::Mode::all());
-        let txn = FakeTxn::new(claim);
-        let path = [segment("lib"), segment("status")];
-        let handler = dir.route(&path).expect("handler");
-        let fut = handler.get(&txn, "macro".into()).expect("GET");
-        let out = futures::executor::block_on(fut).unwrap();
-        assert_eq!(out, "hello macro");
+        let ids = |op: &OpDef| {
+            op.form()
+                .iter()
+                .map(|(id, _)| id.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let pruned = op.clone().prune_dead_bindings(true);
+        assert_eq!(ids(&pruned), ["log", "base", "shadowed", "result"]);
+        assert_eq!(pruned.form()[2], op.form()[4]);
+
+        let pure = op.prune_dead_bindings(false);
+        assert_eq!(ids(&pure), ["base", "shadowed", "result"]);
+
+        // the last binding is the result, so it's kept even though nothing refers to it
+        let single = OpDef::Post(vec![(Id::from_str("tmp").expect("id"), scalar!(1))]);
+        assert_eq!(single.clone().prune_dead_bindings(false), single);
     }
 
     #[test]
-    fn scalar_map_roundtrip() {
-        let mut inner = Map::new();
-        inner.insert(
-            "signed".parse().expect("Id"),
-            Scalar::from(Value::Number(Number::Bool(true.into()))),
-        );
-        inner.insert("bits".parse().expect("Id"), Scalar::from(16_u64));
+    fn decode_errors_name_their_location() {
+        let error = |json: &str| {
+            decode_json::<Scalar>(json)
+                .expect_err("invalid scalar")
+                .to_string()
+        };
 
-        let mut outer = Map::new();
-        outer.insert(
-            "dtype".parse().expect("Id"),
-            Scalar::from(Value::from("f32")),
-        );
-        outer.insert("encoding".parse().expect("Id"), Scalar::Map(inner));
+        let nested = error(r#"{"a": {"b": [1, {"c": 1e999}]}}"#);
+        assert!(nested.starts_with("at a.b[1].c: "), "{nested}");
+        assert!(nested.contains("expected a finite float"), "{nested}");
 
-        let scalar = Scalar::Map(outer);
+        // the location is written once, by the innermost node which the error is raised within
+        assert_eq!(nested.matches("at ").count(), 1, "{nested}");
 
-        let encoded = destream_json::encode(scalar.clone()).expect("encode scalar map");
-        let decoded: Scalar = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode scalar map");
+        let bytes = error(r#"{"a": {"b": [1, {"/state/scalar/value/bytes": 5}]}}"#);
+        assert!(bytes.starts_with("at a.b[1]: "), "{bytes}");
 
-        assert_eq!(decoded, scalar);
+        let op = error(
+            r#"{"/state/scalar/op/post": [
+                ["x", 1],
+                ["y", {"/lib/acme/foo": {"params": {"x": 1e999}}}]
+            ]}"#,
+        );
+        assert!(op.starts_with("at form[1].params.x: "), "{op}");
+
+        // an error at the root has no location
+        assert!(!error("1e999").starts_with("at "));
     }
 
     #[test]
-    fn scalar_tuple_roundtrip() {
-        let scalar = Scalar::Tuple(vec![Scalar::from(7_u64), Scalar::from(Value::from("x"))]);
+    fn opdef_op_refs_descend_into_control_flow() {
+        let op: OpDef = decode_json(
+            r#"{"/state/scalar/op/get": ["key", [
+                ["user", {"/lib/acme/users": [{"$key": []}]}],
+                ["plain", {"a": 1}],
+                ["result", {"/state/scalar/ref/if": [
+                    {"$user/active": [null]},
+                    {"/lib/acme/audit": {"user": {"$user": []}}},
+                    "inactive"
+                ]}]
+            ]]}"#,
+        )
+        .expect("op");
 
-        let encoded = destream_json::encode(scalar.clone()).expect("encode scalar tuple");
-        let decoded: Scalar = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode scalar tuple");
+        let refs = op
+            .op_refs()
+            .map(|op_ref| (op_ref.verb(), op_ref.subject().to_string()))
+            .collect::<Vec<_>>();
 
-        assert_eq!(decoded, scalar);
+        assert_eq!(
+            refs,
+            [
+                (Method::Get, "/lib/acme/users".to_string()),
+                (Method::Get, "$user/active".to_string()),
+                (Method::Post, "/lib/acme/audit".to_string()),
+            ]
+        );
     }
 
     #[test]
-    fn scalar_opref_decodes_as_ref() {
-        let link = Link::from_str("/lib/acme/foo/1.0.0").expect("link");
-        let op = OpRef::Get((Subject::Link(link), Scalar::default()));
-        let scalar = Scalar::from(TCRef::Op(op));
+    fn opref_into_op_def_binds_the_ref() {
+        let op_ref: OpRef = decode_json(r#"{"/lib/acme/foo": [{"$key": []}, 2]}"#).expect("op ref");
 
-        let encoded = destream_json::encode(scalar.clone()).expect("encode scalar ref");
-        let decoded: Scalar = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode scalar ref");
+        let result = Id::from_str("result").expect("id");
+        let op = op_ref.clone().into_op_def(result.clone());
 
-        assert_eq!(decoded, scalar);
+        assert_eq!(op.verb(), Method::Post);
+        assert_eq!(op.last_id(), Some(&result));
+        assert_eq!(op.form(), &[(result, Scalar::from(TCRef::Op(op_ref)))]);
+
+        let decoded: OpDef = decode_json(&encode_json(&op)).expect("op def");
+        assert_eq!(decoded, op);
     }
 
     #[test]
-    fn scalar_typed_opref_get_key_decodes_as_ref() {
-        let subject = Subject::Link(Link::from_str("/lib/acme/foo/1.0.0").expect("link"));
-        let key = Scalar::from(Value::from("k"));
-        let mut encoded_map = BTreeMap::new();
-        encoded_map.insert(
-            PathBuf::from(OPREF_GET).to_string(),
-            (subject.clone(), key.clone()),
+    fn opdef_dependency_layers_group_independent_bindings() {
+        let op: OpDef = decode_json(
+            r#"{"/state/scalar/op/get": ["key", [
+                ["user", {"/lib/acme/users": [{"$key": []}]}],
+                ["orders", {"/lib/acme/orders": [{"$key": []}]}],
+                ["report", {"/lib/acme/report": {"user": {"$user": []}, "orders": {"$orders": []}}}],
+                ["summary", {"$report/summary": ["short"]}]
+            ]]}"#,
+        )
+        .expect("op");
+
+        let layers = op
+            .dependency_layers()
+            .into_iter()
+            .map(|layer| layer.iter().map(Id::to_string).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            layers,
+            [vec!["user", "orders"], vec!["report"], vec!["summary"]]
         );
+        assert!(OpDef::Post(vec![]).dependency_layers().is_empty());
+    }
 
-        let encoded = destream_json::encode(encoded_map).expect("encode typed opref get");
-        let decoded: Scalar = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode typed opref get as scalar");
+    #[test]
+    fn opdef_pretty_debug_indents_nested_scalars() {
+        // leaf values keep their own `Debug` format, re-indented to their nesting level
+        let leaf = |value: &Value, indent: usize| {
+            format!("{value:#?}").replace('\n', &format!("\n{}", " ".repeat(indent)))
+        };
 
-        assert_eq!(decoded, Scalar::from(TCRef::Op(OpRef::Get((subject, key)))));
+        let one = Value::from(1_u64);
+        let s = Value::from("s");
+        let seven = Value::from(7_u64);
+
+        let op = OpDef::Post(vec![
+            (
+                "x".parse().expect("Id"),
+                Scalar::Map(Map::one(
+                    "a".parse::<Id>().expect("Id"),
+                    Scalar::Tuple(vec![Scalar::from(one.clone()), Scalar::from(s.clone())]),
+                )),
+            ),
+            ("y".parse().expect("Id"), Scalar::from(seven.clone())),
+        ]);
+
+        let expected = format!(
+            "Post {{\n    x: {{\n        a: (\n            {},\n            {},\n        ),\n    }},\n    y: {},\n}}",
+            leaf(&one, 12),
+            leaf(&s, 12),
+            leaf(&seven, 4),
+        );
+        assert_eq!(format!("{op:#?}"), expected);
+
+        let compact = format!("Post {{x: {{a: ({one:?}, {s:?})}}, y: {seven:?}}}");
+        assert_eq!(format!("{op:?}"), compact);
     }
 
     #[test]
-    fn opdef_roundtrip() {
-        let form = vec![
-            ("x".parse().expect("Id"), Scalar::from(7_u64)),
-            ("y".parse().expect("Id"), Scalar::from(Value::from("z"))),
+    fn opref_subject_accessors_relink_every_verb() {
+        let dev = Subject::Link(Link::from_str("/lib/acme/dev/foo").expect("link"));
+        let prod = Subject::Link(Link::from_str("/lib/acme/prod/foo").expect("link"));
+        let key = Scalar::from(Value::from("k"));
+
+        let ops = vec![
+            OpRef::Get((dev.clone(), key.clone())),
+            OpRef::Put((dev.clone(), key.clone(), Scalar::from(1_u64))),
+            OpRef::Post((dev.clone(), Map::new())),
+            OpRef::Delete((dev.clone(), key.clone())),
         ];
-        let op = OpDef::Post(form);
 
-        let encoded = destream_json::encode(op.clone()).expect("encode opdef");
-        let decoded: OpDef = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode opdef");
+        for op in ops {
+            assert_eq!(op.subject(), &dev);
 
-        assert_eq!(decoded, op);
+            let relinked = op.clone().map_subject(|subject| {
+                if subject == dev {
+                    prod.clone()
+                } else {
+                    subject
+                }
+            });
+            assert_eq!(relinked.subject(), &prod);
+
+            let mut edited = op.clone();
+            *edited.subject_mut() = prod.clone();
+            assert_eq!(edited, relinked);
+            assert_ne!(edited, op);
+        }
     }
 
     #[test]
     fn tcref_id_roundtrip() {
         let tcref = TCRef::Id("$foo".parse().expect("IdRef"));
         let encoded = destream_json::encode(tcref.clone()).expect("encode tcref id");
-        let decoded: TCRef = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode tcref id");
+        let decoded: TCRef =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode tcref id");
         assert_eq!(decoded, tcref);
     }
 
@@ -287,8 +3986,9 @@ mod tests {
         let state = Scalar::from(7_u64);
         let tcref = TCRef::While(Box::new(While::new(cond, closure, state)));
         let encoded = destream_json::encode(tcref.clone()).expect("encode tcref while");
-        let decoded: TCRef = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode tcref while");
+        let decoded: TCRef =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode tcref while");
         assert_eq!(decoded, tcref);
     }
 
@@ -302,8 +4002,9 @@ mod tests {
             vec![Scalar::from(cond.clone()), then.clone(), or_else.clone()],
         )]))
         .expect("encode legacy if map");
-        let decoded: TCRef = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode tcref if");
+        let decoded: TCRef =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode tcref if");
         assert_eq!(
             decoded,
             TCRef::Cond(Box::new(Cond::new(cond, then, or_else)))
@@ -324,8 +4025,9 @@ mod tests {
         let tcref = TCRef::Cond(Box::new(Cond::new(cond, then, or_else)));
 
         let encoded = destream_json::encode(tcref.clone()).expect("encode tcref cond");
-        let decoded: TCRef = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode tcref cond");
+        let decoded: TCRef =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode tcref cond");
 
         assert_eq!(decoded, tcref);
     }
@@ -341,8 +4043,9 @@ mod tests {
         let tcref = TCRef::ForEach(Box::new(ForEach::new(items, op, item_name)));
 
         let encoded = destream_json::encode(tcref.clone()).expect("encode tcref for_each");
-        let decoded: TCRef = futures::executor::block_on(destream_json::try_decode((), encoded))
-            .expect("decode tcref for_each");
+        let decoded: TCRef =
+            futures::executor::block_on(destream_json::try_decode(Default::default(), encoded))
+                .expect("decode tcref for_each");
 
         assert_eq!(decoded, tcref);
     }
@@ -376,4 +4079,28 @@ mod tests {
         let err = map.require("answer").unwrap_err();
         assert!(err.message().contains("missing answer parameter"));
     }
+
+    #[test]
+    fn map_prefix_helpers_select_namespaced_keys() {
+        let mut map: Map<u64> = ["a", "opt.x", "opt.y", "z"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, key)| (key.parse().expect("Id"), i as u64))
+            .collect();
+
+        let keys = map
+            .keys_with_prefix("opt.")
+            .map(|(key, value)| (key.as_str(), *value))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [("opt.x", 1), ("opt.y", 2)]);
+        assert_eq!(map.keys_with_prefix("missing").count(), 0);
+
+        let opts = map.split_prefix("opt.");
+        assert_eq!(
+            opts.keys().map(Id::as_str).collect::<Vec<_>>(),
+            ["opt.x", "opt.y"]
+        );
+        assert_eq!(map.keys().map(Id::as_str).collect::<Vec<_>>(), ["a", "z"]);
+        assert!(map.split_prefix("opt.").is_empty());
+    }
 }
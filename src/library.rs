@@ -1,9 +1,37 @@
-use std::marker::PhantomData;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    marker::PhantomData,
+    sync::{Arc, Mutex, PoisonError},
+};
 
 use destream::{de, en, EncodeMap, IntoStream};
-use pathlink::Link;
+use futures::future::BoxFuture;
+use pathlink::{Link, PathSegment};
+use tc_error::{ErrorKind, TCError, TCResult};
 
-use crate::{Route, Transaction};
+use crate::{
+    DefaultErrorHandler, Dir, DynHandler, ErrorHandler, Method, OpDef, Route, Scalar, Transaction,
+};
+
+/// The optional top-level field which tags the wire format version of a [`LibrarySchema`] or
+/// [`crate::TxnHeader`] encoding.
+pub const FORMAT_VERSION_KEY: &str = "_v";
+
+/// The wire format version written and understood by this crate.
+///
+/// A payload with no [`FORMAT_VERSION_KEY`] field is read as this version, so the field is omitted
+/// when encoding. Decoders reject any other version rather than misreading it.
+pub const FORMAT_VERSION: u64 = 1;
+
+pub(crate) fn check_format_version(version: u64) -> Result<(), String> {
+    if version == FORMAT_VERSION {
+        Ok(())
+    } else {
+        Err(format!(
+            "unsupported format version {version} (this decoder understands version {FORMAT_VERSION})"
+        ))
+    }
+}
 
 /// Static description of a TinyChain library exposed through `/lib`.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -39,6 +67,111 @@ impl LibrarySchema {
     }
 }
 
+#[cfg(feature = "toml")]
+impl LibrarySchema {
+    /// Parse a schema from a Cargo-style TOML manifest, e.g.:
+    ///
+    /// ```toml
+    /// id = "/lib/acme"
+    /// version = "1.2.3"
+    ///
+    /// [dependencies]
+    /// users = "/lib/users"
+    /// ```
+    ///
+    /// `id` and each dependency must be a valid link, and `version` must look like
+    /// `MAJOR.MINOR.PATCH` (optionally followed by a `-pre` or `+build` suffix). The names of the
+    /// dependencies are only for the author's reference: the schema lists their links, sorted by
+    /// name. Any other field is ignored.
+    pub fn from_toml_str(manifest: &str) -> TCResult<Self> {
+        use std::str::FromStr;
+
+        #[derive(serde::Deserialize)]
+        struct Manifest {
+            id: String,
+            version: String,
+            #[serde(default)]
+            dependencies: BTreeMap<String, String>,
+        }
+
+        let manifest: Manifest = toml::from_str(manifest)
+            .map_err(|err| TCError::bad_request(format!("invalid library manifest: {err}")))?;
+
+        let link = |field: &str, link: &str| {
+            Link::from_str(link).map_err(|err| {
+                TCError::bad_request(format!("invalid {field} link {link:?} in manifest: {err}"))
+            })
+        };
+
+        let id = link("id", &manifest.id)?;
+        check_version(&manifest.version)?;
+
+        let dependencies = manifest
+            .dependencies
+            .iter()
+            .map(|(name, dependency)| link(&format!("dependency {name}"), dependency))
+            .collect::<TCResult<_>>()?;
+
+        Ok(Self::new(id, manifest.version, dependencies))
+    }
+}
+
+#[cfg(feature = "toml")]
+fn check_version(version: &str) -> TCResult<()> {
+    let core = version.split(['-', '+']).next().unwrap_or_default();
+    let parts = core.split('.').collect::<Vec<_>>();
+
+    let is_valid = parts.len() == 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(TCError::bad_request(format!(
+            "invalid library version {version:?} (expected e.g. \"1.2.3\")"
+        )))
+    }
+}
+
+#[cfg(feature = "jsonschema")]
+impl LibrarySchema {
+    /// A JSON Schema describing the wire format of a [`LibrarySchema`] manifest.
+    ///
+    /// `id` and `version` are required strings, `dependencies` is an optional list of link
+    /// strings, and `_v` is the optional format version. Unknown fields are allowed, since the
+    /// decoder ignores them.
+    pub fn json_schema() -> serde_json::Value {
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "LibrarySchema",
+            "type": "object",
+            "properties": {
+                "id": {
+                    "description": "Unique library identifier (usually a `tc://` link)",
+                    "type": "string",
+                },
+                "version": {
+                    "description": "Version string advertised to runtimes",
+                    "type": "string",
+                },
+                "dependencies": {
+                    "description": "Links to the libraries this module depends on",
+                    "type": "array",
+                    "items": { "type": "string" },
+                },
+                "_v": {
+                    "description": "Wire format version (1 if absent)",
+                    "const": 1,
+                },
+            },
+            "required": ["id", "version"],
+            "additionalProperties": true,
+        })
+    }
+}
+
 impl de::FromStream for LibrarySchema {
     type Context = ();
 
@@ -82,6 +215,10 @@ impl de::FromStream for LibrarySchema {
                         "dependencies" => {
                             dependencies = Some(map.next_value::<Vec<Link>>(()).await?);
                         }
+                        FORMAT_VERSION_KEY => {
+                            let version = map.next_value::<u64>(()).await?;
+                            check_format_version(version).map_err(de::Error::custom)?;
+                        }
                         _ => {
                             let _ = map.next_value::<de::IgnoredAny>(()).await?;
                         }
@@ -122,6 +259,113 @@ impl<'en> en::ToStream<'en> for LibrarySchema {
     }
 }
 
+/// A bounded, thread-safe LRU cache of decoded [`LibrarySchema`]s keyed by library [`Link`].
+///
+/// Hosts that repeatedly load the same libraries can use this to skip re-decoding their schemas.
+/// When the cache is full, the least-recently-used schema is evicted. A capacity of zero disables
+/// caching.
+pub struct SchemaCache {
+    capacity: usize,
+    state: Mutex<SchemaCacheState>,
+}
+
+#[derive(Default)]
+struct SchemaCacheState {
+    tick: u64,
+    entries: BTreeMap<Link, (u64, Arc<LibrarySchema>)>,
+    recency: BTreeMap<u64, Link>,
+}
+
+impl SchemaCacheState {
+    fn touch(&mut self, link: &Link) -> Option<Arc<LibrarySchema>> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let (last_used, schema) = self.entries.get_mut(link)?;
+        self.recency.remove(last_used);
+        self.recency.insert(tick, link.clone());
+        *last_used = tick;
+
+        Some(schema.clone())
+    }
+
+    fn insert(&mut self, link: Link, schema: Arc<LibrarySchema>, capacity: usize) {
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some((last_used, _)) = self.entries.insert(link.clone(), (tick, schema)) {
+            self.recency.remove(&last_used);
+        }
+
+        self.recency.insert(tick, link);
+
+        while self.entries.len() > capacity {
+            let Some((_, lru)) = self.recency.pop_first() else {
+                break;
+            };
+
+            self.entries.remove(&lru);
+        }
+    }
+}
+
+impl SchemaCache {
+    /// Construct a new [`SchemaCache`] which holds at most `capacity` schemas.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(SchemaCacheState::default()),
+        }
+    }
+
+    /// The maximum number of schemas held by this cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of schemas currently cached.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Return `true` if no schemas are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Look up the schema cached for `link`, marking it as recently used.
+    pub fn get(&self, link: &Link) -> Option<Arc<LibrarySchema>> {
+        self.lock().touch(link)
+    }
+
+    /// Return the schema cached for `link`, or call `f` to load it and cache the result.
+    ///
+    /// The cache is not locked while `f` runs, so concurrent misses for the same link may each
+    /// call `f`; the last result to finish is the one cached. An error from `f` is returned as-is
+    /// and nothing is cached.
+    pub fn get_or_insert_with<F>(&self, link: &Link, f: F) -> TCResult<Arc<LibrarySchema>>
+    where
+        F: FnOnce() -> TCResult<LibrarySchema>,
+    {
+        if let Some(schema) = self.get(link) {
+            return Ok(schema);
+        }
+
+        let schema = Arc::new(f()?);
+
+        if self.capacity > 0 {
+            self.lock()
+                .insert(link.clone(), schema.clone(), self.capacity);
+        }
+
+        Ok(schema)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SchemaCacheState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
 /// Convenience wrapper that pairs a schema with a reusable routing table.
 pub struct LibraryModule<Txn: ?Sized, Routes> {
     schema: LibrarySchema,
@@ -161,6 +405,17 @@ where
     }
 }
 
+impl<Txn: ?Sized, H> LibraryModule<Txn, Dir<H>> {
+    /// Collect every [`Link`] referenced by the op definitions of this library's handlers, e.g.
+    /// for egress review. See [`Dir::referenced_links`].
+    pub fn referenced_links<'a, F>(&'a self, op_def: F) -> BTreeSet<Link>
+    where
+        F: FnMut(&'a H) -> Option<&'a OpDef>,
+    {
+        self.routes.referenced_links(op_def)
+    }
+}
+
 /// Backwards-compatible alias for the previous `StaticLibrary` type name.
 pub type StaticLibrary<Txn, Routes> = LibraryModule<Txn, Routes>;
 
@@ -175,3 +430,133 @@ pub trait Library {
     /// Root routing table used to dispatch runtime requests.
     fn routes(&self) -> &Self::Routes;
 }
+
+/// An object-safe counterpart of [`Library`], whose routes resolve to [`DynHandler`]s.
+///
+/// Every [`Library`] whose handlers implement [`DynHandler`] is a `DynLibrary`, so a host can
+/// store libraries with different routing tables side by side in a [`LibraryRegistry`].
+pub trait DynLibrary<T: Transaction + ?Sized>: Send + Sync {
+    /// Schema returned by `/lib`.
+    fn schema(&self) -> &LibrarySchema;
+
+    /// Resolve the handler mounted at `path`, relative to this library.
+    fn route_dyn<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a dyn DynHandler<T>>;
+}
+
+impl<L> DynLibrary<L::Txn> for L
+where
+    L: Library + Send + Sync,
+    <L::Routes as Route>::Handler: DynHandler<L::Txn> + Sized,
+{
+    fn schema(&self) -> &LibrarySchema {
+        Library::schema(self)
+    }
+
+    fn route_dyn<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a dyn DynHandler<L::Txn>> {
+        let handler = self.routes().route(path)?;
+        Some(handler)
+    }
+}
+
+/// The libraries loaded by a host, keyed by the [`Link`] of their schema.
+pub struct LibraryRegistry<T: Transaction + ?Sized> {
+    libraries: BTreeMap<Link, Box<dyn DynLibrary<T>>>,
+    error_handler: Box<dyn ErrorHandler>,
+}
+
+impl<T: Transaction + ?Sized> Default for LibraryRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Transaction + ?Sized> LibraryRegistry<T> {
+    pub fn new() -> Self {
+        Self {
+            libraries: BTreeMap::new(),
+            error_handler: Box::new(DefaultErrorHandler),
+        }
+    }
+
+    /// Format every failure of [`LibraryRegistry::dispatch`] with `error_handler` instead of the
+    /// [`DefaultErrorHandler`].
+    pub fn with_error_handler<E: ErrorHandler + 'static>(mut self, error_handler: E) -> Self {
+        self.error_handler = Box::new(error_handler);
+        self
+    }
+
+    /// Register `library` under the ID of its schema, failing if that ID is already registered.
+    pub fn register(&mut self, library: Box<dyn DynLibrary<T>>) -> TCResult<()> {
+        use std::collections::btree_map::Entry;
+
+        match self.libraries.entry(library.schema().id().clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(library);
+                Ok(())
+            }
+            Entry::Occupied(entry) => Err(TCError::bad_request(format!(
+                "library {} is already registered",
+                entry.key()
+            ))),
+        }
+    }
+
+    /// Look up the library registered with the given ID.
+    pub fn get(&self, id: &Link) -> Option<&dyn DynLibrary<T>> {
+        self.libraries.get(id).map(|library| &**library)
+    }
+
+    /// The number of registered libraries.
+    pub fn len(&self) -> usize {
+        self.libraries.len()
+    }
+
+    /// Return `true` if no libraries are registered.
+    pub fn is_empty(&self) -> bool {
+        self.libraries.is_empty()
+    }
+
+    /// Resolve the handler for a request to `link`.
+    ///
+    /// The request is dispatched to the library whose ID is the longest prefix of `link` (with the
+    /// same host), and the rest of the path is routed within that library.
+    pub fn route<'a>(&'a self, link: &'a Link) -> Option<&'a dyn DynHandler<T>> {
+        let path = link.path();
+
+        let (id, library) = self
+            .libraries
+            .iter()
+            .filter(|(id, _)| id.host() == link.host() && path.starts_with(id.path()))
+            .max_by_key(|(id, _)| id.path().len())?;
+
+        library.route_dyn(&path[id.path().len()..])
+    }
+
+    /// Route and call a `method` request to `link`, always producing a response body.
+    ///
+    /// If there is no handler for `link`, or the handler rejects or fails the request, this
+    /// returns the [`ErrorKind`] of the error (e.g. to choose a status code) along with the error
+    /// body formatted by this registry's [`ErrorHandler`].
+    pub fn dispatch<'a>(
+        &'a self,
+        method: Method,
+        txn: &'a T,
+        link: &'a Link,
+        request: Scalar,
+    ) -> BoxFuture<'a, Result<Scalar, (ErrorKind, Scalar)>> {
+        Box::pin(async move {
+            let result = match self.route(link) {
+                Some(handler) => match handler.call(method, txn, request) {
+                    Ok(response) => response.await,
+                    Err(cause) => Err(cause),
+                },
+                None => Err(TCError::not_found(link)),
+            };
+
+            result.map_err(|err| {
+                let kind = err.code();
+                (kind, self.error_handler.on_error(method, link.path(), err))
+            })
+        })
+    }
+}
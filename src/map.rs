@@ -3,15 +3,32 @@
 use std::{
     collections::BTreeMap,
     fmt,
+    future::Future,
     iter::FromIterator,
-    ops::{Deref, DerefMut},
+    marker::PhantomData,
+    ops::{Bound, Deref, DerefMut},
 };
 
 use destream::{de, en};
+use futures::TryFutureExt;
 use tc_error::{TCError, TCResult};
 
+use crate::decode::{Frame, FromStreamAt};
 use crate::Id;
 
+/// A naming convention for [`Id`]s which is stricter than the base `hr_id` grammar, e.g. to
+/// require snake_case binding ids in a library.
+pub trait IdPolicy {
+    /// Return an error if `id` violates this policy.
+    fn check(&self, id: &Id) -> TCResult<()>;
+}
+
+impl<F: Fn(&Id) -> TCResult<()>> IdPolicy for F {
+    fn check(&self, id: &Id) -> TCResult<()> {
+        (self)(id)
+    }
+}
+
 /// A deterministic map type used by the TinyChain IR.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Map<T> {
@@ -69,6 +86,92 @@ impl<T> Map<T> {
             .ok_or_else(|| TCError::not_found(format!("missing {name} parameter")))
     }
 
+    /// Remove and return the parameters with the given `names`, in order.
+    ///
+    /// If any are missing, this returns a "not found" error which names every missing parameter
+    /// (not just the first), and leaves this [`Map`] unchanged. Naming a parameter twice is an
+    /// error too.
+    pub fn require_all(&mut self, names: &[&str]) -> TCResult<Vec<T>> {
+        let ids = names
+            .iter()
+            .map(|name| {
+                name.parse::<Id>().map_err(|err| {
+                    TCError::bad_request(format!("invalid map key id {name:?}: {err}"))
+                })
+            })
+            .collect::<TCResult<Vec<_>>>()?;
+
+        if let Some((i, _)) = ids
+            .iter()
+            .enumerate()
+            .find(|(i, id)| ids[..*i].contains(id))
+        {
+            return Err(TCError::bad_request(format!(
+                "parameter {} is required more than once",
+                names[i]
+            )));
+        }
+
+        let missing = names
+            .iter()
+            .zip(&ids)
+            .filter(|(_, id)| !self.contains_key(*id))
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+
+        match missing.as_slice() {
+            [] => Ok(ids
+                .iter()
+                .map(|id| self.remove(id).expect("parameter"))
+                .collect()),
+            [name] => Err(TCError::not_found(format!("missing {name} parameter"))),
+            names => Err(TCError::not_found(format!(
+                "missing {} parameters",
+                names.join(", ")
+            ))),
+        }
+    }
+
+    /// Iterate over the entries whose key starts with `prefix`, in key order.
+    pub fn keys_with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a Id, &'a T)> {
+        self.inner
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(key, _)| key.as_str().starts_with(prefix))
+    }
+
+    /// Remove and return the entries whose key starts with `prefix`.
+    pub fn split_prefix(&mut self, prefix: &str) -> Map<T> {
+        let keys = self
+            .keys_with_prefix(prefix)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        keys.into_iter()
+            .filter_map(|key| self.inner.remove_entry(&key))
+            .collect()
+    }
+
+    /// Transform every value of this [`Map`] with `f`, keeping its keys, and stop at the first
+    /// error.
+    pub fn try_map<U, E, F>(self, mut f: F) -> Result<Map<U>, E>
+    where
+        F: FnMut(T) -> Result<U, E>,
+    {
+        self.inner
+            .into_iter()
+            .map(|(key, value)| f(value).map(|value| (key, value)))
+            .collect()
+    }
+
+    /// Check every key of this [`Map`] against `policy`, in key order, and return the first
+    /// violation.
+    pub fn validate_keys(&self, policy: &impl IdPolicy) -> TCResult<()> {
+        self.inner.keys().try_for_each(|key| policy.check(key))
+    }
+
     /// Remove and return the parameter with the given `name`, or panic if missing.
     pub fn expect(&mut self, name: &str) -> T
     where
@@ -148,16 +251,61 @@ impl<T> From<Map<T>> for BTreeMap<Id, T> {
 
 impl<T> de::FromStream for Map<T>
 where
-    T: de::FromStream<Context = ()>,
+    T: de::FromStream,
+    T::Context: Copy,
 {
-    type Context = ();
+    type Context = T::Context;
 
     async fn from_stream<D: de::Decoder>(
         context: Self::Context,
         decoder: &mut D,
     ) -> Result<Self, D::Error> {
-        let inner = BTreeMap::<Id, T>::from_stream(context, decoder).await?;
-        Ok(Self { inner })
+        struct MapVisitor<C, T> {
+            context: C,
+            marker: PhantomData<fn() -> T>,
+        }
+
+        impl<T> de::Visitor for MapVisitor<T::Context, T>
+        where
+            T: de::FromStream,
+            T::Context: Copy,
+        {
+            type Value = Map<T>;
+
+            fn expecting() -> &'static str {
+                "a map"
+            }
+
+            async fn visit_map<A: de::MapAccess>(
+                self,
+                mut map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut inner = BTreeMap::new();
+
+                while let Some(key) = map.next_key::<Id>(()).await? {
+                    let value = map.next_value::<T>(self.context).await?;
+                    inner.insert(key, value);
+                }
+
+                Ok(Map { inner })
+            }
+        }
+
+        let visitor = MapVisitor {
+            context,
+            marker: PhantomData,
+        };
+
+        decoder.decode_map(visitor).await
+    }
+}
+
+impl<T: FromStreamAt> FromStreamAt for Map<T> {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        BTreeMap::<Id, T>::from_stream_at(frame, decoder).map_ok(Self::from)
     }
 }
 
@@ -0,0 +1,97 @@
+//! A hook to count routing outcomes, e.g. to export them to Prometheus.
+
+use pathlink::PathSegment;
+
+use crate::Route;
+
+/// The outcome of one call to [`Route::route`] through a [`MeteredRouter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteOutcome {
+    /// The path routed to a handler.
+    Hit,
+    /// Nothing is mounted at the path.
+    NotFound,
+    /// The path leads to an interior directory, not a handler.
+    Directory,
+}
+
+/// A sink for [`RouteOutcome`]s, called once per route.
+///
+/// `()` is a no-op sink, and any `Fn(RouteOutcome)` is a sink. The sink is called on the routing
+/// path of every request, so it should only do something cheap, like incrementing a counter.
+pub trait RouteMetrics {
+    fn record(&self, outcome: RouteOutcome);
+}
+
+impl RouteMetrics for () {
+    fn record(&self, _outcome: RouteOutcome) {}
+}
+
+impl<F: Fn(RouteOutcome)> RouteMetrics for F {
+    fn record(&self, outcome: RouteOutcome) {
+        self(outcome)
+    }
+}
+
+/// A [`Route`] which reports the [`RouteOutcome`] of every route to a [`RouteMetrics`] sink.
+///
+/// The wrapped router may be a [`Dir`](crate::Dir), a [`FrozenDir`](crate::FrozenDir), or an
+/// `Arc` of either (e.g. a [`SharedDir`](crate::SharedDir) snapshot).
+#[derive(Clone, Debug)]
+pub struct MeteredRouter<R, M = ()> {
+    router: R,
+    metrics: M,
+}
+
+impl<R: Route> MeteredRouter<R> {
+    /// Wrap `router` with no metrics sink.
+    pub fn new(router: R) -> Self {
+        Self {
+            router,
+            metrics: (),
+        }
+    }
+}
+
+impl<R: Route, M: RouteMetrics> MeteredRouter<R, M> {
+    /// Report route outcomes to `metrics` instead.
+    pub fn with_metrics<N: RouteMetrics>(self, metrics: N) -> MeteredRouter<R, N> {
+        MeteredRouter {
+            router: self.router,
+            metrics,
+        }
+    }
+
+    /// Borrow the wrapped router.
+    pub fn router(&self) -> &R {
+        &self.router
+    }
+
+    /// Borrow the metrics sink.
+    pub fn metrics(&self) -> &M {
+        &self.metrics
+    }
+}
+
+impl<R: Route, M: RouteMetrics> Route for MeteredRouter<R, M> {
+    type Handler = R::Handler;
+
+    fn route<'a>(&'a self, path: &'a [PathSegment]) -> Option<&'a Self::Handler> {
+        let handler = self.router.route(path);
+
+        let outcome = if handler.is_some() {
+            RouteOutcome::Hit
+        } else if self.router.is_directory(path) {
+            RouteOutcome::Directory
+        } else {
+            RouteOutcome::NotFound
+        };
+
+        self.metrics.record(outcome);
+        handler
+    }
+
+    fn is_directory(&self, path: &[PathSegment]) -> bool {
+        self.router.is_directory(path)
+    }
+}
@@ -0,0 +1,169 @@
+//! Newline-delimited JSON (NDJSON) batches of [`Scalar`]s, e.g. to export a library's op catalog
+//! to a log pipeline and import it again.
+//!
+//! Each line is the compact JSON encoding of one scalar, as `destream_json` encodes it, so every
+//! line can be decoded on its own. `destream_json` writes a line break within a string as-is (and
+//! only understands escaped quotes and backslashes), so a line break is written as the standard
+//! JSON escape `\n` or `\r` to keep each scalar on one line. Before a line is decoded, every
+//! standard JSON string escape (e.g. `\t` or `\u00e9`, as other producers write them) is restored
+//! to the character it stands for.
+
+use std::io;
+
+use futures::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use tc_error::{ErrorKind, TCError, TCResult};
+
+use crate::{DecodeOptions, Scalar};
+
+/// Encode each of `items` as one line of JSON written to `writer`.
+pub async fn encode_ndjson<I, W>(items: I, mut writer: W) -> TCResult<()>
+where
+    I: IntoIterator<Item = Scalar>,
+    W: AsyncWrite + Unpin,
+{
+    for (i, item) in items.into_iter().enumerate() {
+        // e.g. a non-finite float, which JSON can't represent
+        let unsupported =
+            |err| TCError::unsupported(format!("cannot encode NDJSON item {i}: {err}"));
+
+        let mut encoded = destream_json::encode(item).map_err(unsupported)?;
+
+        while let Some(chunk) = encoded.try_next().await.map_err(unsupported)? {
+            let chunk = escape_line_breaks(&chunk);
+            writer.write_all(&chunk).await.map_err(write_error)?;
+        }
+
+        writer.write_all(b"\n").await.map_err(write_error)?;
+    }
+
+    writer.flush().await.map_err(write_error)
+}
+
+/// Decode one [`Scalar`] from each line of `reader`, skipping blank lines.
+///
+/// The scalars are decoded lazily, so a large batch is never buffered in full. An error names
+/// the (one-based) number of the line which failed to decode.
+pub fn decode_ndjson<R>(reader: R) -> impl Stream<Item = TCResult<Scalar>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| async move {
+            match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(decode_line(i + 1, line).await),
+                Err(err) => Some(Err(io_error(
+                    format!("cannot read NDJSON line {}", i + 1),
+                    err,
+                ))),
+            }
+        })
+}
+
+async fn decode_line(number: usize, line: String) -> TCResult<Scalar> {
+    let line = unescape(&line)
+        .map_err(|cause| TCError::bad_request(format!("invalid NDJSON line {number}: {cause}")))?;
+
+    let source = stream::iter([Ok::<_, io::Error>(bytes::Bytes::from(line))]);
+
+    destream_json::try_decode(DecodeOptions::default(), source)
+        .await
+        .map_err(|err| TCError::bad_request(format!("invalid NDJSON line {number}: {err}")))
+}
+
+/// Escape the line breaks in `chunk`. Outside of a string, the compact encoding has no
+/// whitespace, so a line break in the encoding must be within a string.
+fn escape_line_breaks(chunk: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(chunk.len());
+
+    for byte in chunk {
+        match byte {
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            byte => escaped.push(*byte),
+        }
+    }
+
+    escaped
+}
+
+/// Restore each JSON string escape in `line` to the character it stands for, except for escaped
+/// quotes and backslashes, which `destream_json` understands (so `\u0022` becomes `\"`).
+fn unescape(line: &str) -> Result<Vec<u8>, String> {
+    let mut unescaped = Vec::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            push_char(&mut unescaped, c);
+            continue;
+        }
+
+        let c = match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => match hex_escape(&mut chars)? {
+                high @ 0xd800..=0xdbff => {
+                    let low = match (chars.next(), chars.next()) {
+                        (Some('\\'), Some('u')) => hex_escape(&mut chars)?,
+                        _ => return Err(format!("unpaired surrogate \\u{high:04x}")),
+                    };
+
+                    if !(0xdc00..=0xdfff).contains(&low) {
+                        return Err(format!("unpaired surrogate \\u{high:04x}"));
+                    }
+
+                    let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+                    char::from_u32(code).expect("surrogate pair")
+                }
+                code => char::from_u32(code)
+                    .ok_or_else(|| format!("unpaired surrogate \\u{code:04x}"))?,
+            },
+            Some(other) => return Err(format!("invalid escape \\{other}")),
+            None => return Err("unterminated escape".to_string()),
+        };
+
+        match c {
+            '"' | '\\' => unescaped.extend([b'\\', c as u8]),
+            c => push_char(&mut unescaped, c),
+        }
+    }
+
+    Ok(unescaped)
+}
+
+/// Read the four hex digits of a `\uXXXX` escape.
+fn hex_escape(chars: &mut std::str::Chars) -> Result<u32, String> {
+    let digits = chars.take(4).collect::<String>();
+
+    if digits.len() == 4 {
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape \\u{digits}"))
+    } else {
+        Err(format!("invalid escape \\u{digits}"))
+    }
+}
+
+fn push_char(buffer: &mut Vec<u8>, c: char) {
+    buffer.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+/// An error reading or writing NDJSON which is not the fault of its content.
+fn io_error(context: String, err: io::Error) -> TCError {
+    match err.kind() {
+        io::ErrorKind::InvalidData => TCError::bad_request(format!("{context}: {err}")),
+        _ => TCError::new(ErrorKind::Unavailable, format!("{context}: {err}")),
+    }
+}
+
+fn write_error(err: io::Error) -> TCError {
+    io_error("cannot write NDJSON".to_string(), err)
+}
@@ -1,8 +1,15 @@
-use std::str::FromStr;
-
-use crate::{Id, Map, Scalar, Subject};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    future::Future,
+    str::FromStr,
+};
+
+use crate::decode::{decode_root, At, DecodeOptions, Frame, FromStreamAt, Step};
+use crate::{Id, IdPolicy, IdRef, Map, Method, Scalar, Subject, TCRef, SCALAR_PREFIX};
 use destream::{de, en, EncodeMap, IntoStream};
-use pathlink::PathBuf;
+use pathlink::{Link, PathBuf};
+use tc_error::{TCError, TCResult};
 
 /// The data defining a reference to a GET op.
 pub type GetRef = (Subject, Scalar);
@@ -26,9 +33,15 @@ pub type DeleteRef = (Subject, Scalar);
 /// Encoded as a single-entry map:
 ///
 /// - GET: `{ "<subject>": [<key>] }`
+/// - keyless GET (`key` is `None`): `{ "/state/scalar/ref/op/get": [<subject>, null] }`, since
+///   `{ "<subject>": [] }` is the wire shape of a bare link value
 /// - PUT: `{ "<subject>": [<key>, <value>] }`
 /// - POST: `{ "<subject>": { "<name>": <value>, ... } }`
 /// - DELETE: `{ "/state/scalar/ref/op/delete": [<subject>, <key>] }`
+///
+/// A subject which would not decode back as a link key (a link to a host, or a path under
+/// `/state/scalar`) always uses the explicit form, e.g.
+/// `{ "/state/scalar/ref/op/put": [<subject>, <key>, <value>] }`.
 #[derive(Clone, Debug, PartialEq)]
 pub enum OpRef {
     Get(GetRef),
@@ -37,6 +50,104 @@ pub enum OpRef {
     Delete(DeleteRef),
 }
 
+impl OpRef {
+    /// The verb of this op.
+    pub fn verb(&self) -> Method {
+        match self {
+            Self::Get(_) => Method::Get,
+            Self::Put(_) => Method::Put,
+            Self::Post(_) => Method::Post,
+            Self::Delete(_) => Method::Delete,
+        }
+    }
+
+    /// The subject of this op, i.e. the link or scoped ref it targets.
+    pub fn subject(&self) -> &Subject {
+        match self {
+            Self::Get((subject, _)) => subject,
+            Self::Put((subject, _, _)) => subject,
+            Self::Post((subject, _)) => subject,
+            Self::Delete((subject, _)) => subject,
+        }
+    }
+
+    /// A mutable reference to the subject of this op.
+    pub fn subject_mut(&mut self) -> &mut Subject {
+        match self {
+            Self::Get((subject, _)) => subject,
+            Self::Put((subject, _, _)) => subject,
+            Self::Post((subject, _)) => subject,
+            Self::Delete((subject, _)) => subject,
+        }
+    }
+
+    /// Return `true` if this op passes no arguments: a POST with no params, or a GET or DELETE
+    /// whose key is [`Scalar::none`]. A PUT always has a key and value, so it never is.
+    ///
+    /// A parameterless POST is still a POST: `{"/lib/foo": {}}` decodes (and re-encodes) as one,
+    /// while the equivalent keyless GET is `{"/state/scalar/ref/op/get": ["/lib/foo", null]}`.
+    pub fn is_parameterless(&self) -> bool {
+        match self {
+            Self::Get((_, key)) | Self::Delete((_, key)) => key.is_none(),
+            Self::Put(_) => false,
+            Self::Post((_, params)) => params.is_empty(),
+        }
+    }
+
+    /// Apply `f` to each scalar argument of this op, keeping its verb and subject.
+    pub(crate) fn map_args<F: FnMut(Scalar) -> Scalar>(self, f: &mut F) -> Self {
+        match self {
+            Self::Get((subject, key)) => Self::Get((subject, f(key))),
+            Self::Put((subject, key, value)) => Self::Put((subject, f(key), f(value))),
+            Self::Post((subject, params)) => Self::Post((
+                subject,
+                params.into_iter().map(|(k, v)| (k, f(v))).collect(),
+            )),
+            Self::Delete((subject, key)) => Self::Delete((subject, f(key))),
+        }
+    }
+
+    /// Construct an op ref from its subject and [`OpArgs`], with the same rules as the v1
+    /// subject-keyed encoding: one key is a GET, a key and value is a PUT, and a map of params is a
+    /// POST. Any other number of elements is an error. (A DELETE has no subject-keyed form.)
+    pub fn from_subject_args(subject: Subject, args: OpArgs) -> TCResult<Self> {
+        match args {
+            OpArgs::Map(params) => Ok(Self::Post((subject, params))),
+            OpArgs::Seq(items) => {
+                let mut items = items.into_iter();
+                match (items.next(), items.next(), items.next()) {
+                    (Some(key), None, None) => Ok(Self::Get((subject, key))),
+                    (Some(key), Some(value), None) => Ok(Self::Put((subject, key, value))),
+                    _ => Err(TCError::bad_request(
+                        "invalid OpRef params (expected 1 or 2 elements)",
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Wrap this op as an [`OpDef`] whose form is a single binding of `result_id` to this ref, so
+    /// it can be scheduled like any other op definition.
+    ///
+    /// The result is always a POST op definition, whatever the verb of this ref: a POST is the
+    /// general form, since it takes no key or value, and the verb of the ref itself is kept in its
+    /// binding. Any `$id` refs among this op's subject or arguments are left as they are, to be
+    /// resolved in the scope where the definition is executed.
+    pub fn into_op_def(self, result_id: Id) -> OpDef {
+        OpDef::Post(vec![(result_id, TCRef::Op(self).into())])
+    }
+
+    /// Replace the subject of this op with the result of `f`, keeping its verb and arguments.
+    pub fn map_subject<F: FnOnce(Subject) -> Subject>(self, f: F) -> Self {
+        match self {
+            Self::Get((subject, key)) => Self::Get((f(subject), key)),
+            Self::Put((subject, key, value)) => Self::Put((f(subject), key, value)),
+            Self::Post((subject, params)) => Self::Post((f(subject), params)),
+            Self::Delete((subject, key)) => Self::Delete((f(subject), key)),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum OpDefType {
     Get,
@@ -80,7 +191,15 @@ pub type PutOp = (Id, Id, Vec<(Id, Scalar)>);
 pub type PostOp = Vec<(Id, Scalar)>;
 pub type DeleteOp = (Id, Vec<(Id, Scalar)>);
 
-#[derive(Clone, Debug, PartialEq)]
+/// An op definition: a verb plus an ordered form of `(id, scalar)` bindings.
+///
+/// The form is encoded as a list of `[id, scalar]` pairs, never as a map, so the order of its
+/// bindings (which later bindings may depend on, and which may rebind an earlier id) survives a
+/// round trip: e.g. `{"/state/scalar/op/get": ["key", [["b", 1], ["a", {"$b": []}]]]}`.
+///
+/// Like [`Scalar`], the `Debug` output renders the form as an ordered `{id: scalar}` map prefixed
+/// by the verb and its parameter names (e.g. `Get(key) {...}`); `{:#?}` indents it.
+#[derive(Clone, PartialEq)]
 pub enum OpDef {
     Get(GetOp),
     Put(PutOp),
@@ -102,6 +221,38 @@ impl OpDef {
         self.form().last().map(|(id, _)| id)
     }
 
+    /// The verb this op is defined for, i.e. the method used to call it.
+    pub fn verb(&self) -> Method {
+        match self {
+            Self::Get(_) => Method::Get,
+            Self::Put(_) => Method::Put,
+            Self::Post(_) => Method::Post,
+            Self::Delete(_) => Method::Delete,
+        }
+    }
+
+    /// Iterate over the op refs anywhere in this op's form, in depth-first order: including the
+    /// branches and bodies of control-flow refs, nested op definitions, and op refs passed as the
+    /// arguments of another op ref (which follow the op ref they're passed to).
+    pub fn op_refs(&self) -> impl Iterator<Item = &OpRef> + '_ {
+        FormWalk::new(self).filter_map(|(node, _)| match node {
+            FormNode::Ref(TCRef::Op(op_ref)) => Some(op_ref),
+            _ => None,
+        })
+    }
+
+    /// Count the verbs of the op refs anywhere in this op's form, including nested ops and
+    /// branches, e.g. to report that a POST performs two GETs and a PUT.
+    pub fn referenced_verbs(&self) -> BTreeMap<Method, usize> {
+        let mut verbs = BTreeMap::new();
+
+        for op_ref in self.op_refs() {
+            *verbs.entry(op_ref.verb()).or_insert(0) += 1;
+        }
+
+        verbs
+    }
+
     fn class(&self) -> OpDefType {
         match self {
             Self::Get(_) => OpDefType::Get,
@@ -111,21 +262,285 @@ impl OpDef {
         }
     }
 
+    /// Apply `f` to the scalar of each binding in this op's form.
+    pub(crate) fn map_form<F: FnMut(Scalar) -> Scalar>(self, f: &mut F) -> Self {
+        let map = |form: Vec<(Id, Scalar)>, f: &mut F| {
+            form.into_iter()
+                .map(|(id, scalar)| (id, f(scalar)))
+                .collect()
+        };
+
+        match self {
+            Self::Get((key, form)) => Self::Get((key, map(form, f))),
+            Self::Put((key, value, form)) => Self::Put((key, value, map(form, f))),
+            Self::Post(form) => Self::Post(map(form, f)),
+            Self::Delete((key, form)) => Self::Delete((key, map(form, f))),
+        }
+    }
+
+    /// Remove the bindings of this op's form whose ids are never referenced, e.g. a `$tmp` which
+    /// nothing uses.
+    ///
+    /// A binding is live if a later binding which is itself live refers to it, by an `$id` ref or
+    /// a `$id/...` subject, anywhere within its scalar (including branches, loops, and nested op
+    /// definitions). The last binding is the result of the op, so it's always kept. If
+    /// `keep_op_refs` is set, a binding which contains an op ref is also kept (along with the
+    /// bindings it refers to), since calling it may have side effects even if its result is unused.
+    pub fn prune_dead_bindings(self, keep_op_refs: bool) -> Self {
+        let prune = |form: Vec<(Id, Scalar)>| {
+            let mut live = HashSet::new();
+            let mut keep = vec![false; form.len()];
+
+            for (i, (id, scalar)) in form.iter().enumerate().rev() {
+                let is_result = i + 1 == form.len();
+                let has_effects = keep_op_refs
+                    && FormWalk::scalar(scalar)
+                        .any(|(node, _)| matches!(node, FormNode::Ref(TCRef::Op(_))));
+
+                if is_result || live.contains(id) || has_effects {
+                    keep[i] = true;
+
+                    // a reference before this binding refers to an earlier binding of the same id
+                    live.remove(id);
+                    live.extend(referenced_ids(scalar));
+                }
+            }
+
+            form.into_iter()
+                .zip(keep)
+                .filter_map(|(binding, keep)| keep.then_some(binding))
+                .collect()
+        };
+
+        match self {
+            Self::Get((key, form)) => Self::Get((key, prune(form))),
+            Self::Put((key, value, form)) => Self::Put((key, value, prune(form))),
+            Self::Post(form) => Self::Post(prune(form)),
+            Self::Delete((key, form)) => Self::Delete((key, prune(form))),
+        }
+    }
+
+    /// Group the binding ids of this op's form into layers which a scheduler can run one after
+    /// another, running the bindings within each layer concurrently.
+    ///
+    /// A binding is in layer 0 if it refers to no earlier binding (references to the op's params,
+    /// or to ids it doesn't bind, are not dependencies), and otherwise in the layer after the
+    /// latest layer of the bindings it refers to. A reference resolves to the most recent earlier
+    /// binding of that id, as the form is executed in order, so an id which is rebound may appear
+    /// in more than one layer. Within a layer, ids are listed in form order.
+    pub fn dependency_layers(&self) -> Vec<Vec<Id>> {
+        let mut levels = HashMap::<&Id, usize>::new();
+        let mut layers: Vec<Vec<Id>> = Vec::new();
+
+        for (id, scalar) in self.form() {
+            let level = referenced_ids(scalar)
+                .filter_map(|dep| levels.get(&dep).map(|level| level + 1))
+                .max()
+                .unwrap_or(0);
+
+            if level == layers.len() {
+                layers.push(Vec::new());
+            }
+
+            layers[level].push(id.clone());
+            levels.insert(id, level);
+        }
+
+        layers
+    }
+
+    /// Rewrite every [`Link`] in this op's form; see [`Scalar::rewrite_links`].
+    pub fn rewrite_links<F: FnMut(&Link) -> Option<Link>>(self, mut f: F) -> Self {
+        self.map_form(&mut |scalar| scalar.rewrite_links_with(&mut f))
+    }
+
     pub fn walk_scalars(&self) -> OpDefScalarWalk<'_> {
         OpDefScalarWalk::new(self)
     }
+
+    /// Compute the [`Complexity`] of this op in a single pass over its form.
+    pub fn complexity(&self) -> Complexity {
+        let mut complexity = Complexity {
+            bindings: self.form().len(),
+            ..Complexity::default()
+        };
+
+        for (node, depth) in FormWalk::new(self) {
+            complexity.max_depth = complexity.max_depth.max(depth);
+
+            match node {
+                FormNode::Ref(TCRef::Op(_)) => complexity.op_refs += 1,
+                FormNode::Ref(TCRef::While(_) | TCRef::ForEach(_)) => complexity.has_loops = true,
+                _ => {}
+            }
+        }
+
+        complexity
+    }
+
+    /// Estimate the cost of executing this op by summing `model`'s estimate of each op ref in its
+    /// form, saturating at `u64::MAX`.
+    ///
+    /// The body of a loop is charged once per iteration: the number of items of a `ForEach` over
+    /// a literal tuple, or [`CostModel::loop_bound`] otherwise. A conditional is charged for its
+    /// condition plus its more expensive branch. A nested op definition which is not a loop body
+    /// is a value, and costs nothing until it's called.
+    pub fn estimated_cost(&self, model: &impl CostModel) -> u64 {
+        self.form().iter().fold(0, |cost, (_, scalar)| {
+            cost.saturating_add(scalar_cost(model, scalar))
+        })
+    }
+
+    /// Check the parameter names and binding ids of this op against `policy`, in order, and return
+    /// the first violation. The ids of nested op definitions are checked too; the keys of map
+    /// values in the form are data, not ids, and are not.
+    pub fn validate_ids(&self, policy: &impl IdPolicy) -> TCResult<()> {
+        self.validate_own_ids(policy)?;
+
+        FormWalk::new(self).try_for_each(|(node, _)| match node {
+            FormNode::Scalar(Scalar::Op(op)) => op.validate_own_ids(policy),
+            _ => Ok(()),
+        })
+    }
+
+    fn validate_own_ids(&self, policy: &impl IdPolicy) -> TCResult<()> {
+        match self {
+            Self::Get((key, _)) | Self::Delete((key, _)) => policy.check(key)?,
+            Self::Put((key, value, _)) => {
+                policy.check(key)?;
+                policy.check(value)?;
+            }
+            Self::Post(_) => {}
+        }
+
+        self.form().iter().try_for_each(|(id, _)| policy.check(id))
+    }
+
+    /// Collect every [`Link`] this op's form references, deduplicated and sorted.
+    ///
+    /// This includes the subject of each op ref and each link value (which may be passed to
+    /// another op and called from there), at any depth. Ref subjects like `$self/foo` are not
+    /// links and are excluded.
+    pub fn referenced_links(&self) -> BTreeSet<Link> {
+        let mut links = BTreeSet::new();
+
+        for (node, _) in FormWalk::new(self) {
+            match node {
+                FormNode::Ref(TCRef::Op(op_ref)) => {
+                    if let Subject::Link(link) = op_ref.subject() {
+                        links.insert(link.clone());
+                    }
+                }
+                FormNode::Scalar(Scalar::Value(tc_value::Value::Link(link))) => {
+                    links.insert(link.clone());
+                }
+                _ => {}
+            }
+        }
+
+        links
+    }
+}
+
+/// The ids of every `$id` ref and `$id/...` subject anywhere within `scalar`.
+fn referenced_ids(scalar: &Scalar) -> impl Iterator<Item = Id> + '_ {
+    FormWalk::scalar(scalar).filter_map(|(node, _)| match node {
+        FormNode::Ref(TCRef::Id(id_ref)) => Some(id_ref.id().clone()),
+        FormNode::Ref(TCRef::Op(op_ref)) => match op_ref.subject() {
+            Subject::Ref(id_ref, _) => Some(id_ref.id().clone()),
+            Subject::Link(_) => None,
+        },
+        _ => None,
+    })
+}
+
+/// Builds an op form one binding at a time, e.g. to generate a pipeline of op refs.
+///
+/// Each call to [`FormBuilder::bind`] returns an [`IdRef`] to the new binding, which a later step
+/// can use as an argument (via `Scalar::from`) or as a subject.
+#[derive(Clone, Debug, Default)]
+pub struct FormBuilder {
+    form: Vec<(Id, Scalar)>,
+}
+
+impl FormBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a binding of `id` to the result of `op`, returning a reference to it.
+    pub fn bind(&mut self, id: Id, op: OpRef) -> IdRef {
+        self.bind_scalar(id, Scalar::from(TCRef::Op(op)))
+    }
+
+    /// Append a binding of `id` to any `scalar`, returning a reference to it.
+    pub fn bind_scalar(&mut self, id: Id, scalar: Scalar) -> IdRef {
+        self.form.push((id.clone(), scalar));
+        IdRef::new(id)
+    }
+
+    /// The bindings appended so far, in order.
+    pub fn form(&self) -> &[(Id, Scalar)] {
+        &self.form
+    }
+
+    /// Finish the form as a GET op which takes the parameter `key`.
+    pub fn into_get(self, key: Id) -> OpDef {
+        OpDef::Get((key, self.form))
+    }
+
+    /// Finish the form as a PUT op which takes the parameters `key` and `value`.
+    pub fn into_put(self, key: Id, value: Id) -> OpDef {
+        OpDef::Put((key, value, self.form))
+    }
+
+    /// Finish the form as a POST op, whose parameters are passed by name.
+    pub fn into_post(self) -> OpDef {
+        OpDef::Post(self.form)
+    }
+
+    /// Finish the form as a DELETE op which takes the parameter `key`.
+    pub fn into_delete(self, key: Id) -> OpDef {
+        OpDef::Delete((key, self.form))
+    }
+}
+
+impl fmt::Debug for OpDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Get((key, _)) => write!(f, "Get({key}) ")?,
+            Self::Put((key, value, _)) => write!(f, "Put({key}, {value}) ")?,
+            Self::Post(_) => f.write_str("Post ")?,
+            Self::Delete((key, _)) => write!(f, "Delete({key}) ")?,
+        }
+
+        f.debug_map()
+            .entries(self.form().iter().map(|(id, scalar)| (id, scalar)))
+            .finish()
+    }
 }
 
 impl de::FromStream for OpDef {
-    type Context = ();
+    type Context = DecodeOptions;
 
     async fn from_stream<D: de::Decoder>(
-        _context: Self::Context,
+        options: Self::Context,
         decoder: &mut D,
     ) -> Result<Self, D::Error> {
-        struct OpDefVisitor;
+        decode_root(options, decoder).await
+    }
+}
+
+impl FromStreamAt for OpDef {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        struct OpDefVisitor<'a> {
+            frame: Frame<'a>,
+        }
 
-        impl de::Visitor for OpDefVisitor {
+        impl de::Visitor for OpDefVisitor<'_> {
             type Value = OpDef;
 
             fn expecting() -> &'static str {
@@ -146,11 +561,11 @@ impl de::FromStream for OpDef {
                     de::Error::custom("expected Op definition type, e.g. \"/state/scalar/op/get\"")
                 })?;
 
-                decode_opdef_map_entry(op_def_type, &mut map).await
+                decode_opdef_map_entry(self.frame, op_def_type, &mut map).await
             }
         }
 
-        decoder.decode_map(OpDefVisitor).await
+        decoder.decode_map(OpDefVisitor { frame })
     }
 }
 
@@ -177,15 +592,26 @@ impl<'en> en::ToStream<'en> for OpDef {
 }
 
 impl de::FromStream for OpRef {
-    type Context = ();
+    type Context = DecodeOptions;
 
     async fn from_stream<D: de::Decoder>(
-        _context: Self::Context,
+        options: Self::Context,
         decoder: &mut D,
     ) -> Result<Self, D::Error> {
-        struct OpRefVisitor;
+        decode_root(options, decoder).await
+    }
+}
+
+impl FromStreamAt for OpRef {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        struct OpRefVisitor<'a> {
+            frame: Frame<'a>,
+        }
 
-        impl de::Visitor for OpRefVisitor {
+        impl de::Visitor for OpRefVisitor<'_> {
             type Value = OpRef;
 
             fn expecting() -> &'static str {
@@ -201,29 +627,53 @@ impl de::FromStream for OpRef {
                     .await?
                     .ok_or_else(|| de::Error::custom("expected OpRef, found empty map"))?;
 
-                decode_opref_map_entry(key, &mut map).await
+                decode_opref_map_entry(self.frame, key, &mut map).await
             }
         }
 
-        decoder.decode_map(OpRefVisitor).await
+        decoder.decode_map(OpRefVisitor { frame })
     }
 }
 
 impl<'en> en::IntoStream<'en> for OpRef {
     fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
+        let explicit = requires_explicit_form(self.subject());
+
         match self {
+            OpRef::Get((subject, key)) if explicit || key.is_none() => {
+                let mut map = encoder.encode_map(Some(1))?;
+                map.encode_key(PathBuf::from(crate::OPREF_GET).to_string())?;
+                map.encode_value(SubjectScalarSeq::new(subject, key))?;
+                map.end()
+            }
             OpRef::Get((subject, key)) => {
                 let mut map = encoder.encode_map(Some(1))?;
                 map.encode_key(subject.to_string())?;
                 map.encode_value(ScalarSeq::new(vec![key]))?;
                 map.end()
             }
+            OpRef::Put((subject, key, value)) if explicit => {
+                let mut map = encoder.encode_map(Some(1))?;
+                map.encode_entry(
+                    PathBuf::from(crate::OPREF_PUT).to_string(),
+                    (subject, key, value),
+                )?;
+                map.end()
+            }
             OpRef::Put((subject, key, value)) => {
                 let mut map = encoder.encode_map(Some(1))?;
                 map.encode_key(subject.to_string())?;
                 map.encode_value(ScalarSeq::new(vec![key, value]))?;
                 map.end()
             }
+            OpRef::Post((subject, params)) if explicit => {
+                let mut map = encoder.encode_map(Some(1))?;
+                map.encode_entry(
+                    PathBuf::from(crate::OPREF_POST).to_string(),
+                    (subject, params),
+                )?;
+                map.end()
+            }
             OpRef::Post((subject, params)) => {
                 let mut map = encoder.encode_map(Some(1))?;
                 map.encode_entry(subject.to_string(), params)?;
@@ -239,6 +689,21 @@ impl<'en> en::IntoStream<'en> for OpRef {
     }
 }
 
+/// Return `true` if `subject` cannot be encoded as the key of a compact op ref, because the
+/// decoder would not read that key back as a link: either the link has a host, or its path is in
+/// the reserved `/state/scalar` namespace of typed values, op definitions, and refs.
+fn requires_explicit_form(subject: &Subject) -> bool {
+    match subject {
+        Subject::Link(link) => {
+            let path = link.path().as_ref();
+            link.host().is_some()
+                || (path.len() >= SCALAR_PREFIX.len()
+                    && path[..SCALAR_PREFIX.len()] == SCALAR_PREFIX[..])
+        }
+        Subject::Ref(..) => false,
+    }
+}
+
 impl<'en> en::ToStream<'en> for OpRef {
     fn to_stream<E: en::Encoder<'en>>(&'en self, encoder: E) -> Result<E::Ok, E::Error> {
         self.clone().into_stream(encoder)
@@ -287,23 +752,49 @@ impl<'en> en::IntoStream<'en> for SubjectScalarSeq {
     }
 }
 
-/// Internal helper used to decode `OpRef` and `TCRef` argument shapes.
+/// The arguments of an op ref in its v1 subject-keyed form, e.g. `{"/lib/foo": <args>}`.
+///
+/// A tuple of one element is the key of a GET, a tuple of two elements is the key and value of a
+/// PUT, and a map is the params of a POST (see [`OpRef::from_subject_args`]).
 #[derive(Clone, Debug, PartialEq)]
-pub(crate) enum OpArgs {
+pub enum OpArgs {
     Map(Map<Scalar>),
     Seq(Vec<Scalar>),
 }
 
+impl From<Vec<Scalar>> for OpArgs {
+    fn from(items: Vec<Scalar>) -> Self {
+        Self::Seq(items)
+    }
+}
+
+impl From<Map<Scalar>> for OpArgs {
+    fn from(params: Map<Scalar>) -> Self {
+        Self::Map(params)
+    }
+}
+
 impl de::FromStream for OpArgs {
-    type Context = ();
+    type Context = DecodeOptions;
 
     async fn from_stream<D: de::Decoder>(
-        _context: Self::Context,
+        options: Self::Context,
         decoder: &mut D,
     ) -> Result<Self, D::Error> {
-        struct ArgsVisitor;
+        decode_root(options, decoder).await
+    }
+}
+
+impl FromStreamAt for OpArgs {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        struct ArgsVisitor<'a> {
+            frame: Frame<'a>,
+        }
 
-        impl de::Visitor for ArgsVisitor {
+        impl de::Visitor for ArgsVisitor<'_> {
             type Value = OpArgs;
 
             fn expecting() -> &'static str {
@@ -316,7 +807,13 @@ impl de::FromStream for OpArgs {
             ) -> Result<Self::Value, A::Error> {
                 let mut params = Map::<Scalar>::new();
                 while let Some(key) = map.next_key::<Id>(()).await? {
-                    let value = map.next_value::<Scalar>(()).await?;
+                    if params.contains_key(&key) {
+                        return Err(de::Error::custom(format!("duplicate parameter '{key}'")));
+                    }
+
+                    let at = self.frame.child(Step::Key(key.as_str()));
+                    let At(value, _) = map.next_value::<At<Scalar>>(self.frame.within(&at)).await?;
+
                     params.insert(key, value);
                 }
                 Ok(OpArgs::Map(params))
@@ -332,27 +829,90 @@ impl de::FromStream for OpArgs {
                     Vec::new()
                 };
 
-                while let Some(item) = access.next_element::<Scalar>(()).await? {
-                    items.push(item);
+                loop {
+                    let at = self.frame.child(Step::Index(items.len()));
+                    match access
+                        .next_element::<At<Scalar>>(self.frame.within(&at))
+                        .await?
+                    {
+                        Some(At(item, _)) => items.push(item),
+                        None => break,
+                    }
                 }
 
                 Ok(OpArgs::Seq(items))
             }
         }
 
-        decoder.decode_any(ArgsVisitor).await
+        decoder.decode_any(ArgsVisitor { frame })
+    }
+}
+
+/// The form of an op definition, decoded so that an error names the index of its binding.
+struct Form(Vec<(Id, Scalar)>);
+
+impl FromStreamAt for Form {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        struct FormVisitor<'a> {
+            frame: Frame<'a>,
+        }
+
+        impl de::Visitor for FormVisitor<'_> {
+            type Value = Form;
+
+            fn expecting() -> &'static str {
+                "an Op definition form (a sequence of [id, scalar] pairs)"
+            }
+
+            async fn visit_seq<A: de::SeqAccess>(
+                self,
+                mut access: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut form = Vec::with_capacity(access.size_hint().unwrap_or(0));
+
+                loop {
+                    let at = self.frame.child(Step::Binding(form.len()));
+                    let binding = access
+                        .next_element::<At<(Id, Scalar)>>(self.frame.within(&at))
+                        .await?;
+
+                    match binding {
+                        Some(At(binding, _)) => form.push(binding),
+                        None => break Ok(Form(form)),
+                    }
+                }
+            }
+        }
+
+        decoder.decode_seq(FormVisitor { frame })
     }
 }
 
 pub(crate) async fn decode_opdef_map_entry<A: de::MapAccess>(
+    frame: Frame<'_>,
     op_def_type: OpDefType,
     map: &mut A,
 ) -> Result<OpDef, A::Error> {
     let op = match op_def_type {
-        OpDefType::Get => OpDef::Get(map.next_value::<GetOp>(()).await?),
-        OpDefType::Put => OpDef::Put(map.next_value::<PutOp>(()).await?),
-        OpDefType::Post => OpDef::Post(map.next_value::<PostOp>(()).await?),
-        OpDefType::Delete => OpDef::Delete(map.next_value::<DeleteOp>(()).await?),
+        OpDefType::Get => {
+            let At((key, Form(form)), _) = map.next_value(frame).await?;
+            OpDef::Get((key, form))
+        }
+        OpDefType::Put => {
+            let At((key, value, Form(form)), _) = map.next_value(frame).await?;
+            OpDef::Put((key, value, form))
+        }
+        OpDefType::Post => {
+            let At(Form(form), _) = map.next_value(frame).await?;
+            OpDef::Post(form)
+        }
+        OpDefType::Delete => {
+            let At((key, Form(form)), _) = map.next_value(frame).await?;
+            OpDef::Delete((key, form))
+        }
     };
 
     while map.next_key::<de::IgnoredAny>(()).await?.is_some() {
@@ -362,23 +922,15 @@ pub(crate) async fn decode_opdef_map_entry<A: de::MapAccess>(
     Ok(op)
 }
 
-pub(crate) fn opref_from_subject_args<E: de::Error>(
-    subject: Subject,
-    args: OpArgs,
-) -> Result<OpRef, E> {
-    match args {
-        OpArgs::Map(params) => Ok(OpRef::Post((subject, params))),
-        OpArgs::Seq(items) => match items.as_slice() {
-            [key] => Ok(OpRef::Get((subject, key.clone()))),
-            [key, value] => Ok(OpRef::Put((subject, key.clone(), value.clone()))),
-            _ => Err(de::Error::custom(
-                "invalid OpRef params (expected 1 or 2 elements)",
-            )),
-        },
-    }
+/// Construct an op ref from its subject and [`OpArgs`], as a decoder would.
+///
+/// This is [`OpRef::from_subject_args`], reporting an error as a decode error.
+pub fn opref_from_subject_args<E: de::Error>(subject: Subject, args: OpArgs) -> Result<OpRef, E> {
+    OpRef::from_subject_args(subject, args).map_err(|err| de::Error::custom(err.message()))
 }
 
 pub(crate) async fn decode_opref_map_entry<A: de::MapAccess>(
+    frame: Frame<'_>,
     key: String,
     map: &mut A,
 ) -> Result<OpRef, A::Error> {
@@ -386,54 +938,37 @@ pub(crate) async fn decode_opref_map_entry<A: de::MapAccess>(
         let path = PathBuf::from_str(&key).ok();
 
         if path.as_ref() == Some(&PathBuf::from(crate::OPREF_GET)) {
-            let get = map.next_value::<(Subject, Scalar)>(()).await?;
+            let get = map.next_value::<At<(Subject, Scalar)>>(frame).await?.0;
             OpRef::Get(get)
         } else if path.as_ref() == Some(&PathBuf::from(crate::OPREF_PUT)) {
-            let put = map.next_value::<(Subject, Scalar, Scalar)>(()).await?;
+            let put = map
+                .next_value::<At<(Subject, Scalar, Scalar)>>(frame)
+                .await?
+                .0;
             OpRef::Put(put)
         } else if path.as_ref() == Some(&PathBuf::from(crate::OPREF_POST)) {
-            let post = map.next_value::<(Subject, Map<Scalar>)>(()).await?;
+            let post = map.next_value::<At<(Subject, Map<Scalar>)>>(frame).await?.0;
             OpRef::Post(post)
         } else if path.as_ref() == Some(&PathBuf::from(crate::OPREF_DELETE)) {
-            let delete = map.next_value::<(Subject, Scalar)>(()).await?;
+            let delete = map.next_value::<At<(Subject, Scalar)>>(frame).await?.0;
             OpRef::Delete(delete)
         } else {
             let subject = crate::scalar::subject_from_str(&key)
                 .map_err(|err| de::Error::custom(err.to_string()))?;
 
-            let args = map.next_value::<OpArgs>(()).await?;
-            match args {
-                OpArgs::Map(params) => OpRef::Post((subject, params)),
-                OpArgs::Seq(items) => match items.as_slice() {
-                    [key] => OpRef::Get((subject, key.clone())),
-                    [key, value] => OpRef::Put((subject, key.clone(), value.clone())),
-                    _ => {
-                        return Err(de::Error::custom(
-                            "invalid OpRef params (expected 1 or 2 elements)",
-                        ));
-                    }
-                },
-            }
+            let At(args, _) = map.next_value::<At<OpArgs>>(frame).await?;
+            opref_from_subject_args(subject, args)?
         }
     } else {
         let subject = crate::scalar::subject_from_str(&key)
             .map_err(|err| de::Error::custom(err.to_string()))?;
 
-        let args = map.next_value::<OpArgs>(()).await?;
-        match args {
-            OpArgs::Map(params) => OpRef::Post((subject, params)),
-            OpArgs::Seq(items) => match items.as_slice() {
-                [key] => OpRef::Get((subject, key.clone())),
-                [key, value] => OpRef::Put((subject, key.clone(), value.clone())),
-                _ => {
-                    return Err(de::Error::custom(
-                        "invalid OpRef params (expected 1 or 2 elements)",
-                    ));
-                }
-            },
-        }
+        let At(args, _) = map.next_value::<At<OpArgs>>(frame).await?;
+        opref_from_subject_args(subject, args)?
     };
 
+    frame.check_subject(op.subject())?;
+
     while map.next_key::<de::IgnoredAny>(()).await?.is_some() {
         let _ = map.next_value::<de::IgnoredAny>(()).await?;
     }
@@ -471,3 +1006,178 @@ impl<'a> Iterator for OpDefScalarWalk<'a> {
         }
     }
 }
+
+/// Size and shape metrics for an [`OpDef`], used by a scheduler to estimate cost before execution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Complexity {
+    /// The number of bindings in the op's form.
+    pub bindings: usize,
+    /// The number of op references anywhere in the form, including nested ops and branches.
+    pub op_refs: usize,
+    /// Whether the form contains a `While` or `ForEach` loop.
+    pub has_loops: bool,
+    /// The maximum nesting depth of the form, where each binding's scalar is at depth 1.
+    pub max_depth: usize,
+}
+
+/// A runtime's estimate of what each op costs to execute, for [`OpDef::estimated_cost`].
+///
+/// The units are up to the runtime (e.g. microseconds, or an abstract weight), so that a
+/// scheduler can e.g. charge a GET to a remote link more than a local arithmetic op.
+pub trait CostModel {
+    /// Estimate the cost of resolving `op` itself, not counting the cost of its arguments.
+    fn estimate(&self, op: &OpRef) -> u64;
+
+    /// The number of iterations to assume for a loop whose length isn't known before it runs.
+    ///
+    /// Defaults to 1.
+    fn loop_bound(&self) -> u64 {
+        1
+    }
+}
+
+fn scalar_cost(model: &impl CostModel, scalar: &Scalar) -> u64 {
+    match scalar {
+        Scalar::Value(_) | Scalar::Bytes(_) | Scalar::Op(_) => 0,
+        Scalar::Map(map) => sum_cost(model, map.values()),
+        Scalar::Tuple(items) => sum_cost(model, items),
+        Scalar::Ref(tc_ref) => ref_cost(model, tc_ref),
+    }
+}
+
+fn ref_cost(model: &impl CostModel, tc_ref: &TCRef) -> u64 {
+    match tc_ref {
+        TCRef::Id(_) => 0,
+        TCRef::Op(op) => {
+            let args = match op {
+                OpRef::Get((_, key)) | OpRef::Delete((_, key)) => scalar_cost(model, key),
+                OpRef::Put((_, key, value)) => sum_cost(model, [key, value]),
+                OpRef::Post((_, params)) => sum_cost(model, params.values()),
+            };
+
+            model.estimate(op).saturating_add(args)
+        }
+        TCRef::Cond(cond) => ref_cost(model, &cond.cond)
+            .saturating_add(scalar_cost(model, &cond.then).max(scalar_cost(model, &cond.or_else))),
+        TCRef::While(while_ref) => {
+            let iteration = body_cost(model, &while_ref.cond)
+                .saturating_add(body_cost(model, &while_ref.closure));
+
+            scalar_cost(model, &while_ref.state)
+                .saturating_add(iteration.saturating_mul(model.loop_bound()))
+        }
+        TCRef::ForEach(for_each) => {
+            let iterations = match &for_each.items {
+                Scalar::Tuple(items) => items.len() as u64,
+                _ => model.loop_bound(),
+            };
+
+            scalar_cost(model, &for_each.items)
+                .saturating_add(body_cost(model, &for_each.op).saturating_mul(iterations))
+        }
+    }
+}
+
+/// The cost of one call to a loop body or condition, which may be an op definition.
+fn body_cost(model: &impl CostModel, body: &Scalar) -> u64 {
+    match body {
+        Scalar::Op(op) => op.estimated_cost(model),
+        other => scalar_cost(model, other),
+    }
+}
+
+fn sum_cost<'a, I>(model: &impl CostModel, scalars: I) -> u64
+where
+    I: IntoIterator<Item = &'a Scalar>,
+{
+    scalars.into_iter().fold(0, |cost, scalar| {
+        cost.saturating_add(scalar_cost(model, scalar))
+    })
+}
+
+/// A node visited while walking an op form, including the contents of refs and nested ops.
+pub(crate) enum FormNode<'a> {
+    Scalar(&'a Scalar),
+    Ref(&'a TCRef),
+}
+
+/// Depth-first walk over every node of an op form, paired with its nesting depth (each binding's
+/// scalar is at depth 1, and a [`Scalar::Ref`] shares the depth of the ref it wraps).
+///
+/// The walk keeps its own stack, so it does not recurse on deeply nested input.
+pub(crate) struct FormWalk<'a> {
+    stack: Vec<(FormNode<'a>, usize)>,
+}
+
+impl<'a> FormWalk<'a> {
+    pub(crate) fn new(op: &'a OpDef) -> Self {
+        let stack = op
+            .form()
+            .iter()
+            .rev()
+            .map(|(_, scalar)| (FormNode::Scalar(scalar), 1))
+            .collect();
+
+        Self { stack }
+    }
+
+    /// Walk `scalar` and everything within it, starting with `scalar` itself at depth 0.
+    pub(crate) fn scalar(scalar: &'a Scalar) -> Self {
+        Self {
+            stack: vec![(FormNode::Scalar(scalar), 0)],
+        }
+    }
+
+    fn push_scalars<I>(&mut self, scalars: I, depth: usize)
+    where
+        I: IntoIterator<Item = &'a Scalar>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        self.stack.extend(
+            scalars
+                .into_iter()
+                .rev()
+                .map(|scalar| (FormNode::Scalar(scalar), depth)),
+        );
+    }
+}
+
+impl<'a> Iterator for FormWalk<'a> {
+    type Item = (FormNode<'a>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, depth) = self.stack.pop()?;
+        let child = depth + 1;
+
+        match &node {
+            FormNode::Scalar(Scalar::Value(_) | Scalar::Bytes(_)) => {}
+            FormNode::Scalar(Scalar::Map(map)) => self.push_scalars(map.values(), child),
+            FormNode::Scalar(Scalar::Tuple(items)) => self.push_scalars(items, child),
+            FormNode::Scalar(Scalar::Op(op)) => {
+                self.push_scalars(op.form().iter().map(|(_, scalar)| scalar), child)
+            }
+            FormNode::Scalar(Scalar::Ref(tc_ref)) => {
+                self.stack.push((FormNode::Ref(tc_ref), depth));
+            }
+            FormNode::Ref(TCRef::Id(_)) => {}
+            FormNode::Ref(TCRef::Op(op)) => match op {
+                OpRef::Get((_, key)) | OpRef::Delete((_, key)) => self.push_scalars([key], child),
+                OpRef::Put((_, key, value)) => self.push_scalars([key, value], child),
+                OpRef::Post((_, params)) => self.push_scalars(params.values(), child),
+            },
+            FormNode::Ref(TCRef::Cond(cond)) => {
+                self.push_scalars([&cond.then, &cond.or_else], child);
+                self.stack.push((FormNode::Ref(&cond.cond), child));
+            }
+            FormNode::Ref(TCRef::While(while_ref)) => self.push_scalars(
+                [&while_ref.cond, &while_ref.closure, &while_ref.state],
+                child,
+            ),
+            FormNode::Ref(TCRef::ForEach(for_each)) => {
+                self.push_scalars([&for_each.items, &for_each.op], child)
+            }
+        }
+
+        Some((node, depth))
+    }
+}
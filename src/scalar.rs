@@ -1,11 +1,13 @@
-use std::{fmt, str::FromStr};
+use std::{cmp::Ordering, fmt, future::Future, str::FromStr};
 
 use destream::{de, en, IntoStream};
 use number_general::Number;
+use number_general::{Float, Int, UInt};
 use pathlink::{path_label, Link, PathBuf, PathLabel};
-use tc_error::TCError;
+use tc_error::{TCError, TCResult};
 use tc_value::{decode_typed_value_map_entry, Value};
 
+use crate::decode::{self, At, DecodeOptions, Frame, FromStreamAt, Step};
 use crate::{Id, Map};
 
 /// Scalar values exchanged via the TinyChain IR.
@@ -18,13 +20,21 @@ use crate::{Id, Map};
 /// - A scalar value is encoded like a v1 scalar value (e.g. `null`, or a typed map like
 ///   `{"\/state\/scalar\/value\/number": 3}`).
 /// - A reference is encoded as an op ref / TC ref map (see [`crate::OpRef`] and [`crate::TCRef`]).
-#[derive(Clone, Debug, PartialEq)]
+/// - A byte string is encoded as a typed map whose value is the encoder's native byte encoding,
+///   which is base64 in JSON (e.g. `{"\/state\/scalar\/value\/bytes": "3q2+7w=="}`).
+///
+/// The `Debug` output is a compact, readable rendering (not the wire encoding): values and refs
+/// are shown as-is, maps as `{key: value}`, and tuples as `(a, b)`. Use `{:#?}` to indent nested
+/// maps and tuples.
+#[derive(Clone, PartialEq)]
 pub enum Scalar {
     Value(Value),
     Ref(Box<crate::tcref::TCRef>),
     Op(crate::op::OpDef),
     Map(Map<Scalar>),
     Tuple(Vec<Scalar>),
+    /// An opaque binary blob.
+    Bytes(Vec<u8>),
 }
 
 /// A reference to a named value in a scope (e.g. "$self").
@@ -86,11 +96,23 @@ pub enum Subject {
     Ref(IdRef, PathBuf),
 }
 
+pub const SCALAR_PREFIX: PathLabel = path_label(&["state", "scalar"]);
 pub const SCALAR_REF_PREFIX: PathLabel = path_label(&["state", "scalar", "ref"]);
 pub const OPREF_PREFIX: PathLabel = path_label(&["state", "scalar", "ref", "op"]);
 pub const OPDEF_PREFIX: PathLabel = path_label(&["state", "scalar", "op"]);
 pub const OPDEF_REFLECT_PREFIX: PathLabel = path_label(&["state", "scalar", "op", "reflect"]);
 pub const SCALAR_REFLECT_PREFIX: PathLabel = path_label(&["state", "scalar", "reflect"]);
+pub const NUMBER_OP_PREFIX: PathLabel = path_label(&["state", "scalar", "value", "number"]);
+
+/// The methods of a number, which are the only ops under [`NUMBER_OP_PREFIX`] that a strict
+/// decode accepts (see [`crate::UnknownClassPolicy::Reject`]). These are the methods a v1 host
+/// routes on a number.
+pub const NUMBER_METHODS: [&str; 33] = [
+    "abs", "acos", "acosh", "add", "and", "asin", "asinh", "atan", "atanh", "cos", "cosh", "div",
+    "exp", "ge", "gt", "imag", "le", "ln", "log", "lt", "mod", "mul", "not", "or", "pow", "real",
+    "round", "sin", "sinh", "sub", "tan", "tanh", "xor",
+];
+pub const SCALAR_BYTES: PathLabel = path_label(&["state", "scalar", "value", "bytes"]);
 pub const SCALAR_MAP: PathLabel = path_label(&["state", "scalar", "map"]);
 pub const SCALAR_TUPLE: PathLabel = path_label(&["state", "scalar", "tuple"]);
 pub const OPREF_GET: PathLabel = path_label(&["state", "scalar", "ref", "op", "get"]);
@@ -101,6 +123,9 @@ pub const TCREF_IF: PathLabel = path_label(&["state", "scalar", "ref", "if"]);
 pub const TCREF_COND: PathLabel = path_label(&["state", "scalar", "ref", "cond"]);
 pub const TCREF_WHILE: PathLabel = path_label(&["state", "scalar", "ref", "while"]);
 pub const TCREF_FOR_EACH: PathLabel = path_label(&["state", "scalar", "ref", "for_each"]);
+pub const TCREF_AFTER: PathLabel = path_label(&["state", "scalar", "ref", "after"]);
+pub const TCREF_CASE: PathLabel = path_label(&["state", "scalar", "ref", "case"]);
+pub const TCREF_WITH: PathLabel = path_label(&["state", "scalar", "ref", "with"]);
 pub const OPDEF_GET: PathLabel = path_label(&["state", "scalar", "op", "get"]);
 pub const OPDEF_PUT: PathLabel = path_label(&["state", "scalar", "op", "put"]);
 pub const OPDEF_POST: PathLabel = path_label(&["state", "scalar", "op", "post"]);
@@ -144,6 +169,27 @@ impl fmt::Display for IdRef {
     }
 }
 
+impl Subject {
+    /// Resolve this subject to a concrete [`Link`]: a `Link` subject is returned unchanged, and a
+    /// ref like `$self/foo/bar` is resolved to the link its id is bound to in `bindings`, plus its
+    /// suffix path. Returns a "not found" error if the id is not bound.
+    pub fn resolve_ref(&self, bindings: &Map<Link>) -> TCResult<Link> {
+        match self {
+            Self::Link(link) => Ok(link.clone()),
+            Self::Ref(id_ref, suffix) => {
+                let link = bindings.get(id_ref.id()).ok_or_else(|| {
+                    TCError::not_found(format!("a link bound to {id_ref} (to resolve {self})"))
+                })?;
+
+                Ok(suffix
+                    .iter()
+                    .cloned()
+                    .fold(link.clone(), |link, segment| link.append(segment)))
+            }
+        }
+    }
+}
+
 impl fmt::Display for Subject {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -154,6 +200,19 @@ impl fmt::Display for Subject {
     }
 }
 
+impl From<Link> for Subject {
+    fn from(link: Link) -> Self {
+        Self::Link(link)
+    }
+}
+
+impl From<IdRef> for Subject {
+    /// A ref to `id_ref` itself, with an empty suffix path.
+    fn from(id_ref: IdRef) -> Self {
+        Self::Ref(id_ref, PathBuf::default())
+    }
+}
+
 impl de::FromStream for Subject {
     type Context = ();
 
@@ -180,15 +239,26 @@ impl<'en> en::ToStream<'en> for Subject {
 }
 
 impl de::FromStream for Scalar {
-    type Context = ();
+    type Context = DecodeOptions;
 
     async fn from_stream<D: de::Decoder>(
-        _context: Self::Context,
+        options: Self::Context,
         decoder: &mut D,
     ) -> Result<Self, D::Error> {
-        struct ScalarVisitor;
+        decode::decode_root(options, decoder).await
+    }
+}
+
+impl FromStreamAt for Scalar {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        struct ScalarVisitor<'a> {
+            frame: Frame<'a>,
+        }
 
-        impl de::Visitor for ScalarVisitor {
+        impl de::Visitor for ScalarVisitor<'_> {
             type Value = Scalar;
 
             fn expecting() -> &'static str {
@@ -216,6 +286,13 @@ impl de::FromStream for Scalar {
             }
 
             fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+                // e.g. an overflowing exponent like `1e999`, which could not be re-encoded
+                if !value.is_finite() {
+                    return Err(de::Error::custom(format!(
+                        "invalid Scalar number {value} (expected a finite float)"
+                    )));
+                }
+
                 Ok(Scalar::Value(Value::Number(Number::from(value))))
             }
 
@@ -233,8 +310,15 @@ impl de::FromStream for Scalar {
                     Vec::new()
                 };
 
-                while let Some(value) = seq.next_element::<Scalar>(()).await? {
-                    items.push(value);
+                loop {
+                    let at = self.frame.child(Step::Index(items.len()));
+                    match seq
+                        .next_element::<At<Scalar>>(self.frame.within(&at))
+                        .await?
+                    {
+                        Some(At(value, _)) => items.push(value),
+                        None => break,
+                    }
                 }
 
                 Ok(Scalar::Tuple(items))
@@ -249,26 +333,39 @@ impl de::FromStream for Scalar {
                 };
 
                 if key.starts_with('/') {
+                    let key_path = PathBuf::from_str(&key).ok();
+
+                    if key_path == Some(PathBuf::from(SCALAR_BYTES)) {
+                        let bytes = map.next_value::<bytes::Bytes>(()).await?;
+                        while map.next_key::<de::IgnoredAny>(()).await?.is_some() {
+                            let _ = map.next_value::<de::IgnoredAny>(()).await?;
+                        }
+                        return Ok(Scalar::Bytes(bytes.to_vec()));
+                    }
+
                     if let Some(value) = decode_typed_value_map_entry(&key, &mut map).await? {
                         return Ok(Scalar::Value(value));
                     }
 
-                    let key_path = PathBuf::from_str(&key).ok();
-
                     if let Some(path) = key_path.as_ref() {
                         if let Some(op_def_type) = crate::op::OpDefType::from_path(path) {
-                            let op_def =
-                                crate::op::decode_opdef_map_entry(op_def_type, &mut map).await?;
+                            let op_def = crate::op::decode_opdef_map_entry(
+                                self.frame,
+                                op_def_type,
+                                &mut map,
+                            )
+                            .await?;
                             return Ok(Scalar::Op(op_def));
                         }
 
                         if is_tcref_or_opref_path(path) {
-                            let r = crate::tcref::decode_tcref_map_entry(key, &mut map).await?;
+                            let r = crate::tcref::decode_tcref_map_entry(self.frame, key, &mut map)
+                                .await?;
                             return Ok(Scalar::Ref(Box::new(r)));
                         }
                     }
 
-                    let args = map.next_value::<crate::op::OpArgs>(()).await?;
+                    let At(args, _) = map.next_value::<At<crate::op::OpArgs>>(self.frame).await?;
                     if let crate::op::OpArgs::Seq(items) = &args {
                         if items.is_empty() {
                             if let Ok(link) = Link::from_str(&key) {
@@ -282,6 +379,7 @@ impl de::FromStream for Scalar {
 
                     let subject =
                         subject_from_str(&key).map_err(|err| de::Error::custom(err.to_string()))?;
+                    self.frame.check_subject(&subject)?;
                     let op = crate::op::opref_from_subject_args::<A::Error>(subject, args)?;
                     while map.next_key::<de::IgnoredAny>(()).await?.is_some() {
                         let _ = map.next_value::<de::IgnoredAny>(()).await?;
@@ -290,22 +388,31 @@ impl de::FromStream for Scalar {
                 }
 
                 if key.starts_with('$') {
-                    let r = crate::tcref::decode_tcref_map_entry(key, &mut map).await?;
+                    let r = crate::tcref::decode_tcref_map_entry(self.frame, key, &mut map).await?;
                     return Ok(Scalar::Ref(Box::new(r)));
                 }
 
                 let mut out = Map::new();
-                let value = map.next_value::<Scalar>(()).await?;
+                let at = self.frame.child(Step::Key(&key));
+                let At(value, _) = map.next_value::<At<Scalar>>(self.frame.within(&at)).await?;
+
                 let id: Id = key
                     .parse::<Id>()
                     .map_err(|err| de::Error::custom(err.to_string()))?;
                 out.insert(id, value);
 
                 while let Some(key) = map.next_key::<String>(()).await? {
-                    let value = map.next_value::<Scalar>(()).await?;
+                    let at = self.frame.child(Step::Key(&key));
+                    let At(value, _) = map.next_value::<At<Scalar>>(self.frame.within(&at)).await?;
+
                     let id: Id = key
                         .parse::<Id>()
                         .map_err(|err| de::Error::custom(err.to_string()))?;
+
+                    if out.contains_key(&id) {
+                        return Err(de::Error::custom(format!("duplicate key '{id}'")));
+                    }
+
                     out.insert(id, value);
                 }
 
@@ -313,7 +420,7 @@ impl de::FromStream for Scalar {
             }
         }
 
-        decoder.decode_any(ScalarVisitor).await
+        decoder.decode_any(ScalarVisitor { frame })
     }
 }
 
@@ -325,6 +432,16 @@ impl<'en> en::IntoStream<'en> for Scalar {
             Scalar::Op(op) => op.into_stream(encoder),
             Scalar::Map(map) => map.into_stream(encoder),
             Scalar::Tuple(tuple) => tuple.into_stream(encoder),
+            Scalar::Bytes(bytes) => {
+                use destream::en::EncodeMap;
+
+                let mut map = encoder.encode_map(Some(1))?;
+                map.encode_entry(
+                    PathBuf::from(SCALAR_BYTES).to_string(),
+                    bytes::Bytes::from(bytes),
+                )?;
+                map.end()
+            }
         }
     }
 }
@@ -340,13 +457,55 @@ fn is_tcref_or_opref_path(path: &PathBuf) -> bool {
         || path == &PathBuf::from(TCREF_COND)
         || path == &PathBuf::from(TCREF_WHILE)
         || path == &PathBuf::from(TCREF_FOR_EACH)
+        || path == &PathBuf::from(TCREF_AFTER)
+        || path == &PathBuf::from(TCREF_CASE)
+        || path == &PathBuf::from(TCREF_WITH)
         || path == &PathBuf::from(OPREF_GET)
         || path == &PathBuf::from(OPREF_PUT)
         || path == &PathBuf::from(OPREF_POST)
         || path == &PathBuf::from(OPREF_DELETE)
 }
 
+/// The op ref wrapped in `map`, by the rule of [`Scalar::canonicalize_refs`].
+fn wrapped_op_ref(map: &Map<Scalar>) -> Option<crate::OpRef> {
+    if map.len() != 1 {
+        return None;
+    }
+
+    let (key, value) = map.iter().next()?;
+    let key = key.as_str();
+
+    if !key.starts_with('/') && !key.starts_with('$') {
+        return None;
+    }
+
+    if let Ok(path) = PathBuf::from_str(key) {
+        if path.len() >= SCALAR_PREFIX.len() && path[..SCALAR_PREFIX.len()] == SCALAR_PREFIX[..] {
+            return None;
+        }
+    }
+
+    let args = match value {
+        Scalar::Tuple(items) => crate::OpArgs::Seq(items.clone()),
+        Scalar::Map(params) => crate::OpArgs::Map(params.clone()),
+        _ => return None,
+    };
+
+    let subject = subject_from_str(key).ok()?;
+    crate::OpRef::from_subject_args(subject, args).ok()
+}
+
 pub(crate) fn subject_from_str(s: &str) -> Result<Subject, TCError> {
+    // catch the malformed subjects which the id and link parsers would report unhelpfully
+    match s {
+        "" => return Err(TCError::bad_request("empty subject")),
+        "$" => return Err(TCError::bad_request("ref id must follow '$'")),
+        _ if s.starts_with("$/") => {
+            return Err(TCError::bad_request("missing ref id before '/'"));
+        }
+        _ => {}
+    }
+
     if s.starts_with('$') {
         if let Some(i) = s.find('/') {
             let id = &s[..i];
@@ -365,6 +524,25 @@ pub(crate) fn subject_from_str(s: &str) -> Result<Subject, TCError> {
     }
 }
 
+impl fmt::Debug for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scalar::Value(value) => fmt::Debug::fmt(value, f),
+            Scalar::Ref(r) => fmt::Debug::fmt(r, f),
+            Scalar::Op(op) => fmt::Debug::fmt(op, f),
+            Scalar::Map(map) => f.debug_map().entries(map.iter()).finish(),
+            Scalar::Tuple(items) => {
+                let mut tuple = f.debug_tuple("");
+                for item in items {
+                    tuple.field(item);
+                }
+                tuple.finish()
+            }
+            Scalar::Bytes(bytes) => write!(f, "b\"{}\"", bytes.escape_ascii()),
+        }
+    }
+}
+
 impl Default for Scalar {
     fn default() -> Self {
         Scalar::Value(Value::default())
@@ -377,6 +555,12 @@ impl From<Value> for Scalar {
     }
 }
 
+impl From<IdRef> for Scalar {
+    fn from(id_ref: IdRef) -> Self {
+        Scalar::Ref(Box::new(crate::tcref::TCRef::Id(id_ref)))
+    }
+}
+
 impl From<crate::tcref::TCRef> for Scalar {
     fn from(value: crate::tcref::TCRef) -> Self {
         Scalar::Ref(Box::new(value))
@@ -389,17 +573,486 @@ impl From<crate::op::OpDef> for Scalar {
     }
 }
 
+/// `()` converts to [`Scalar::none`], the empty success response.
+impl From<()> for Scalar {
+    fn from(_: ()) -> Self {
+        Scalar::none()
+    }
+}
+
+impl From<Vec<u8>> for Scalar {
+    fn from(bytes: Vec<u8>) -> Self {
+        Scalar::Bytes(bytes)
+    }
+}
+
 impl From<u64> for Scalar {
     fn from(value: u64) -> Self {
         Scalar::Value(Value::from(value))
     }
 }
 
+impl From<i64> for Scalar {
+    fn from(value: i64) -> Self {
+        Scalar::Value(Value::Number(Number::from(value)))
+    }
+}
+
+/// An `i32` is widened to an `i64`, e.g. for an unsuffixed integer literal in [`crate::scalar!`].
+impl From<i32> for Scalar {
+    fn from(value: i32) -> Self {
+        Scalar::from(i64::from(value))
+    }
+}
+
+impl From<f64> for Scalar {
+    fn from(value: f64) -> Self {
+        Scalar::Value(Value::Number(Number::from(value)))
+    }
+}
+
+impl From<bool> for Scalar {
+    fn from(value: bool) -> Self {
+        Scalar::Value(Value::Number(Number::from(value)))
+    }
+}
+
+impl From<&str> for Scalar {
+    fn from(value: &str) -> Self {
+        Scalar::Value(Value::String(value.to_string()))
+    }
+}
+
+impl From<String> for Scalar {
+    fn from(value: String) -> Self {
+        Scalar::Value(Value::String(value))
+    }
+}
+
+/// Construct a [`Scalar`] from JSON-like syntax, like `serde_json::json!`.
+///
+/// Maps, tuples, `null`, and number, string, and bool literals are supported, at any depth. Any
+/// other expression is converted with `Scalar::from`, e.g. to splice in an op ref. Map keys must be
+/// string literals which parse as an [`Id`]; the macro panics if one doesn't.
+///
+/// ```
+/// use tc_ir::{scalar, Map, Scalar, TCRef};
+///
+/// let op: TCRef = "$self".parse::<tc_ir::IdRef>().map(TCRef::Id).unwrap();
+/// let config = scalar!({ "x": 1, "items": [1, 2.5, "three", null], "op": (op.clone()) });
+///
+/// let Scalar::Map(map) = &config else { panic!() };
+/// assert_eq!(map.get("x"), Some(&Scalar::from(1)));
+/// assert_eq!(map.get("op"), Some(&Scalar::from(op)));
+/// ```
+#[macro_export]
+macro_rules! scalar {
+    (null) => {
+        $crate::Scalar::none()
+    };
+    ([ $($items:tt)* ]) => {
+        $crate::Scalar::Tuple($crate::scalar!(@tuple [] $($items)*))
+    };
+    ({ $($entries:tt)* }) => {
+        $crate::Scalar::Map($crate::scalar!(@map [] $($entries)*))
+    };
+    ($other:expr) => {
+        $crate::Scalar::from($other)
+    };
+
+    (@tuple [$($item:expr,)*]) => {
+        ::std::vec![$($item),*]
+    };
+    (@tuple [$($item:expr,)*] null $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@tuple [$($item,)* $crate::Scalar::none(),] $($($rest)*)?)
+    };
+    (@tuple [$($item:expr,)*] [ $($inner:tt)* ] $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@tuple [$($item,)* $crate::scalar!([$($inner)*]),] $($($rest)*)?)
+    };
+    (@tuple [$($item:expr,)*] { $($inner:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@tuple [$($item,)* $crate::scalar!({$($inner)*}),] $($($rest)*)?)
+    };
+    (@tuple [$($item:expr,)*] $next:expr $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@tuple [$($item,)* $crate::Scalar::from($next),] $($($rest)*)?)
+    };
+
+    (@map [$(($key:expr, $value:expr),)*]) => {
+        [$(($key, $value)),*]
+            .into_iter()
+            .map(|(key, value): (&str, $crate::Scalar)| {
+                let key = key
+                    .parse::<$crate::Id>()
+                    .unwrap_or_else(|cause| panic!("invalid scalar! map key {key:?}: {cause}"));
+
+                (key, value)
+            })
+            .collect::<$crate::Map<$crate::Scalar>>()
+    };
+    (@map [$($entry:tt)*] $key:literal : null $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@map [$($entry)* ($key, $crate::Scalar::none()),] $($($rest)*)?)
+    };
+    (@map [$($entry:tt)*] $key:literal : [ $($inner:tt)* ] $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@map [$($entry)* ($key, $crate::scalar!([$($inner)*])),] $($($rest)*)?)
+    };
+    (@map [$($entry:tt)*] $key:literal : { $($inner:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@map [$($entry)* ($key, $crate::scalar!({$($inner)*})),] $($($rest)*)?)
+    };
+    (@map [$($entry:tt)*] $key:literal : $value:expr $(, $($rest:tt)*)?) => {
+        $crate::scalar!(@map [$($entry)* ($key, $crate::Scalar::from($value)),] $($($rest)*)?)
+    };
+}
+
+impl TryFrom<Scalar> for u64 {
+    type Error = TCError;
+
+    /// Strictly convert an unsigned or non-negative integer scalar into a `u64`. Floats are
+    /// rejected even if they're integral; see [`Scalar::as_u64_lossy`].
+    fn try_from(scalar: Scalar) -> TCResult<Self> {
+        match scalar {
+            Scalar::Value(Value::Number(Number::UInt(n))) => Ok(n.into()),
+            Scalar::Value(Value::Number(Number::Int(n))) if i64::from(n) >= 0 => {
+                Ok(i64::from(n) as u64)
+            }
+            other => Err(TCError::unexpected(other, "an unsigned integer")),
+        }
+    }
+}
+
+impl TryFrom<Scalar> for String {
+    type Error = TCError;
+
+    fn try_from(scalar: Scalar) -> TCResult<Self> {
+        match scalar {
+            Scalar::Value(Value::String(string)) => Ok(string.to_string()),
+            other => Err(TCError::unexpected(other, "a string")),
+        }
+    }
+}
+
+impl<T1, T2> TryFrom<Scalar> for (T1, T2)
+where
+    T1: TryFrom<Scalar, Error = TCError>,
+    T2: TryFrom<Scalar, Error = TCError>,
+{
+    type Error = TCError;
+
+    /// Destructure a tuple of exactly two elements, converting each in turn.
+    fn try_from(scalar: Scalar) -> TCResult<Self> {
+        let [a, b] = tuple_items(scalar)?;
+        Ok((tuple_item(0, a)?, tuple_item(1, b)?))
+    }
+}
+
+impl<T1, T2, T3> TryFrom<Scalar> for (T1, T2, T3)
+where
+    T1: TryFrom<Scalar, Error = TCError>,
+    T2: TryFrom<Scalar, Error = TCError>,
+    T3: TryFrom<Scalar, Error = TCError>,
+{
+    type Error = TCError;
+
+    /// Destructure a tuple of exactly three elements, converting each in turn.
+    fn try_from(scalar: Scalar) -> TCResult<Self> {
+        let [a, b, c] = tuple_items(scalar)?;
+        Ok((tuple_item(0, a)?, tuple_item(1, b)?, tuple_item(2, c)?))
+    }
+}
+
+fn tuple_items<const N: usize>(scalar: Scalar) -> TCResult<[Scalar; N]> {
+    match scalar {
+        Scalar::Tuple(items) => items.try_into().map_err(|items: Vec<Scalar>| {
+            TCError::bad_request(format!(
+                "expected a tuple of {N} elements but found {}",
+                items.len()
+            ))
+        }),
+        other => Err(TCError::unexpected(other, "a tuple")),
+    }
+}
+
+fn tuple_item<T: TryFrom<Scalar, Error = TCError>>(i: usize, item: Scalar) -> TCResult<T> {
+    T::try_from(item)
+        .map_err(|cause| TCError::bad_request(format!("tuple element {i}: {}", cause.message())))
+}
+
 impl Scalar {
+    /// The empty success response: a mutation which completed without a meaningful payload.
+    ///
+    /// This is `Value::None`, which encodes as `null`.
+    pub fn none() -> Self {
+        Scalar::Value(Value::None)
+    }
+
+    /// Return `true` if this is [`Scalar::none`].
+    pub fn is_none(&self) -> bool {
+        matches!(self, Scalar::Value(Value::None))
+    }
+
+    /// Borrow the contents of this scalar if it is a byte string.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Scalar::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
     pub fn walk(&self) -> ScalarWalk<'_> {
         ScalarWalk::new(self)
     }
 
+    /// Order two scalar values, e.g. to evaluate a condition like `$i < 10`.
+    ///
+    /// Numbers are ordered numerically (across number classes, so `1 < 2.5`) and strings
+    /// lexically by byte. Every other pair is incomparable and returns `None`: a number and a
+    /// string, any non-value scalar (map, tuple, ref, or op), and complex numbers.
+    pub fn partial_cmp_value(&self, other: &Scalar) -> Option<Ordering> {
+        match (self, other) {
+            (Scalar::Value(Value::Number(l)), Scalar::Value(Value::Number(r))) => l.partial_cmp(r),
+            (Scalar::Value(Value::String(l)), Scalar::Value(Value::String(r))) => Some(l.cmp(r)),
+            _ => None,
+        }
+    }
+
+    /// Read this scalar as a `u64`, also accepting an integral float like `3.0`, which some JSON
+    /// clients emit for whole numbers.
+    ///
+    /// A float is only accepted if converting it loses nothing: it must be finite, have no
+    /// fractional part (so `3.5` is rejected rather than truncated), and be in the range of a
+    /// `u64`. Negative numbers, booleans, complex numbers, and non-numbers return `None`.
+    pub fn as_u64_lossy(&self) -> Option<u64> {
+        // 2^64, the first float past the end of the u64 range (`u64::MAX as f64` rounds up to it)
+        const U64_END: f64 = 18_446_744_073_709_551_616.0;
+
+        match self {
+            Scalar::Value(Value::Number(Number::Float(f))) => {
+                let f = f64::from(*f);
+                let integral = f.is_finite() && f.fract() == 0.0;
+                (integral && (0.0..U64_END).contains(&f)).then_some(f as u64)
+            }
+            Scalar::Value(Value::Number(Number::UInt(n))) => Some(u64::from(*n)),
+            Scalar::Value(Value::Number(Number::Int(n))) => u64::try_from(i64::from(*n)).ok(),
+            _ => None,
+        }
+    }
+
+    /// Compare two scalars by value, e.g. to evaluate a condition like `$status == 200`.
+    ///
+    /// Numbers are compared after coercion to their common number class, so `1u64`, `1i64`, and
+    /// `1.0` are all equal to one another (and `true` equals `1`). This means `1.0 == 1` holds:
+    /// a condition should not depend on whether a client happened to encode a whole number with
+    /// a decimal point. Maps and tuples are compared element-wise by the same rule; every other
+    /// pair of scalars is compared exactly.
+    pub fn value_eq(&self, other: &Scalar) -> bool {
+        match (self, other) {
+            // comparing two numbers casts both to the wider of their two number classes
+            (Scalar::Value(Value::Number(l)), Scalar::Value(Value::Number(r))) => l == r,
+            (Scalar::Map(l), Scalar::Map(r)) => {
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r.iter())
+                        .all(|((l_key, l), (r_key, r))| l_key == r_key && l.value_eq(r))
+            }
+            (Scalar::Tuple(l), Scalar::Tuple(r)) => {
+                l.len() == r.len() && l.iter().zip(r).all(|(l, r)| l.value_eq(r))
+            }
+            (l, r) => l == r,
+        }
+    }
+
+    /// Consume this scalar as a map, yielding its entries in key order.
+    pub fn into_entries(self) -> TCResult<impl Iterator<Item = (Id, Scalar)>> {
+        match self {
+            Scalar::Map(map) => Ok(map.into_iter()),
+            other => Err(TCError::unexpected(other, "a map")),
+        }
+    }
+
+    /// Consume this scalar as a tuple, yielding its elements in order.
+    pub fn into_elements(self) -> TCResult<impl Iterator<Item = Scalar>> {
+        match self {
+            Scalar::Tuple(items) => Ok(items.into_iter()),
+            other => Err(TCError::unexpected(other, "a tuple")),
+        }
+    }
+
+    /// Fold the elements of this tuple into an accumulator, stopping at the first error.
+    pub fn try_fold<B, F>(self, init: B, f: F) -> TCResult<B>
+    where
+        F: FnMut(B, Scalar) -> TCResult<B>,
+    {
+        self.into_elements()?.try_fold(init, f)
+    }
+
+    /// Look up a nested scalar by a pointer like `/servers/0/name`, descending into maps by key
+    /// and into tuples by index.
+    ///
+    /// The empty pointer `""` refers to this scalar itself. Returns `None` for a missing key, an
+    /// out-of-range index, a step into any other kind of scalar, or a pointer which does not start
+    /// with `/`.
+    pub fn get_path(&self, pointer: &str) -> Option<&Scalar> {
+        pointer_steps(pointer)?.try_fold(self, |scalar, step| match scalar {
+            Scalar::Map(map) => map.get(step),
+            Scalar::Tuple(items) => items.get(pointer_index(step)?),
+            _ => None,
+        })
+    }
+
+    /// Like [`Scalar::get_path`], but borrowing the nested scalar mutably.
+    pub fn get_path_mut(&mut self, pointer: &str) -> Option<&mut Scalar> {
+        pointer_steps(pointer)?.try_fold(self, |scalar, step| match scalar {
+            Scalar::Map(map) => map.get_mut(step),
+            Scalar::Tuple(items) => items.get_mut(pointer_index(step)?),
+            _ => None,
+        })
+    }
+
+    /// Replace the nested scalar at `pointer` (see [`Scalar::get_path`]) with `new`, in place,
+    /// and return the scalar it replaced. The rest of the tree is not cloned or rebuilt.
+    ///
+    /// Returns a "not found" error if `pointer` does not resolve; this never inserts a new key.
+    pub fn replace_at(&mut self, pointer: &str, new: Scalar) -> TCResult<Scalar> {
+        self.get_path_mut(pointer)
+            .map(|old| std::mem::replace(old, new))
+            .ok_or_else(|| TCError::not_found(format!("no scalar at pointer {pointer:?}")))
+    }
+
+    /// The nesting depth of this scalar: 1 for a value, ref, op, or empty collection, and 1 plus
+    /// the greatest depth of its children for a non-empty map or tuple.
+    ///
+    /// Like [`Scalar::walk`], this looks inside maps and tuples only, and it does not recurse, so
+    /// it is safe to call on arbitrarily deep input.
+    pub fn depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(self, 1)];
+
+        while let Some((scalar, depth)) = stack.pop() {
+            max_depth = max_depth.max(depth);
+
+            match scalar {
+                Scalar::Map(map) => stack.extend(map.values().map(|child| (child, depth + 1))),
+                Scalar::Tuple(items) => stack.extend(items.iter().map(|child| (child, depth + 1))),
+                _ => {}
+            }
+        }
+
+        max_depth
+    }
+
+    /// Count every node of this scalar: each value, ref, op definition, map, and tuple, including
+    /// itself and the contents of refs and op forms.
+    ///
+    /// This does not recurse, so it is safe to call on arbitrarily deep input.
+    pub fn node_count(&self) -> usize {
+        crate::op::FormWalk::scalar(self)
+            .filter(|(node, _)| {
+                // a ref is counted once, as the `FormNode::Ref` which follows its `Scalar::Ref`
+                !matches!(node, crate::op::FormNode::Scalar(Scalar::Ref(_)))
+            })
+            .count()
+    }
+
+    /// Walk this scalar like [`Scalar::walk`], pairing each node with its [`ScalarPath`].
+    pub fn walk_paths(&self) -> ScalarPathWalk<'_> {
+        ScalarPathWalk::new(self)
+    }
+
+    /// Replace constant arithmetic subexpressions with their literal results.
+    ///
+    /// The foldable form is a GET ref whose subject is `/state/scalar/value/number/<op>` (with no
+    /// host) and whose key is a tuple of two literal numbers, e.g.
+    /// `{"/state/scalar/ref/op/get": ["/state/scalar/value/number/add", [1, 2]]}` (a GET to a
+    /// subject under `/state/scalar` always uses the explicit form). The foldable ops are `add`,
+    /// `sub`, and `mul`, and both operands must be of the same 64-bit class (`u64`, `i64`, or
+    /// `f64`, which is what JSON numbers decode to). Integer overflow is not folded. Every other
+    /// scalar, including refs with a non-literal argument, passes through unchanged. Folding is
+    /// applied bottom-up, so nested constant expressions fold completely.
+    pub fn fold_constants(self) -> Scalar {
+        match self.map_children(&mut Scalar::fold_constants) {
+            Scalar::Ref(tc_ref) => match fold_number_op(&tc_ref) {
+                Some(number) => Scalar::Value(Value::Number(number)),
+                None => Scalar::Ref(tc_ref),
+            },
+            other => other,
+        }
+    }
+
+    /// Rewrite every [`Link`] in this scalar, e.g. to promote a form from a staging host to a
+    /// production host.
+    ///
+    /// `f` is called for each `Value::Link` and each link subject of an op ref, at any depth
+    /// (including nested op forms). Returning `Some(link)` replaces the link, `None` keeps it.
+    /// Ref subjects like `$x/path` are not links and are left untouched.
+    pub fn rewrite_links<F: FnMut(&Link) -> Option<Link>>(self, mut f: F) -> Scalar {
+        self.rewrite_links_with(&mut f)
+    }
+
+    pub(crate) fn rewrite_links_with<F: FnMut(&Link) -> Option<Link>>(self, f: &mut F) -> Scalar {
+        use crate::TCRef;
+
+        let scalar = self.map_children(&mut |child| child.rewrite_links_with(f));
+        let mut rewrite = |link: Link| f(&link).unwrap_or(link);
+
+        match scalar {
+            Scalar::Value(Value::Link(link)) => Scalar::Value(Value::Link(rewrite(link))),
+            Scalar::Ref(tc_ref) => match *tc_ref {
+                TCRef::Op(op) => {
+                    Scalar::Ref(Box::new(TCRef::Op(op.map_subject(
+                        |subject| match subject {
+                            Subject::Link(link) => Subject::Link(rewrite(link)),
+                            subject => subject,
+                        },
+                    ))))
+                }
+                other => Scalar::Ref(Box::new(other)),
+            },
+            other => other,
+        }
+    }
+
+    /// Rewrite each op ref which is wrapped in a [`Scalar::Map`] to a [`Scalar::Ref`], anywhere in
+    /// this scalar (including refs and nested op forms), so that downstream code only has to
+    /// handle one representation of an op ref.
+    ///
+    /// The decoder never produces such a map, since a decoded map key is a valid [`Id`], which
+    /// can't contain `/` or `$`. But an encoder which builds its keys without validating them
+    /// (e.g. with [`hr_id::label`]) can. A map is rewritten if and only if:
+    ///
+    /// - it has exactly one entry,
+    /// - its key starts with `/` or `$` and parses as a [`Subject`],
+    /// - its key is not under `/state/scalar`, where a key names a class whose data may look like
+    ///   op args (e.g. an op definition), and
+    /// - its value is valid op args (see [`crate::OpRef::from_subject_args`]): a tuple of one
+    ///   element (a GET), a tuple of two elements (a PUT), or a map (a POST).
+    ///
+    /// Any other map is left as-is. In particular an empty tuple is not op args, so
+    /// `{"/lib/acme/foo": []}` is left alone, although the decoder reads that encoding as a link.
+    pub fn canonicalize_refs(self) -> Scalar {
+        let scalar = self.map_children(&mut Scalar::canonicalize_refs);
+
+        let Scalar::Map(map) = scalar else {
+            return scalar;
+        };
+
+        match wrapped_op_ref(&map) {
+            Some(op) => Scalar::Ref(Box::new(crate::TCRef::Op(op))),
+            None => Scalar::Map(map),
+        }
+    }
+
+    /// Apply `f` to each direct child of this scalar, including the scalars within op forms and
+    /// refs. The subject of an op ref is not a scalar and is left as-is. If `f` replaces the
+    /// condition of a `Cond` ref with a scalar which is not a ref, the original condition is kept.
+    pub(crate) fn map_children<F: FnMut(Scalar) -> Scalar>(self, f: &mut F) -> Scalar {
+        match self {
+            Scalar::Value(value) => Scalar::Value(value),
+            Scalar::Map(map) => Scalar::Map(map.into_iter().map(|(k, v)| (k, f(v))).collect()),
+            Scalar::Tuple(items) => Scalar::Tuple(items.into_iter().map(&mut *f).collect()),
+            Scalar::Op(op) => Scalar::Op(op.map_form(f)),
+            Scalar::Ref(tc_ref) => Scalar::Ref(Box::new(tc_ref.map_scalars(f))),
+            Scalar::Bytes(bytes) => Scalar::Bytes(bytes),
+        }
+    }
+
     pub fn walk_tcref(&self) -> impl Iterator<Item = &crate::tcref::TCRef> {
         self.walk().filter_map(|scalar| match scalar {
             Scalar::Ref(r) => Some(r.as_ref()),
@@ -408,6 +1061,92 @@ impl Scalar {
     }
 }
 
+fn pointer_steps(pointer: &str) -> Option<impl Iterator<Item = &str>> {
+    let steps = match pointer {
+        "" => None,
+        pointer => Some(pointer.strip_prefix('/')?.split('/')),
+    };
+
+    Some(steps.into_iter().flatten())
+}
+
+fn pointer_index(step: &str) -> Option<usize> {
+    // reject forms like `+1` which `usize::from_str` would otherwise accept
+    if step.bytes().all(|byte| byte.is_ascii_digit()) {
+        step.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn fold_number_op(tc_ref: &crate::tcref::TCRef) -> Option<Number> {
+    use crate::{OpRef, TCRef};
+
+    let TCRef::Op(OpRef::Get((Subject::Link(link), Scalar::Tuple(args)))) = tc_ref else {
+        return None;
+    };
+
+    let path = link.path().as_ref();
+    if link.host().is_some() || path.len() != 5 || path[..4] != NUMBER_OP_PREFIX[..] {
+        return None;
+    }
+
+    let (l, r) = match args.as_slice() {
+        [Scalar::Value(Value::Number(l)), Scalar::Value(Value::Number(r))] => (*l, *r),
+        _ => return None,
+    };
+
+    let op = path[4].as_str();
+    match (l, r) {
+        (Number::UInt(UInt::U64(l)), Number::UInt(UInt::U64(r))) => match op {
+            "add" => l.checked_add(r),
+            "sub" => l.checked_sub(r),
+            "mul" => l.checked_mul(r),
+            _ => None,
+        }
+        .map(Number::from),
+        (Number::Int(Int::I64(l)), Number::Int(Int::I64(r))) => match op {
+            "add" => l.checked_add(r),
+            "sub" => l.checked_sub(r),
+            "mul" => l.checked_mul(r),
+            _ => None,
+        }
+        .map(Number::from),
+        (Number::Float(Float::F64(l)), Number::Float(Float::F64(r))) => match op {
+            "add" => Some(l + r),
+            "sub" => Some(l - r),
+            "mul" => Some(l * r),
+            _ => None,
+        }
+        .map(Number::from),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl Scalar {
+    /// Decode a [`Scalar`] from YAML, e.g. a library op form written by hand.
+    ///
+    /// The YAML document is converted to JSON and decoded exactly like a JSON scalar, so typed
+    /// keys like `/state/scalar/op/get` and subject keys like `/lib/acme/foo` are recognized. Map
+    /// keys must be strings.
+    ///
+    /// Like any other `destream` decode this is async, so the caller chooses how to run it
+    /// rather than having a thread of its executor blocked.
+    pub async fn from_yaml_str(yaml: &str) -> TCResult<Self> {
+        let json: serde_json::Value = serde_yaml::from_str(yaml)
+            .map_err(|err| TCError::bad_request(format!("invalid YAML scalar: {err}")))?;
+
+        let json = serde_json::to_vec(&json)
+            .map_err(|err| TCError::bad_request(format!("invalid YAML scalar: {err}")))?;
+
+        let source = futures::stream::iter([Ok::<_, std::io::Error>(bytes::Bytes::from(json))]);
+        destream_json::try_decode(DecodeOptions::default(), source)
+            .await
+            .map_err(|err| TCError::bad_request(format!("invalid YAML scalar: {err}")))
+    }
+}
+
 pub struct ScalarWalk<'a> {
     stack: Vec<&'a Scalar>,
 }
@@ -441,3 +1180,103 @@ impl<'a> Iterator for ScalarWalk<'a> {
         Some(next)
     }
 }
+
+/// One step of a [`ScalarPath`]: a map key or a tuple index.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ScalarPathStep {
+    Key(Id),
+    Index(usize),
+}
+
+/// The location of a node within a [`Scalar`], as a breadcrumb of map keys and tuple indices.
+///
+/// Displayed like `form[2].params.x`; the root is displayed as an empty string.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ScalarPath {
+    steps: Vec<ScalarPathStep>,
+}
+
+impl ScalarPath {
+    /// The path to the root of a scalar.
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    /// The steps of this path, from the root down.
+    pub fn steps(&self) -> &[ScalarPathStep] {
+        &self.steps
+    }
+
+    /// Return `true` if this is the path to the root.
+    pub fn is_root(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Construct the path to a child of the node at this path.
+    pub fn child(&self, step: ScalarPathStep) -> Self {
+        let mut steps = Vec::with_capacity(self.steps.len() + 1);
+        steps.extend_from_slice(&self.steps);
+        steps.push(step);
+        Self { steps }
+    }
+}
+
+impl FromIterator<ScalarPathStep> for ScalarPath {
+    fn from_iter<I: IntoIterator<Item = ScalarPathStep>>(iter: I) -> Self {
+        Self {
+            steps: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for ScalarPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps.iter().enumerate() {
+            match step {
+                ScalarPathStep::Key(key) if i == 0 => write!(f, "{key}")?,
+                ScalarPathStep::Key(key) => write!(f, ".{key}")?,
+                ScalarPathStep::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ScalarPathWalk<'a> {
+    stack: Vec<(ScalarPath, &'a Scalar)>,
+}
+
+impl<'a> ScalarPathWalk<'a> {
+    fn new(root: &'a Scalar) -> Self {
+        Self {
+            stack: vec![(ScalarPath::root(), root)],
+        }
+    }
+}
+
+impl<'a> Iterator for ScalarPathWalk<'a> {
+    type Item = (ScalarPath, &'a Scalar);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, next) = self.stack.pop()?;
+
+        match next {
+            Scalar::Map(map) => {
+                for (key, value) in map.iter().rev() {
+                    let child = path.child(ScalarPathStep::Key(key.clone()));
+                    self.stack.push((child, value));
+                }
+            }
+            Scalar::Tuple(items) => {
+                for (index, value) in items.iter().enumerate().rev() {
+                    let child = path.child(ScalarPathStep::Index(index));
+                    self.stack.push((child, value));
+                }
+            }
+            _ => {}
+        }
+
+        Some((path, next))
+    }
+}
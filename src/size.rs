@@ -0,0 +1,192 @@
+//! An approximate breakdown of the encoded size of a [`Scalar`], for debugging payload bloat.
+
+use std::fmt;
+
+use pathlink::{PathBuf, PathLabel};
+use tc_value::Value;
+
+use crate::{
+    FormNode, FormWalk, OpDef, OpRef, Scalar, TCRef, OPDEF_DELETE, OPDEF_GET, OPDEF_POST,
+    OPDEF_PUT, SCALAR_BYTES, TCREF_COND, TCREF_FOR_EACH, TCREF_WHILE,
+};
+
+/// The approximate number of bytes of the JSON encoding of a [`Scalar`] attributed to each kind
+/// of content, as computed by [`Scalar::size_report`].
+///
+/// The estimate ignores string escapes and whitespace, so it is not byte-exact, but it shows
+/// where the bytes of a payload go.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// String values, including their quotes.
+    pub strings: usize,
+    /// Number values.
+    pub numbers: usize,
+    /// Link values, including their quotes.
+    pub links: usize,
+    /// Byte strings, as base64.
+    pub bytes: usize,
+    /// Other values, like `null`.
+    pub other: usize,
+    /// The keys of maps and POST params, including their quotes and colons.
+    pub map_keys: usize,
+    /// The subjects and brackets of refs, and the class keys of control-flow refs.
+    pub refs: usize,
+    /// The class keys, parameter names, and binding ids of op definitions.
+    pub ops: usize,
+    /// Brackets, braces, and commas of maps and tuples, and the class keys of byte strings.
+    pub structure: usize,
+}
+
+impl SizeReport {
+    /// The approximate total encoded size.
+    pub fn total(&self) -> usize {
+        self.strings
+            + self.numbers
+            + self.links
+            + self.bytes
+            + self.other
+            + self.map_keys
+            + self.refs
+            + self.ops
+            + self.structure
+    }
+
+    fn categories(&self) -> [(&'static str, usize); 9] {
+        [
+            ("strings", self.strings),
+            ("numbers", self.numbers),
+            ("links", self.links),
+            ("bytes", self.bytes),
+            ("other", self.other),
+            ("map keys", self.map_keys),
+            ("refs", self.refs),
+            ("ops", self.ops),
+            ("structure", self.structure),
+        ]
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+
+        for (name, size) in self.categories() {
+            let percent = (size * 100).checked_div(total).unwrap_or(0);
+            writeln!(f, "{name:<10} {size:>10} ({percent}%)")?;
+        }
+
+        write!(f, "{:<10} {total:>10}", "total")
+    }
+}
+
+impl Scalar {
+    /// Estimate how many bytes of the JSON encoding of this scalar go to each kind of content.
+    ///
+    /// This is a diagnostic aid (e.g. to find out why a library manifest is so large), not a
+    /// substitute for encoding it.
+    pub fn size_report(&self) -> SizeReport {
+        let mut report = SizeReport::default();
+
+        for (node, _) in FormWalk::scalar(self) {
+            match node {
+                FormNode::Scalar(scalar) => measure_scalar(&mut report, scalar),
+                FormNode::Ref(tc_ref) => measure_ref(&mut report, tc_ref),
+            }
+        }
+
+        report
+    }
+}
+
+fn measure_scalar(report: &mut SizeReport, scalar: &Scalar) {
+    match scalar {
+        Scalar::Value(Value::String(string)) => report.strings += quoted(string.len()),
+        Scalar::Value(Value::Number(number)) => report.numbers += number.to_string().len(),
+        Scalar::Value(Value::Link(link)) => report.links += quoted(link.to_string().len()),
+        Scalar::Value(other) => {
+            // the canonical encoding is a reasonable proxy for values with no obvious JSON size
+            report.other += Scalar::Value(other.clone()).canonical_bytes().len()
+        }
+        Scalar::Bytes(bytes) => {
+            report.bytes += quoted(bytes.len().div_ceil(3) * 4);
+            report.structure += class_key(SCALAR_BYTES);
+        }
+        Scalar::Map(map) => {
+            report.map_keys += map.keys().map(|key| key_len(key.as_str())).sum::<usize>();
+            report.structure += brackets(map.len());
+        }
+        Scalar::Tuple(items) => report.structure += brackets(items.len()),
+        Scalar::Op(op) => report.ops += op_def_overhead(op),
+        Scalar::Ref(_) => {}
+    }
+}
+
+fn measure_ref(report: &mut SizeReport, tc_ref: &TCRef) {
+    report.refs += match tc_ref {
+        // {"$id": []}
+        TCRef::Id(id_ref) => quoted(id_ref.to_string().len()) + 3 + brackets(0),
+        TCRef::Op(op_ref) => {
+            let subject = quoted(op_ref.subject().to_string().len()) + 3;
+
+            match op_ref {
+                OpRef::Get(_) | OpRef::Delete(_) => subject + brackets(1),
+                OpRef::Put(_) => subject + brackets(2),
+                OpRef::Post((_, params)) => {
+                    report.map_keys += params
+                        .keys()
+                        .map(|key| key_len(key.as_str()))
+                        .sum::<usize>();
+
+                    subject + brackets(params.len())
+                }
+            }
+        }
+        TCRef::Cond(_) => class_key(TCREF_COND) + brackets(3),
+        TCRef::While(_) => class_key(TCREF_WHILE) + brackets(3),
+        TCRef::ForEach(for_each) => {
+            class_key(TCREF_FOR_EACH) + brackets(3) + quoted(for_each.item_name.as_str().len())
+        }
+    }
+}
+
+fn op_def_overhead(op: &OpDef) -> usize {
+    let (class, params) = match op {
+        OpDef::Get((key, _)) => (OPDEF_GET, vec![key]),
+        OpDef::Put((key, value, _)) => (OPDEF_PUT, vec![key, value]),
+        OpDef::Post(_) => (OPDEF_POST, vec![]),
+        OpDef::Delete((key, _)) => (OPDEF_DELETE, vec![key]),
+    };
+
+    // {"<class>": ["<param>", ..., [["<id>", <scalar>], ...]]}
+    let names = params
+        .iter()
+        .map(|param| quoted(param.as_str().len()))
+        .sum::<usize>();
+
+    let bindings = op
+        .form()
+        .iter()
+        .map(|(id, _)| quoted(id.as_str().len()) + brackets(2))
+        .sum::<usize>();
+
+    class_key(class) + brackets(params.len() + 1) + names + brackets(op.form().len()) + bindings
+}
+
+/// The size of `{"<class>": ...}` around the encoding of a class's data.
+fn class_key(class: PathLabel) -> usize {
+    quoted(PathBuf::from(class).to_string().len()) + 3
+}
+
+/// The size of the brackets and commas around a collection of `len` items.
+fn brackets(len: usize) -> usize {
+    2 + len.saturating_sub(1)
+}
+
+/// The size of a map key, with its quotes and colon.
+fn key_len(key: &str) -> usize {
+    quoted(key.len()) + 1
+}
+
+fn quoted(len: usize) -> usize {
+    len + 2
+}
@@ -1,8 +1,10 @@
+use std::future::Future;
 use std::str::FromStr;
 
 use destream::{de, en, IntoStream};
 use pathlink::PathBuf;
 
+use crate::decode::{decode_root, At, DecodeOptions, Frame, FromStreamAt};
 use crate::{Id, IdRef, Scalar};
 use tc_value::Value;
 
@@ -25,6 +27,48 @@ pub enum TCRef {
     ForEach(Box<ForEach>),
 }
 
+impl TCRef {
+    /// Apply `f` to each scalar within this ref (see [`Scalar::map_children`]).
+    pub(crate) fn map_scalars<F: FnMut(Scalar) -> Scalar>(self, f: &mut F) -> Self {
+        match self {
+            Self::Op(op) => Self::Op(op.map_args(f)),
+            Self::Id(id_ref) => Self::Id(id_ref),
+            Self::Cond(cond) => {
+                let Cond {
+                    cond,
+                    then,
+                    or_else,
+                } = *cond;
+
+                let cond = match f(Scalar::from(cond.clone())) {
+                    Scalar::Ref(mapped) => *mapped,
+                    _ => cond,
+                };
+
+                Self::Cond(Box::new(Cond::new(cond, f(then), f(or_else))))
+            }
+            Self::While(while_ref) => {
+                let While {
+                    cond,
+                    closure,
+                    state,
+                } = *while_ref;
+
+                Self::While(Box::new(While::new(f(cond), f(closure), f(state))))
+            }
+            Self::ForEach(for_each) => {
+                let ForEach {
+                    items,
+                    op,
+                    item_name,
+                } = *for_each;
+
+                Self::ForEach(Box::new(ForEach::new(f(items), f(op), item_name)))
+            }
+        }
+    }
+}
+
 /// A conditional reference with scalar branches.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Cond {
@@ -80,15 +124,26 @@ impl ForEach {
 }
 
 impl de::FromStream for TCRef {
-    type Context = ();
+    type Context = DecodeOptions;
 
     async fn from_stream<D: de::Decoder>(
-        _context: Self::Context,
+        options: Self::Context,
         decoder: &mut D,
     ) -> Result<Self, D::Error> {
-        struct RefVisitor;
+        decode_root(options, decoder).await
+    }
+}
+
+impl FromStreamAt for TCRef {
+    fn from_stream_at<D: de::Decoder>(
+        frame: Frame<'_>,
+        decoder: &mut D,
+    ) -> impl Future<Output = Result<Self, D::Error>> + Send {
+        struct RefVisitor<'a> {
+            frame: Frame<'a>,
+        }
 
-        impl de::Visitor for RefVisitor {
+        impl de::Visitor for RefVisitor<'_> {
             type Value = TCRef;
 
             fn expecting() -> &'static str {
@@ -104,11 +159,11 @@ impl de::FromStream for TCRef {
                     .await?
                     .ok_or_else(|| de::Error::custom("expected ref map key"))?;
 
-                decode_tcref_map_entry(key, &mut map).await
+                decode_tcref_map_entry(self.frame, key, &mut map).await
             }
         }
 
-        decoder.decode_map(RefVisitor).await
+        decoder.decode_map(RefVisitor { frame })
     }
 }
 
@@ -131,6 +186,7 @@ impl<'en> en::ToStream<'en> for TCRef {
 }
 
 pub(crate) async fn decode_tcref_map_entry<A: de::MapAccess>(
+    frame: Frame<'_>,
     key: String,
     map: &mut A,
 ) -> Result<TCRef, A::Error> {
@@ -139,8 +195,21 @@ pub(crate) async fn decode_tcref_map_entry<A: de::MapAccess>(
     } else {
         None
     };
+    if let Some(path) = key_path.as_ref() {
+        if path == &PathBuf::from(crate::TCREF_AFTER)
+            || path == &PathBuf::from(crate::TCREF_CASE)
+            || path == &PathBuf::from(crate::TCREF_WITH)
+        {
+            return Err(de::Error::custom(format!(
+                "unsupported v1 ref type {path} (not yet part of the v2 IR)"
+            )));
+        }
+
+        frame.check_control_flow(path)?;
+    }
+
     if key_path.as_ref() == Some(&PathBuf::from(crate::TCREF_IF)) {
-        let items = map.next_value::<Vec<Scalar>>(()).await?;
+        let At(items, _) = map.next_value::<At<Vec<Scalar>>>(frame).await?;
         let mut iter = items.into_iter();
         let (cond, then, or_else) = match (iter.next(), iter.next(), iter.next(), iter.next()) {
             (Some(cond), Some(then), Some(or_else), None) => (cond, then, or_else),
@@ -168,7 +237,7 @@ pub(crate) async fn decode_tcref_map_entry<A: de::MapAccess>(
     }
 
     if key_path.as_ref() == Some(&PathBuf::from(crate::TCREF_COND)) {
-        let items = map.next_value::<Vec<Scalar>>(()).await?;
+        let At(items, _) = map.next_value::<At<Vec<Scalar>>>(frame).await?;
         let mut iter = items.into_iter();
         let (cond, then, or_else) = match (iter.next(), iter.next(), iter.next(), iter.next()) {
             (Some(cond), Some(then), Some(or_else), None) => (cond, then, or_else),
@@ -196,7 +265,7 @@ pub(crate) async fn decode_tcref_map_entry<A: de::MapAccess>(
     }
 
     if key_path.as_ref() == Some(&PathBuf::from(crate::TCREF_WHILE)) {
-        let items = map.next_value::<Vec<Scalar>>(()).await?;
+        let At(items, _) = map.next_value::<At<Vec<Scalar>>>(frame).await?;
         let mut iter = items.into_iter();
         let (cond, closure, state) = match (iter.next(), iter.next(), iter.next(), iter.next()) {
             (Some(cond), Some(closure), Some(state), None) => (cond, closure, state),
@@ -215,7 +284,7 @@ pub(crate) async fn decode_tcref_map_entry<A: de::MapAccess>(
     }
 
     if key_path.as_ref() == Some(&PathBuf::from(crate::TCREF_FOR_EACH)) {
-        let items = map.next_value::<Vec<Scalar>>(()).await?;
+        let At(items, _) = map.next_value::<At<Vec<Scalar>>>(frame).await?;
         let mut iter = items.into_iter();
         let (items, op, item_name) = match (iter.next(), iter.next(), iter.next(), iter.next()) {
             (Some(items), Some(op), Some(item_name), None) => (items, op, item_name),
@@ -245,7 +314,7 @@ pub(crate) async fn decode_tcref_map_entry<A: de::MapAccess>(
     }
 
     if key.starts_with('$') {
-        let args = map.next_value::<crate::op::OpArgs>(()).await?;
+        let At(args, _) = map.next_value::<At<crate::op::OpArgs>>(frame).await?;
         if let crate::op::OpArgs::Seq(items) = &args {
             if items.is_empty() {
                 let id_ref =
@@ -260,7 +329,7 @@ pub(crate) async fn decode_tcref_map_entry<A: de::MapAccess>(
         return Ok(TCRef::Op(op));
     }
 
-    let op = crate::op::decode_opref_map_entry(key, map).await?;
+    let op = crate::op::decode_opref_map_entry(frame, key, map).await?;
     Ok(TCRef::Op(op))
 }
 
@@ -1,8 +1,20 @@
-use std::{fmt, str::FromStr};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use destream::{de, en, EncodeMap, IntoStream};
 use pathlink::Link;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tc_error::{ErrorKind, TCError, TCResult};
+
+use crate::{claim::redact_link, library::check_format_version, Claim, FORMAT_VERSION_KEY};
+
 /// Network time as nanoseconds since Unix epoch.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct NetworkTime(u64);
@@ -15,8 +27,52 @@ impl NetworkTime {
     pub const fn as_nanos(&self) -> u64 {
         self.0
     }
+
+    /// The current time according to the system clock.
+    ///
+    /// This is the local clock, not a consensus timestamp, so it suits a transaction which only
+    /// this process will see (like a [`SimpleTxn`]). A clock set before the Unix epoch reads as
+    /// zero.
+    pub fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Self(u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX))
+    }
+
+    /// Construct a `NetworkTime` from milliseconds since the Unix epoch, or `None` if that many
+    /// nanoseconds would overflow a `u64`.
+    pub const fn from_millis(millis: u64) -> Option<Self> {
+        match millis.checked_mul(NANOS_PER_MILLI) {
+            Some(nanos) => Some(Self(nanos)),
+            None => None,
+        }
+    }
+
+    /// Construct a `NetworkTime` from seconds since the Unix epoch, or `None` if that many
+    /// nanoseconds would overflow a `u64`.
+    pub const fn from_secs(secs: u64) -> Option<Self> {
+        match secs.checked_mul(NANOS_PER_SEC) {
+            Some(nanos) => Some(Self(nanos)),
+            None => None,
+        }
+    }
+
+    /// Whole milliseconds since the Unix epoch, truncating any remainder.
+    pub const fn as_millis(&self) -> u64 {
+        self.0 / NANOS_PER_MILLI
+    }
+
+    /// Whole seconds since the Unix epoch, truncating any remainder.
+    pub const fn as_secs(&self) -> u64 {
+        self.0 / NANOS_PER_SEC
+    }
 }
 
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
 impl fmt::Display for NetworkTime {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -32,6 +88,9 @@ impl FromStr for NetworkTime {
     }
 }
 
+/// The length of the binary encoding of a [`TxnId`].
+pub const TXN_ID_LEN: usize = 42;
+
 /// The unique ID of a transaction, copied from `tc-transact` (with serde support).
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct TxnId {
@@ -50,6 +109,23 @@ impl TxnId {
         }
     }
 
+    /// The least ID with the given `timestamp` (nonce zero, zero trace).
+    ///
+    /// Together with [`TxnId::max_for`] this bounds a window of time in an ordered collection,
+    /// e.g. `pending.range(TxnId::min_for(start)..=TxnId::max_for(end))`.
+    pub const fn min_for(timestamp: NetworkTime) -> Self {
+        Self::from_parts(timestamp, 0)
+    }
+
+    /// The greatest ID with the given `timestamp` (nonce `u16::MAX`, trace of all `0xff` bytes).
+    pub const fn max_for(timestamp: NetworkTime) -> Self {
+        Self {
+            timestamp,
+            nonce: u16::MAX,
+            trace: [u8::MAX; 32],
+        }
+    }
+
     /// Attach a tracing hash (host + txn) to this ID.
     pub fn with_trace(mut self, trace: [u8; 32]) -> Self {
         self.trace = trace;
@@ -70,6 +146,71 @@ impl TxnId {
     pub const fn trace_bytes(&self) -> &[u8; 32] {
         &self.trace
     }
+
+    /// Return the next ID after this one from the same issuer (with the same trace).
+    ///
+    /// This increments the nonce; if the nonce is already `u16::MAX`, it instead moves the
+    /// timestamp forward by one nanosecond and resets the nonce to zero. Either way the result is
+    /// strictly greater than `self`, so repeatedly advancing yields a monotonic sequence of IDs
+    /// even when many transactions start at the same timestamp.
+    ///
+    /// # Panics
+    /// Panics if the timestamp would overflow `u64::MAX` nanoseconds.
+    pub fn advance(&self) -> TxnId {
+        let (timestamp, nonce) = match self.nonce.checked_add(1) {
+            Some(nonce) => (self.timestamp, nonce),
+            None => {
+                let nanos = self.timestamp.as_nanos().checked_add(1);
+                let nanos = nanos.expect("TxnId timestamp overflow");
+                (NetworkTime::from_nanos(nanos), 0)
+            }
+        };
+
+        Self::from_parts(timestamp, nonce).with_trace(self.trace)
+    }
+
+    /// Return `true` if `other` has the same timestamp and nonce as this ID but a different trace.
+    ///
+    /// Two such IDs were issued independently (e.g. by different hosts) at the same logical
+    /// time. They are still totally ordered (by trace, as a last resort), but that order carries
+    /// no causal meaning, so a consensus component may need to treat them as conflicting.
+    pub fn is_concurrent_with(&self, other: &TxnId) -> bool {
+        self.timestamp == other.timestamp && self.nonce == other.nonce && self.trace != other.trace
+    }
+
+    /// Encode this ID in its fixed binary layout: the big-endian timestamp (8 bytes), then the
+    /// big-endian nonce (2 bytes), then the trace (32 bytes).
+    ///
+    /// This layout is stable across versions, for transports which prefer fixed binary framing.
+    pub fn to_bytes(&self) -> [u8; TXN_ID_LEN] {
+        let mut bytes = [0u8; TXN_ID_LEN];
+        bytes[..8].copy_from_slice(&self.timestamp.as_nanos().to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.nonce.to_be_bytes());
+        bytes[10..].copy_from_slice(&self.trace);
+        bytes
+    }
+
+    /// Decode an ID from the binary layout written by [`TxnId::to_bytes`].
+    pub fn from_bytes(bytes: &[u8; TXN_ID_LEN]) -> Self {
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&bytes[..8]);
+
+        let mut trace = [0u8; 32];
+        trace.copy_from_slice(&bytes[10..]);
+
+        Self::from_parts(
+            NetworkTime::from_nanos(u64::from_be_bytes(timestamp)),
+            u16::from_be_bytes([bytes[8], bytes[9]]),
+        )
+        .with_trace(trace)
+    }
+}
+
+/// Sort a batch of transaction IDs by the full [`TxnId`] order: timestamp, then nonce, then trace.
+///
+/// Concurrent IDs (see [`TxnId::is_concurrent_with`]) end up adjacent, ordered by trace.
+pub fn sort_txn_ids(ids: &mut [TxnId]) {
+    ids.sort_unstable();
 }
 
 fn decode_hex_byte(pair: &str) -> Result<u8, &'static str> {
@@ -120,6 +261,31 @@ impl FromStr for TxnId {
     }
 }
 
+/// A cooperative cancellation signal, e.g. for a client disconnect or a missed deadline.
+///
+/// Clones share the same flag, so the runtime can keep one clone and cancel it while a handler
+/// future polls another via [`Transaction::cancel_token`].
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signal cancellation to every clone of this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Return `true` if this token (or any clone of it) has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
 /// Basic transaction context every handler receives.
 pub trait Transaction: Send + Sync {
     /// Unique identifier chosen by the control plane.
@@ -130,14 +296,101 @@ pub trait Transaction: Send + Sync {
 
     /// Authorization claim scoped to this transaction.
     fn claim(&self) -> &Claim;
+
+    /// The link this request targets, e.g. to resolve `$self` or to log the request.
+    ///
+    /// This may be narrower than the link of the [`Transaction::claim`], which is the scope the
+    /// caller is authorized for. Defaults to the claimed link.
+    fn target(&self) -> &Link {
+        &self.claim().link
+    }
+
+    /// The cancellation signal for this transaction, if the runtime provides one.
+    ///
+    /// Long-running handler futures should check it between steps and stop early once it is
+    /// cancelled.
+    fn cancel_token(&self) -> Option<&CancelToken> {
+        None
+    }
+}
+
+/// A self-contained [`Transaction`] for a single-shot op, e.g. to call a handler from a CLI or a
+/// test without a control plane.
+///
+/// [`SimpleTxn::new`] stamps the transaction with [`NetworkTime::now`] and nonce zero, so two
+/// transactions constructed in the same nanosecond share an ID; use [`SimpleTxn::from_header`] to
+/// choose the ID.
+#[derive(Clone, Debug)]
+pub struct SimpleTxn {
+    header: TxnHeader,
+    cancel: CancelToken,
+}
+
+impl SimpleTxn {
+    pub fn new(claim: Claim) -> Self {
+        let now = NetworkTime::now();
+        Self::from_header(TxnHeader::new(TxnId::from_parts(now, 0), now, claim))
+    }
+
+    /// Construct a transaction with the ID, timestamp, claim, and target of `header`.
+    pub fn from_header(header: TxnHeader) -> Self {
+        Self {
+            header,
+            cancel: CancelToken::new(),
+        }
+    }
+
+    /// Replace the request target of this transaction, keeping its claim.
+    pub fn with_target(mut self, target: Link) -> Self {
+        self.header = self.header.with_target(target);
+        self
+    }
+
+    /// The header of this transaction, e.g. to forward it with a sub-request.
+    pub fn header(&self) -> &TxnHeader {
+        &self.header
+    }
+
+    /// Signal the handler futures of this transaction to stop; see [`Transaction::cancel_token`].
+    pub fn cancel(&self) {
+        self.cancel.cancel()
+    }
+}
+
+impl Transaction for SimpleTxn {
+    fn id(&self) -> TxnId {
+        self.header.id()
+    }
+
+    fn timestamp(&self) -> NetworkTime {
+        self.header.timestamp()
+    }
+
+    fn claim(&self) -> &Claim {
+        self.header.claim()
+    }
+
+    fn target(&self) -> &Link {
+        self.header.target()
+    }
+
+    fn cancel_token(&self) -> Option<&CancelToken> {
+        Some(&self.cancel)
+    }
 }
 
 /// Serializable header that conveys transaction context across process or WASM boundaries.
+///
+/// The request target defaults to the claimed link, and is only encoded when it differs. The
+/// deadline and parent transaction are optional, and only encoded when present.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TxnHeader {
     id: TxnId,
     timestamp: NetworkTime,
     claim: Claim,
+    target: Link,
+    deadline: Option<NetworkTime>,
+    parent: Option<TxnId>,
 }
 
 impl TxnHeader {
@@ -145,12 +398,15 @@ impl TxnHeader {
         Self {
             id,
             timestamp,
+            target: claim.link.clone(),
             claim,
+            deadline: None,
+            parent: None,
         }
     }
 
     pub fn from_transaction<T: Transaction + ?Sized>(txn: &T) -> Self {
-        Self::new(txn.id(), txn.timestamp(), txn.claim().clone())
+        Self::new(txn.id(), txn.timestamp(), txn.claim().clone()).with_target(txn.target().clone())
     }
 
     pub fn id(&self) -> TxnId {
@@ -164,8 +420,161 @@ impl TxnHeader {
     pub fn claim(&self) -> &Claim {
         &self.claim
     }
+
+    /// The link this request targets; see [`Transaction::target`].
+    pub fn target(&self) -> &Link {
+        &self.target
+    }
+
+    /// Replace the request target of this header, keeping its claim.
+    pub fn with_target(mut self, target: Link) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// The time by which this transaction must complete, if any.
+    pub fn deadline(&self) -> Option<NetworkTime> {
+        self.deadline
+    }
+
+    /// Set the time by which this transaction must complete.
+    pub fn with_deadline(mut self, deadline: NetworkTime) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// The transaction which issued this one as a sub-request, if any.
+    pub fn parent(&self) -> Option<TxnId> {
+        self.parent
+    }
+
+    /// Record the transaction which issued this one as a sub-request.
+    pub fn with_parent(mut self, parent: TxnId) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+
+    /// Render this header for a log without revealing its claimed or target link; see
+    /// [`Claim::redacted`].
+    pub fn redacted(&self) -> String {
+        let mut redacted = format!(
+            "txn {} at {} claim {} target {}",
+            self.id,
+            self.timestamp,
+            self.claim.redacted(),
+            redact_link(&self.target)
+        );
+
+        if let Some(deadline) = self.deadline {
+            redacted.push_str(&format!(" deadline {deadline}"));
+        }
+
+        if let Some(parent) = self.parent {
+            redacted.push_str(&format!(" parent {parent}"));
+        }
+
+        redacted
+    }
+
+    fn encoded_target(&self) -> Option<String> {
+        (self.target != self.claim.link).then(|| self.target.to_string())
+    }
+
+    /// Replace the claim of this header, keeping its ID, timestamp, and target.
+    pub fn with_claim(mut self, claim: Claim) -> Self {
+        self.claim = claim;
+        self
+    }
+
+    /// Narrow the claim of this header to `mask` on `link`, e.g. to delegate a sub-op.
+    ///
+    /// Returns a "forbidden" error if the new claim would grant any authority which the current
+    /// claim does not, i.e. if `link` differs from the claimed link or `mask` has a bit which the
+    /// current mask lacks.
+    pub fn attenuate(self, link: Link, mask: umask::Mode) -> TCResult<Self> {
+        let requested = Claim::new(link, mask);
+
+        match self.claim.intersect(&requested) {
+            Some(claim) if claim == requested => Ok(self.with_claim(claim)),
+            _ => Err(TCError::new(
+                ErrorKind::Forbidden,
+                format!(
+                    "cannot attenuate a claim of {} on {} to {} on {}",
+                    self.claim.mask, self.claim.link, requested.mask, requested.link
+                ),
+            )),
+        }
+    }
+
+    /// Combine this header with `other`, another partial header of the same transaction, e.g. to
+    /// merge an incoming header with what this host already knows about the transaction.
+    ///
+    /// The merged header has the narrower of the two claims (see [`Claim::intersect`]), the
+    /// earlier of their deadlines, and the parent and trace of whichever header has them. The
+    /// target of `self` is kept. Fails if the headers have different IDs or timestamps, traces,
+    /// or parents, or if their claims are on different links.
+    pub fn merge(self, other: TxnHeader) -> TCResult<Self> {
+        let conflict = |field: &str| {
+            TCError::bad_request(format!(
+                "cannot merge transaction headers with different {field}s ({} and {})",
+                self.id, other.id
+            ))
+        };
+
+        if self.id.timestamp != other.id.timestamp || self.id.nonce != other.id.nonce {
+            return Err(conflict("ID"));
+        } else if self.timestamp != other.timestamp {
+            return Err(conflict("timestamp"));
+        }
+
+        let id = match (self.id.trace, other.id.trace) {
+            (trace, UNTRACED) | (UNTRACED, trace) => self.id.with_trace(trace),
+            (trace, other_trace) if trace == other_trace => self.id,
+            _ => return Err(conflict("trace")),
+        };
+
+        let parent = match (self.parent, other.parent) {
+            (Some(parent), Some(other_parent)) if parent != other_parent => {
+                return Err(conflict("parent"))
+            }
+            (parent, other_parent) => parent.or(other_parent),
+        };
+
+        let claim = self.claim.intersect(&other.claim).ok_or_else(|| {
+            TCError::new(
+                ErrorKind::Forbidden,
+                format!(
+                    "cannot merge a claim on {} with a claim on {}",
+                    self.claim.link, other.claim.link
+                ),
+            )
+        })?;
+
+        let deadline = match (self.deadline, other.deadline) {
+            (Some(deadline), Some(other_deadline)) => Some(deadline.min(other_deadline)),
+            (deadline, other_deadline) => deadline.or(other_deadline),
+        };
+
+        Ok(Self {
+            id,
+            timestamp: self.timestamp,
+            claim,
+            target: self.target,
+            deadline,
+            parent,
+        })
+    }
+
+    fn encoded_len(&self, target: &Option<String>) -> usize {
+        3 + usize::from(target.is_some())
+            + usize::from(self.deadline.is_some())
+            + usize::from(self.parent.is_some())
+    }
 }
 
+/// The trace of a [`TxnId`] which has none; see [`TxnId::with_trace`].
+const UNTRACED: [u8; 32] = [0u8; 32];
+
 impl Serialize for TxnHeader {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -173,11 +582,22 @@ impl Serialize for TxnHeader {
     {
         use serde::ser::SerializeMap;
 
-        let mut map = serializer.serialize_map(Some(3))?;
+        let target = self.encoded_target();
+
+        let mut map = serializer.serialize_map(Some(self.encoded_len(&target)))?;
         map.serialize_entry("id", &self.id.to_string())?;
         map.serialize_entry("timestamp", &self.timestamp.as_nanos())?;
         let claim = (self.claim.link.to_string(), u32::from(self.claim.mask));
         map.serialize_entry("claim", &claim)?;
+        if let Some(target) = target {
+            map.serialize_entry("target", &target)?;
+        }
+        if let Some(deadline) = self.deadline {
+            map.serialize_entry("deadline", &deadline.as_nanos())?;
+        }
+        if let Some(parent) = self.parent {
+            map.serialize_entry("parent", &parent.to_string())?;
+        }
         map.end()
     }
 }
@@ -205,6 +625,9 @@ impl<'de> Deserialize<'de> for TxnHeader {
                 let mut id: Option<TxnId> = None;
                 let mut timestamp: Option<NetworkTime> = None;
                 let mut claim: Option<Claim> = None;
+                let mut target: Option<Link> = None;
+                let mut deadline: Option<NetworkTime> = None;
+                let mut parent: Option<TxnId> = None;
 
                 while let Some(key) = map.next_key::<String>()? {
                     match key.as_str() {
@@ -225,6 +648,26 @@ impl<'de> Deserialize<'de> for TxnHeader {
                             let mask: umask::Mode = mask.into();
                             claim = Some(Claim::new(link, mask));
                         }
+                        "target" => {
+                            let link = map.next_value::<String>()?;
+                            let link = Link::from_str(&link)
+                                .map_err(|err| serde::de::Error::custom(err.to_string()))?;
+                            target = Some(link);
+                        }
+                        "deadline" => {
+                            let nanos = map.next_value::<u64>()?;
+                            deadline = Some(NetworkTime::from_nanos(nanos));
+                        }
+                        "parent" => {
+                            let value = map.next_value::<String>()?;
+                            let parsed = TxnId::from_str(&value)
+                                .map_err(|err| serde::de::Error::custom(err.to_string()))?;
+                            parent = Some(parsed);
+                        }
+                        FORMAT_VERSION_KEY => {
+                            let version = map.next_value::<u64>()?;
+                            check_format_version(version).map_err(serde::de::Error::custom)?;
+                        }
                         _ => {
                             let _ = map.next_value::<serde::de::IgnoredAny>()?;
                         }
@@ -235,8 +678,14 @@ impl<'de> Deserialize<'de> for TxnHeader {
                 let timestamp =
                     timestamp.ok_or_else(|| serde::de::Error::custom("missing timestamp"))?;
                 let claim = claim.ok_or_else(|| serde::de::Error::custom("missing claim"))?;
-
-                Ok(TxnHeader::new(id, timestamp, claim))
+                let target = target.unwrap_or_else(|| claim.link.clone());
+
+                Ok(TxnHeader {
+                    target,
+                    deadline,
+                    parent,
+                    ..TxnHeader::new(id, timestamp, claim)
+                })
             }
         }
 
@@ -267,6 +716,9 @@ impl de::FromStream for TxnHeader {
                 let mut id = None;
                 let mut timestamp = None;
                 let mut claim = None;
+                let mut target = None;
+                let mut deadline = None;
+                let mut parent = None;
 
                 while let Some(key) = map.next_key::<String>(()).await? {
                     match key.as_str() {
@@ -286,6 +738,25 @@ impl de::FromStream for TxnHeader {
                             let mask: umask::Mode = mask.into();
                             claim = Some(Claim::new(link, mask));
                         }
+                        "target" => {
+                            let link = map.next_value::<String>(()).await?;
+                            let link = Link::from_str(&link)
+                                .map_err(|err| de::Error::custom(err.to_string()))?;
+                            target = Some(link);
+                        }
+                        "deadline" => {
+                            let nanos = map.next_value::<u64>(()).await?;
+                            deadline = Some(NetworkTime::from_nanos(nanos));
+                        }
+                        "parent" => {
+                            let value = map.next_value::<String>(()).await?;
+                            let parsed = TxnId::from_str(&value).map_err(de::Error::custom)?;
+                            parent = Some(parsed);
+                        }
+                        FORMAT_VERSION_KEY => {
+                            let version = map.next_value::<u64>(()).await?;
+                            check_format_version(version).map_err(de::Error::custom)?;
+                        }
                         _ => {
                             let _ = map.next_value::<de::IgnoredAny>(()).await?;
                         }
@@ -295,8 +766,14 @@ impl de::FromStream for TxnHeader {
                 let id = id.ok_or_else(|| de::Error::custom("missing id"))?;
                 let timestamp = timestamp.ok_or_else(|| de::Error::custom("missing timestamp"))?;
                 let claim = claim.ok_or_else(|| de::Error::custom("missing claim"))?;
-
-                Ok(TxnHeader::new(id, timestamp, claim))
+                let target = target.unwrap_or_else(|| claim.link.clone());
+
+                Ok(TxnHeader {
+                    target,
+                    deadline,
+                    parent,
+                    ..TxnHeader::new(id, timestamp, claim)
+                })
             }
         }
 
@@ -306,11 +783,22 @@ impl de::FromStream for TxnHeader {
 
 impl<'en> en::IntoStream<'en> for TxnHeader {
     fn into_stream<E: en::Encoder<'en>>(self, encoder: E) -> Result<E::Ok, E::Error> {
-        let mut map = encoder.encode_map(Some(3))?;
+        let target = self.encoded_target();
+
+        let mut map = encoder.encode_map(Some(self.encoded_len(&target)))?;
         map.encode_entry("id", self.id.to_string())?;
         map.encode_entry("timestamp", self.timestamp.as_nanos())?;
         let claim = (self.claim.link.to_string(), u32::from(self.claim.mask));
         map.encode_entry("claim", claim)?;
+        if let Some(target) = target {
+            map.encode_entry("target", target)?;
+        }
+        if let Some(deadline) = self.deadline {
+            map.encode_entry("deadline", deadline.as_nanos())?;
+        }
+        if let Some(parent) = self.parent {
+            map.encode_entry("parent", parent.to_string())?;
+        }
         map.end()
     }
 }
@@ -320,53 +808,3 @@ impl<'en> en::ToStream<'en> for TxnHeader {
         self.clone().into_stream(encoder)
     }
 }
-
-/// Authorization data issued by the control-plane / IAM stack.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Claim {
-    pub link: Link,
-    pub mask: umask::Mode,
-}
-
-impl Claim {
-    pub fn new(link: Link, mask: umask::Mode) -> Self {
-        Self { link, mask }
-    }
-
-    /// Return true if this claim grants the required mask.
-    pub fn allows(&self, link: &Link, required: umask::Mode) -> bool {
-        if self.link != *link {
-            return false;
-        }
-
-        let have: u32 = self.mask.into();
-        let need: u32 = required.into();
-        have & need == need
-    }
-}
-
-impl Serialize for Claim {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let tuple = (self.link.to_string(), u32::from(self.mask) as u16);
-        tuple.serialize(serializer)
-    }
-}
-
-impl<'de> Deserialize<'de> for Claim {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        <(String, u16)>::deserialize(deserializer).and_then(|(link, mask)| {
-            let link =
-                Link::from_str(&link).map_err(|err| serde::de::Error::custom(err.to_string()))?;
-            Ok(Claim {
-                link,
-                mask: (mask as u32).into(),
-            })
-        })
-    }
-}
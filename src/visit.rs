@@ -0,0 +1,71 @@
+//! A traversal of a [`Scalar`] which reports entering and leaving each collection, for analysis
+//! which depends on where a node is (e.g. building a scoped symbol table).
+
+use crate::{Id, Map, Scalar};
+
+/// Callbacks for [`Scalar::visit`]. Every callback does nothing by default.
+///
+/// Each `enter_map` or `enter_tuple` is matched by a `leave_map` or `leave_tuple` after every
+/// child of the collection has been visited, so the calls nest like the scalar does.
+pub trait ScalarVisitor {
+    /// Called before the entries of `map` are visited.
+    fn enter_map(&mut self, map: &Map<Scalar>) {
+        let _ = map;
+    }
+
+    /// Called before visiting the value of each entry of a map, with its key.
+    fn visit_key(&mut self, key: &Id) {
+        let _ = key;
+    }
+
+    /// Called after the entries of `map` have been visited.
+    fn leave_map(&mut self, map: &Map<Scalar>) {
+        let _ = map;
+    }
+
+    /// Called before the items of `items` are visited.
+    fn enter_tuple(&mut self, items: &[Scalar]) {
+        let _ = items;
+    }
+
+    /// Called after the items of `items` have been visited.
+    fn leave_tuple(&mut self, items: &[Scalar]) {
+        let _ = items;
+    }
+
+    /// Called for each scalar which is not a map or a tuple: a value, a ref, an op, or bytes.
+    ///
+    /// The traversal does not descend into refs or op definitions.
+    fn visit_value(&mut self, scalar: &Scalar) {
+        let _ = scalar;
+    }
+}
+
+impl Scalar {
+    /// Traverse this scalar depth-first, in order, reporting each collection and leaf to
+    /// `visitor`. Unlike [`Scalar::walk`], this reports where each collection begins and ends.
+    pub fn visit<V: ScalarVisitor>(&self, visitor: &mut V) {
+        match self {
+            Scalar::Map(map) => {
+                visitor.enter_map(map);
+
+                for (key, value) in map.iter() {
+                    visitor.visit_key(key);
+                    value.visit(visitor);
+                }
+
+                visitor.leave_map(map);
+            }
+            Scalar::Tuple(items) => {
+                visitor.enter_tuple(items);
+
+                for item in items {
+                    item.visit(visitor);
+                }
+
+                visitor.leave_tuple(items);
+            }
+            leaf => visitor.visit_value(leaf),
+        }
+    }
+}
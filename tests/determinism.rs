@@ -0,0 +1,184 @@
+//! Every public encodable IR type must encode to the same bytes every time, and re-encode to the
+//! same bytes after a round trip, since peers compare and hash encoded IR.
+
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use destream::{de::FromStream, en::ToStream};
+use futures::{executor::block_on, stream, TryStreamExt};
+use pathlink::Link;
+use serde::{de::DeserializeOwned, Serialize};
+
+use tc_ir::{
+    claim, Claim, FingerprintedLibrary, LibrarySchema, NetworkTime, OpDef, OpRef, Scalar, TCRef,
+    TxnHeader, TxnId,
+};
+
+const SCALAR: &str = r#"{
+    "zeta": [1, -2, 3.5, "four", null, true],
+    "alpha": {"nested": {"b": 2, "a": 1}, "link": {"/lib/acme/foo": []}},
+    "bytes": {"/state/scalar/value/bytes": "AAEC"},
+    "call": {"/lib/acme/foo": {"y": 2, "x": 1}},
+    "mid": {"$self": []}
+}"#;
+
+const OP_DEF: &str = r#"{"/state/scalar/op/post": [
+    ["a", {"/lib/acme/foo": ["key"]}],
+    ["b", {"$a/bar": ["key", {"c": 3, "b": 2, "a": 1}]}],
+    ["c", {"/state/scalar/ref/if": [{"$b": []}, "then", "else"]}]
+]}"#;
+
+const OP_REF: &str = r#"{"/lib/acme/foo": {"zeta": 1, "alpha": [2, 3], "mid": {"$x": []}}}"#;
+
+const TC_REF: &str = r#"{"/state/scalar/ref/while": [
+    {"$cond": []},
+    {"/state/scalar/op/post": [["next", {"/lib/acme/step": [{"$state": []}]}]]},
+    {"b": 0, "a": 1}
+]}"#;
+
+fn link(link: &str) -> Link {
+    Link::from_str(link).expect("link")
+}
+
+fn header() -> TxnHeader {
+    let id = TxnId::from_parts(NetworkTime::from_nanos(1_700_000_000_000_000_000), 7)
+        .with_trace([0xab; 32]);
+
+    let claim = Claim::builder(link("/lib/acme")).read().execute().build();
+
+    TxnHeader::new(
+        id,
+        NetworkTime::from_nanos(1_700_000_000_000_000_000),
+        claim,
+    )
+    .with_target(link("/lib/acme/foo"))
+}
+
+fn schema() -> LibrarySchema {
+    LibrarySchema::new(
+        link("/lib/acme"),
+        "1.2.3",
+        vec![link("/lib/zeta"), link("/lib/alpha"), link("/lib/mid")],
+    )
+}
+
+fn encode<T: for<'en> ToStream<'en>>(value: &T) -> Vec<u8> {
+    let encoded = destream_json::encode(value).expect("encode");
+    let chunks: Vec<_> = block_on(encoded.try_collect()).expect("encoded chunks");
+    chunks.concat()
+}
+
+fn decode<T>(bytes: Vec<u8>) -> T
+where
+    T: FromStream,
+    T::Context: Default,
+{
+    let source = stream::iter([Ok::<_, std::io::Error>(bytes.into())]);
+    block_on(destream_json::try_decode(T::Context::default(), source)).expect("decode")
+}
+
+fn parse<T>(json: &str) -> T
+where
+    T: FromStream,
+    T::Context: Default,
+{
+    decode(json.as_bytes().to_vec())
+}
+
+/// Assert that `value` encodes to the same bytes twice, and that decoding and re-encoding them
+/// yields an equal value with the same bytes.
+fn assert_destream_stable<T>(value: T)
+where
+    T: FromStream + for<'en> ToStream<'en> + PartialEq + Debug,
+    T::Context: Default,
+{
+    let first = encode(&value);
+    assert_eq!(first, encode(&value), "{value:?} encoded differently twice");
+
+    let decoded: T = decode(first.clone());
+    assert_eq!(decoded, value);
+    assert_eq!(
+        encode(&decoded),
+        first,
+        "{value:?} re-encoded differently after a round trip"
+    );
+}
+
+/// Like [`assert_destream_stable`], through `serde_json`.
+fn assert_serde_stable<T>(value: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let first = serde_json::to_vec(&value).expect("serialize");
+    assert_eq!(
+        first,
+        serde_json::to_vec(&value).expect("serialize"),
+        "{value:?} serialized differently twice"
+    );
+
+    let decoded: T = serde_json::from_slice(&first).expect("deserialize");
+    assert_eq!(decoded, value);
+    assert_eq!(
+        serde_json::to_vec(&decoded).expect("serialize"),
+        first,
+        "{value:?} re-serialized differently after a round trip"
+    );
+}
+
+#[test]
+fn scalar_encoding_is_stable() {
+    assert_destream_stable(parse::<Scalar>(SCALAR));
+}
+
+#[test]
+fn op_def_encoding_is_stable() {
+    assert_destream_stable(parse::<OpDef>(OP_DEF));
+}
+
+#[test]
+fn op_ref_encoding_is_stable() {
+    assert_destream_stable(parse::<OpRef>(OP_REF));
+}
+
+#[test]
+fn tc_ref_encoding_is_stable() {
+    assert_destream_stable(parse::<TCRef>(TC_REF));
+}
+
+#[test]
+fn txn_header_encoding_is_stable() {
+    assert_destream_stable(header());
+    assert_serde_stable(header());
+}
+
+#[test]
+fn claim_encoding_is_stable() {
+    assert_serde_stable(Claim::new(link("/lib/acme"), claim::READ));
+    assert_serde_stable(Claim::grant_admin(link("/lib/acme")));
+}
+
+#[test]
+fn library_schema_encoding_is_stable() {
+    assert_destream_stable(schema());
+}
+
+#[test]
+fn fingerprinted_library_encoding_is_stable() {
+    let path = |path: &str| pathlink::PathBuf::from_str(path).expect("path");
+    let ops = [
+        (path("/zeta"), parse::<OpDef>(OP_DEF)),
+        (path("/alpha"), parse::<OpDef>(OP_DEF)),
+    ];
+
+    assert_destream_stable(FingerprintedLibrary::new(schema(), ops));
+}
+
+#[test]
+fn map_keys_encode_in_sorted_order() {
+    // the fixtures above list keys out of order, so stable output must not depend on input order
+    let encoded = String::from_utf8(encode(&parse::<Scalar>(SCALAR))).expect("utf8");
+    let keys = ["alpha", "bytes", "call", "mid", "zeta"]
+        .map(|key| encoded.find(&format!("\"{key}\"")).expect(key));
+
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]), "{encoded}");
+}